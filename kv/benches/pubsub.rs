@@ -5,8 +5,8 @@ use criterion::{async_executor::FuturesExecutor, criterion_group, criterion_main
 use futures::StreamExt;
 
 use kv::{
-    start_client_with_config, start_server_with_config, ClientConfig, CommandRequest, ServerConfig,
-    StorageConfig, YamuxCtrl,
+    start_client_with_config, start_server_with_config, ClientConfig, CommandRequest, FsyncPolicy,
+    ServerConfig, StorageConfig, YamuxCtrl,
 };
 
 use rand::prelude::SliceRandom;
@@ -28,7 +28,12 @@ async fn start_server() -> Result<()> {
     let addr = "127.0.0.1:9999";
     let mut config: ServerConfig = toml::from_str(include_str!("../fixtures/server.conf"))?;
     config.general.addr = addr.into();
-    config.storage = StorageConfig::MemTable;
+    config.storage = StorageConfig::MemTable {
+        wal_path: None,
+        fsync_policy: FsyncPolicy::default(),
+        snapshot_interval: None,
+        eviction: None,
+    };
     tokio::spawn(async move {
         start_server_with_config(&config).await.unwrap();
     });
@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use futures::{future::join_all, StreamExt};
+use kv::{CommandRequest, FsyncPolicy, MemTable, Service, ServiceInner, SledDb, Storage, Value};
+use tokio::runtime::Builder;
+
+/// 每轮并发执行的请求数量
+const CONCURRENCY: usize = 64;
+
+/// 并发执行 hset / hget，返回所有请求都完成后的结果
+async fn hset_hget<Store: Storage + 'static>(service: &Service<Store>, round: usize) {
+    let tasks = (0..CONCURRENCY).map(|i| {
+        let service = service.clone();
+        tokio::spawn(async move {
+            let key = format!("key-{}", (round * CONCURRENCY + i) % 1024);
+            let cmd = CommandRequest::new_hset("bench", &key, (i as i64).into());
+            service.execute(cmd).next().await.unwrap();
+            let cmd = CommandRequest::new_hget("bench", &key);
+            service.execute(cmd).next().await.unwrap();
+        })
+    });
+    for res in join_all(tasks).await {
+        res.unwrap();
+    }
+}
+
+fn bench_store<Store: Storage + 'static>(c: &mut Criterion, name: &str, store: Store) {
+    let rt = Builder::new_multi_thread()
+        .worker_threads(4)
+        .thread_name("storage")
+        .enable_all()
+        .build()
+        .unwrap();
+    let service: Service<Store> = ServiceInner::new(store).into();
+    let mut round = 0;
+    c.bench_with_input(
+        BenchmarkId::new("concurrent_hset_hget", name),
+        &service,
+        |b, service| {
+            b.to_async(&rt).iter(|| {
+                round += 1;
+                hset_hget(service, round)
+            })
+        },
+    );
+}
+
+fn storage(c: &mut Criterion) {
+    bench_store(c, "memtable", MemTable::new());
+
+    let dir = tempfile::tempdir().unwrap();
+    let interval = Some(Duration::from_secs(1));
+    let store = MemTable::with_wal(dir.path().join("kv.wal"), FsyncPolicy::EverySec, interval);
+    bench_store(c, "memtable_wal", store.unwrap());
+    bench_store(c, "sleddb", SledDb::new(dir.path().join("sled")));
+}
+
+/// 列表整体保存在一个 Value 中，lpush / rpop 的耗时随列表长度增长
+fn list(c: &mut Criterion) {
+    let mut group = c.benchmark_group("list_lpush_rpop");
+    for len in [100, 1_000, 10_000] {
+        let store = MemTable::new();
+        let values = (0..len as i64).map(Value::from).collect();
+        store.rpush("bench", "jobs", values).unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(len), &store, |b, store| {
+            b.iter(|| {
+                store.lpush("bench", "jobs", vec![0.into()]).unwrap();
+                store.rpop("bench", "jobs", 1).unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(20).measurement_time(Duration::from_secs(10));
+    targets = storage, list
+}
+criterion_main!(benches);
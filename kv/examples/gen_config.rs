@@ -2,8 +2,8 @@ use std::fs;
 
 use anyhow::Result;
 use kv::{
-    ClientConfig, ClientTlsConfig, GeneralConfig, LogConfig, RotationConfig, ServerConfig,
-    ServerTlsConfig, StorageConfig,
+    BackupConfig, ClientConfig, ClientTlsConfig, GeneralConfig, KeyspaceConfig, LogConfig,
+    PubsubConfig, RotationConfig, ServerConfig, ServerTlsConfig, StorageConfig,
 };
 fn main() -> Result<()> {
     // const CA_CERT: &str = include!("../fixtures/ca.cert");
@@ -29,6 +29,9 @@ fn main() -> Result<()> {
             key: SERVER_KEY.to_string(),
             ca: None,
         },
+        keyspace: KeyspaceConfig::default(),
+        pubsub: PubsubConfig::default(),
+        backup: BackupConfig::default(),
     };
 
     let _ = fs::write(
@@ -0,0 +1,30 @@
+use anyhow::{bail, Result};
+use kv::{CommandRequest, ProstClientStream, TlsClientConnector};
+use tokio::net::TcpStream;
+use tracing::info;
+
+/// 用法：kvadmin backup <path> 或 kvadmin restore <path>，path 是 kvs 配置的备份目录下的相对路径
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt::init();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let cmd = match args.as_slice() {
+        [op, path] if op == "backup" => CommandRequest::new_backup(path),
+        [op, path] if op == "restore" => CommandRequest::new_restore(path),
+        _ => bail!("usage: kvadmin <backup|restore> <path>"),
+    };
+    let ca_cert = include_str!("../fixtures/ca.cert");
+
+    let addr = "127.0.0.1:9527";
+    let connector = TlsClientConnector::new("kvserver.acme.inc", None, Some(ca_cert))?;
+    let stream = TcpStream::connect(addr).await?;
+    let stream = connector.connect(stream).await?;
+    let mut client = ProstClientStream::new(stream);
+
+    let data = client.execute(cmd).await?;
+    if data.status != 200 {
+        bail!("{} failed: {}", args[0], data.message);
+    }
+    info!("{} {:?} keys", args[0], data.values);
+    Ok(())
+}
@@ -1,6 +1,6 @@
 use crate::KvError;
 use serde::{Deserialize, Serialize};
-use std::fs;
+use std::{fs, str::FromStr};
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct ServerConfig {
@@ -8,6 +8,12 @@ pub struct ServerConfig {
     pub storage: StorageConfig,
     pub tls: ServerTlsConfig,
     pub log: LogConfig,
+    #[serde(default)]
+    pub keyspace: KeyspaceConfig,
+    #[serde(default)]
+    pub pubsub: PubsubConfig,
+    #[serde(default)]
+    pub backup: BackupConfig,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -22,10 +28,205 @@ pub struct GeneralConfig {
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
-#[serde(tag = "type", content = "args")]
+#[serde(tag = "type", content = "args", from = "StorageConfigArgs")]
 pub enum StorageConfig {
-    MemTable,
+    /// 内存存储，没有 args 时不做持久化也不限制容量
+    MemTable {
+        /// WAL 文件的路径，为空时不做持久化，快照保存在同目录下的 `<wal_path>.snapshot`
+        wal_path: Option<String>,
+        fsync_policy: FsyncPolicy,
+        /// 生成快照并清空 WAL 的间隔（秒），为空时不生成快照
+        snapshot_interval: Option<u64>,
+        /// 容量限制，为空时不限制
+        eviction: Option<EvictionConfig>,
+    },
     SledDb(String),
+    /// redb 数据库文件的路径，需要开启 redb feature
+    #[cfg(feature = "redb")]
+    RedbDb(String),
+}
+
+/// 反序列化 StorageConfig 时使用：serde 不允许结构体形式的变体缺少 args，
+/// 这里 MemTable 的 args 是可选的，兼容旧的 `type = "MemTable"` 配置
+#[derive(Deserialize)]
+#[serde(tag = "type", content = "args")]
+enum StorageConfigArgs {
+    MemTable(Option<MemTableArgs>),
+    SledDb(String),
+    #[cfg(feature = "redb")]
+    RedbDb(String),
+}
+
+#[derive(Default, Deserialize)]
+struct MemTableArgs {
+    #[serde(default)]
+    wal_path: Option<String>,
+    #[serde(default)]
+    fsync_policy: FsyncPolicy,
+    #[serde(default)]
+    snapshot_interval: Option<u64>,
+    #[serde(default)]
+    eviction: Option<EvictionConfig>,
+}
+
+impl From<StorageConfigArgs> for StorageConfig {
+    fn from(args: StorageConfigArgs) -> Self {
+        match args {
+            StorageConfigArgs::MemTable(args) => {
+                let args = args.unwrap_or_default();
+                Self::MemTable {
+                    wal_path: args.wal_path,
+                    fsync_policy: args.fsync_policy,
+                    snapshot_interval: args.snapshot_interval,
+                    eviction: args.eviction,
+                }
+            }
+            StorageConfigArgs::SledDb(path) => Self::SledDb(path),
+            #[cfg(feature = "redb")]
+            StorageConfigArgs::RedbDb(path) => Self::RedbDb(path),
+        }
+    }
+}
+
+/// MemTable 的容量限制，超出任意一个限制时按 policy 淘汰 key
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct EvictionConfig {
+    #[serde(default)]
+    pub policy: EvictionPolicy,
+    /// 所有 table 中 key 的总数上限
+    pub max_entries: Option<usize>,
+    /// 所有 table 估算占用的内存上限（字节）
+    pub max_memory: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// 淘汰最久没有访问的 key
+    #[default]
+    Lru,
+    /// 淘汰访问次数最少的 key
+    Lfu,
+    /// 随机淘汰
+    Random,
+}
+
+/// WAL 调用 fsync 的时机
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// 每次写入后都 fsync
+    Always,
+    /// 每秒 fsync 一次，宕机时最多丢失一秒的数据
+    #[default]
+    EverySec,
+    /// 由操作系统决定什么时候落盘
+    Never,
+}
+
+/// keyspace 通知：修改 key 时在 `__keyspace:<table>` topic 上发布变更事件
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct KeyspaceConfig {
+    /// 需要发布通知的 table，"*" 表示所有 table，为空时不发布
+    #[serde(default)]
+    pub tables: Vec<String>,
+}
+
+/// Backup 和 Restore 命令可以读写的目录
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BackupConfig {
+    /// 备份文件所在的目录，命令中的 path 是这个目录下的相对路径，为空时不允许备份和恢复
+    #[serde(default)]
+    pub dir: Option<String>,
+}
+
+/// pub/sub 的消息保留策略
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PubsubConfig {
+    /// 每个 topic 保留的消息数量，订阅时可以从保留的消息开始重放，默认为 0，不保留
+    #[serde(default)]
+    pub retention: usize,
+    /// 是否把保留的消息保存到 Storage 中，重启后可以继续重放
+    #[serde(default)]
+    pub persist: bool,
+    /// 消费组成员收到消息后需要在这个时间（毫秒）内确认，否则重新投递
+    #[serde(default = "default_ack_timeout")]
+    pub ack_timeout_ms: u64,
+    /// 消费组中一条消息最多投递的次数，超过后放进死信 topic
+    #[serde(default = "default_max_deliveries")]
+    pub max_deliveries: u32,
+    /// 消费组的成员全部离开后保留消费组的时间（毫秒），期间发布的消息等成员重新加入后投递。
+    /// 超时后删除消费组，还没有确认的消息放进死信 topic
+    #[serde(default = "default_group_idle_timeout")]
+    pub group_idle_timeout_ms: u64,
+    /// 订阅时没有指定溢出策略时使用的策略
+    #[serde(default)]
+    pub overflow: OverflowPolicy,
+}
+
+/// 订阅者的缓冲区满了之后怎么处理新消息
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// 等待订阅者腾出空间，不影响其他订阅者
+    #[default]
+    Block,
+    /// 丢掉缓冲区中最旧的消息
+    DropOldest,
+    /// 丢掉新消息
+    DropNewest,
+    /// 断开订阅
+    Disconnect,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = KvError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Block" => Ok(Self::Block),
+            "DropOldest" => Ok(Self::DropOldest),
+            "DropNewest" => Ok(Self::DropNewest),
+            "Disconnect" => Ok(Self::Disconnect),
+            _ => Err(KvError::InvalidCommand(format!(
+                "unknown overflow policy {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl OverflowPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Block => "Block",
+            Self::DropOldest => "DropOldest",
+            Self::DropNewest => "DropNewest",
+            Self::Disconnect => "Disconnect",
+        }
+    }
+}
+
+impl Default for PubsubConfig {
+    fn default() -> Self {
+        Self {
+            retention: 0,
+            persist: false,
+            ack_timeout_ms: default_ack_timeout(),
+            max_deliveries: default_max_deliveries(),
+            group_idle_timeout_ms: default_group_idle_timeout(),
+            overflow: OverflowPolicy::default(),
+        }
+    }
+}
+
+fn default_ack_timeout() -> u64 {
+    30_000
+}
+
+fn default_max_deliveries() -> u32 {
+    5
+}
+
+fn default_group_idle_timeout() -> u64 {
+    60_000
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -73,13 +274,40 @@ impl ClientConfig {
 
 #[cfg(test)]
 mod test {
-    use crate::config::{ClientConfig, ServerConfig};
+    use crate::config::{
+        BackupConfig, ClientConfig, EvictionConfig, EvictionPolicy, FsyncPolicy, KeyspaceConfig,
+        OverflowPolicy, PubsubConfig, ServerConfig, StorageConfig,
+    };
 
     #[test]
     fn server_config_should_be_loaded() {
         let result: Result<ServerConfig, toml::de::Error> =
             toml::from_str(include_str!("../fixtures/server.conf"));
         assert!(result.is_ok());
+        let config = result.unwrap();
+        assert_eq!(config.keyspace, KeyspaceConfig::default());
+        assert_eq!(config.pubsub, PubsubConfig::default());
+        assert_eq!(config.backup, BackupConfig::default());
+    }
+
+    #[test]
+    fn pubsub_config_should_be_loaded() {
+        let config: PubsubConfig =
+            toml::from_str("persist = true\noverflow = \"DropOldest\"").unwrap();
+        assert_eq!(config.retention, 0);
+        assert!(config.persist);
+        assert_eq!(config.overflow, OverflowPolicy::DropOldest);
+        assert_eq!(
+            "DropOldest".parse::<OverflowPolicy>().unwrap(),
+            config.overflow
+        );
+        assert!("drop".parse::<OverflowPolicy>().is_err());
+    }
+
+    #[test]
+    fn keyspace_config_should_be_loaded() {
+        let config: KeyspaceConfig = toml::from_str(r#"tables = ["t1", "t2"]"#).unwrap();
+        assert_eq!(config.tables, ["t1", "t2"]);
     }
 
     #[test]
@@ -88,4 +316,46 @@ mod test {
             toml::from_str(include_str!("../fixtures/client.conf"));
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn memtable_storage_config_should_be_loaded() {
+        let config: StorageConfig = toml::from_str(
+            r#"
+            type = "MemTable"
+            args = { wal_path = "/tmp/kv.wal", snapshot_interval = 60, eviction = { policy = "Lfu", max_entries = 1000 } }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            StorageConfig::MemTable {
+                wal_path: Some("/tmp/kv.wal".into()),
+                fsync_policy: FsyncPolicy::EverySec,
+                snapshot_interval: Some(60),
+                eviction: Some(EvictionConfig {
+                    policy: EvictionPolicy::Lfu,
+                    max_entries: Some(1000),
+                    max_memory: None,
+                }),
+            }
+        );
+
+        // 序列化之后可以再读回来
+        let content = toml::to_string(&config).unwrap();
+        assert_eq!(toml::from_str::<StorageConfig>(&content).unwrap(), config);
+    }
+
+    #[test]
+    fn legacy_memtable_storage_config_should_be_loaded() {
+        let plain = StorageConfig::MemTable {
+            wal_path: None,
+            fsync_policy: FsyncPolicy::default(),
+            snapshot_interval: None,
+            eviction: None,
+        };
+        let config: StorageConfig = toml::from_str(r#"type = "MemTable""#).unwrap();
+        assert_eq!(config, plain);
+        let config: StorageConfig = toml::from_str("type = \"MemTable\"\nargs = {}").unwrap();
+        assert_eq!(config, plain);
+    }
 }
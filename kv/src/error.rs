@@ -1,3 +1,4 @@
+use sled::transaction::TransactionError;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -8,10 +9,16 @@ pub enum KvError {
     #[error("Frame is larger than max size")]
     FrameError,
 
+    #[error("Precondition failed: {0}")]
+    PreconditionFailed(String),
+
+    #[error("Transaction conflict: {0}")]
+    Conflict(String),
+
     #[error("Command is invalid: `{0}`")]
     InvalidCommand(String),
 
-    #[error("Cannot convert value {:0} to {1}")]
+    #[error("Cannot convert value {0} to {1}")]
     ConvertError(String, &'static str),
 
     #[error("Cannot process command {0} with table: {1}, key: {2}. Error: {3}")]
@@ -26,6 +33,10 @@ pub enum KvError {
     #[error("Failed to access sled db")]
     SledError(#[from] sled::Error),
 
+    #[cfg(feature = "redb")]
+    #[error("Failed to access redb")]
+    RedbError(Box<redb::Error>),
+
     #[error("Internal error: {0}")]
     Internal(String),
 
@@ -35,9 +46,45 @@ pub enum KvError {
     #[error("TLS error")]
     TlsError(#[from] tokio_rustls::rustls::TLSError),
 
+    #[error("Yamux connection error")]
+    YamuxError(#[from] yamux::ConnectionError),
+
     #[error("Certificate parse error: error to load {0} {0}")]
     CertifcateParseError(&'static str, &'static str),
 
     #[error("Parse config error")]
     ConfigError(#[from] toml::de::Error),
 }
+
+impl From<TransactionError<KvError>> for KvError {
+    fn from(e: TransactionError<KvError>) -> Self {
+        match e {
+            TransactionError::Abort(e) => e,
+            TransactionError::Storage(e) => e.into(),
+        }
+    }
+}
+
+/// redb 的各个操作返回不同的错误类型，统一转换成 redb::Error
+#[cfg(feature = "redb")]
+macro_rules! impl_from_redb_error {
+    ($($t:ty),*) => {
+        $(
+            impl From<$t> for KvError {
+                fn from(e: $t) -> Self {
+                    KvError::RedbError(Box::new(e.into()))
+                }
+            }
+        )*
+    };
+}
+
+#[cfg(feature = "redb")]
+impl_from_redb_error!(
+    redb::Error,
+    redb::DatabaseError,
+    redb::TransactionError,
+    redb::TableError,
+    redb::StorageError,
+    redb::CommitError
+);
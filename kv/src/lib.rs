@@ -17,27 +17,56 @@ pub use storage::*;
 use anyhow::Result;
 use tokio::net::{TcpListener, TcpStream};
 use tokio_rustls::client;
-use tokio_util::compat::FuturesAsyncReadCompatExt;
 use tracing::{info, instrument, span};
 
 #[instrument(skip_all)]
 pub async fn start_server_with_config(config: &ServerConfig) -> Result<()> {
     let acceptor =
         TlsServerAcceptor::new(&config.tls.cert, &config.tls.key, config.tls.ca.as_deref())?;
-    let addr = &config.general.addr;
     match &config.storage {
-        StorageConfig::MemTable => start_tls_server(addr, MemTable::new(), acceptor).await?,
-        StorageConfig::SledDb(path) => start_tls_server(addr, SledDb::new(path), acceptor).await?,
+        StorageConfig::MemTable {
+            wal_path,
+            fsync_policy,
+            snapshot_interval,
+            eviction,
+        } => {
+            let store = match wal_path {
+                Some(path) => {
+                    let interval = snapshot_interval.map(Duration::from_secs);
+                    MemTable::with_wal(path, *fsync_policy, interval)?
+                }
+                None => MemTable::new(),
+            };
+            let store = match *eviction {
+                Some(config) => store.with_eviction(config)?,
+                None => store,
+            };
+            start_tls_server(config, store, acceptor).await?
+        }
+        StorageConfig::SledDb(path) => {
+            start_tls_server(config, SledDb::new(path), acceptor).await?
+        }
+        #[cfg(feature = "redb")]
+        StorageConfig::RedbDb(path) => {
+            start_tls_server(config, RedbDb::new(path), acceptor).await?
+        }
     };
     Ok(())
 }
 
-async fn start_tls_server<Store: Storage>(
-    addr: &str,
+async fn start_tls_server<Store: Storage + 'static>(
+    config: &ServerConfig,
     store: Store,
     acceptor: TlsServerAcceptor,
 ) -> Result<()> {
-    let service: Service<Store> = ServiceInner::new(store).into();
+    let addr = &config.general.addr;
+    let mut service = ServiceInner::new(store)
+        .notify_keyspace(config.keyspace.tables.iter().cloned())
+        .pubsub(config.pubsub.clone());
+    if let Some(dir) = &config.backup.dir {
+        service = service.backup_dir(dir);
+    }
+    let service: Service<Store> = service.into();
     let listener = TcpListener::bind(addr).await?;
     info!("listening on http://{}", addr);
     loop {
@@ -49,15 +78,7 @@ async fn start_tls_server<Store: Storage>(
         let svc = service.clone();
         tokio::spawn(async move {
             let stream = tls.accept(stream).await.unwrap();
-            YamuxCtrl::new_server(stream, None, move |stream| {
-                let svc1 = svc.clone();
-                async move {
-                    let stream = ProstServerStream::new(stream.compat(), svc1.clone());
-                    tokio::time::sleep(Duration::from_millis(100)).await;
-                    stream.process().await.unwrap();
-                    Ok(())
-                }
-            });
+            YamuxCtrl::new_service(stream, None, svc);
         });
     }
 }
@@ -5,7 +5,7 @@ use std::io::prelude::*;
 use tokio::io::{AsyncRead, AsyncReadExt};
 use tracing::debug;
 
-use crate::{CommandRequest, CommandResponse, KvError};
+use crate::{BackupRecord, CommandRequest, CommandResponse, KvError};
 
 pub const LEN_LEN: usize = 4;
 const MAX_FRAME: usize = 2 * 1024 * 1024 * 1024;
@@ -63,6 +63,7 @@ where
 
 impl FrameCoder for CommandRequest {}
 impl FrameCoder for CommandResponse {}
+impl FrameCoder for BackupRecord {}
 
 fn decode_header(header: usize) -> (usize, bool) {
     let len = header & !COMPRESSION_BIT;
@@ -83,6 +84,20 @@ where
     Ok(())
 }
 
+/// 从同步的 reader 中读出一个完整的 frame，用于读取文件
+pub fn read_frame_blocking<R: Read>(reader: &mut R, buf: &mut BytesMut) -> Result<(), KvError> {
+    let mut header = [0u8; LEN_LEN];
+    reader.read_exact(&mut header)?;
+    let header = u32::from_be_bytes(header) as usize;
+    let (len, _compressed) = decode_header(header);
+    buf.reserve(LEN_LEN + len);
+    buf.put_u32(header as _);
+    let start = buf.len();
+    buf.resize(start + len, 0);
+    reader.read_exact(&mut buf[start..])?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use crate::utils::DummyStream;
@@ -114,7 +129,7 @@ mod tests {
         let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
         cmd.encode_frame(&mut buf).unwrap();
 
-        assert_eq!(is_compressed(&buf), false);
+        assert!(!is_compressed(&buf));
         let cmd1 = CommandRequest::decode_frame(&mut buf).unwrap();
         assert_eq!(cmd, cmd1);
     }
@@ -126,7 +141,7 @@ mod tests {
         let res: CommandResponse = values.into();
         res.encode_frame(&mut buf).unwrap();
 
-        assert_eq!(is_compressed(&buf), false);
+        assert!(!is_compressed(&buf));
         let res1 = CommandResponse::decode_frame(&mut buf).unwrap();
         assert_eq!(res, res1);
     }
@@ -138,7 +153,7 @@ mod tests {
         let res: CommandResponse = value.into();
         res.encode_frame(&mut buf).unwrap();
 
-        assert_eq!(is_compressed(&buf), true);
+        assert!(is_compressed(&buf));
 
         let res1 = CommandResponse::decode_frame(&mut buf).unwrap();
         assert_eq!(res, res1);
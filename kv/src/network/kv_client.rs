@@ -0,0 +1,196 @@
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::{CommandRequest, CommandResponse, KvError, Kvpair, StreamResult, Value, YamuxCtrl};
+
+/// 在一个 yamux 连接上执行命令的客户端，每个命令使用单独的 stream，clone 后可以并发调用
+pub struct KvClient<S> {
+    ctrl: YamuxCtrl<S>,
+}
+
+impl<S> Clone for KvClient<S> {
+    fn clone(&self) -> Self {
+        Self {
+            ctrl: self.ctrl.clone(),
+        }
+    }
+}
+
+impl<S> KvClient<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    pub fn new(ctrl: YamuxCtrl<S>) -> Self {
+        Self { ctrl }
+    }
+
+    /// 执行命令，非 200 的响应转换成对应的 KvError
+    pub async fn execute(&self, cmd: CommandRequest) -> Result<CommandResponse, KvError> {
+        let mut stream = self.ctrl.clone().open_stream().await?;
+        stream.execute_unary(&cmd).await?.into_result()
+    }
+
+    /// 获取 key 的值，key 不存在时返回 None
+    pub async fn get(
+        &self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+    ) -> Result<Option<Value>, KvError> {
+        match self.execute(CommandRequest::new_hget(table, key)).await {
+            Ok(res) => Ok(first_value(res)),
+            Err(KvError::NotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// 设置 key 的值，返回之前的值
+    pub async fn set(
+        &self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+    ) -> Result<Option<Value>, KvError> {
+        let cmd = CommandRequest::new_hset(table, key, value.into());
+        self.execute(cmd).await.map(first_value)
+    }
+
+    /// 删除 key，返回被删除的值
+    pub async fn del(
+        &self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+    ) -> Result<Option<Value>, KvError> {
+        let cmd = CommandRequest::new_hdel(table, key);
+        self.execute(cmd).await.map(first_value)
+    }
+
+    pub async fn exists(
+        &self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+    ) -> Result<bool, KvError> {
+        let res = self
+            .execute(CommandRequest::new_hexists(table, key))
+            .await?;
+        match res.values.first() {
+            Some(v) => v.try_into(),
+            None => Err(KvError::ConvertError(res.format(), "Bool")),
+        }
+    }
+
+    pub async fn get_all(&self, table: impl Into<String>) -> Result<Vec<Kvpair>, KvError> {
+        let res = self.execute(CommandRequest::new_hgetall(table)).await?;
+        Ok(res.pairs)
+    }
+
+    pub async fn publish(&self, topic: impl Into<String>, data: Vec<Value>) -> Result<(), KvError> {
+        self.execute(CommandRequest::new_publish(topic, data))
+            .await
+            .map(|_| ())
+    }
+
+    /// 订阅 topic，返回的 StreamResult 中带有 subscription id
+    pub async fn subscribe(&self, topic: impl Into<String>) -> Result<StreamResult, KvError> {
+        let stream = self.ctrl.clone().open_stream().await?;
+        stream
+            .execute_streaming(&CommandRequest::new_subscribe(topic))
+            .await
+    }
+
+    pub async fn unsubscribe(&self, topic: impl Into<String>, id: u32) -> Result<(), KvError> {
+        self.execute(CommandRequest::new_unsubscribe(topic, id))
+            .await
+            .map(|_| ())
+    }
+}
+
+/// 响应中的第一个值，空的 Value 表示没有值
+fn first_value(res: CommandResponse) -> Option<Value> {
+    res.values.into_iter().next().filter(|v| v.value.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use futures::StreamExt;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_rustls::client;
+
+    use super::*;
+    use crate::{
+        assert_res_ok,
+        command_request::RequestData,
+        network::tls::tls_utils::{tls_acceptor, tls_connector},
+        MemTable, Service, ServiceInner,
+    };
+
+    async fn start_client() -> Result<KvClient<client::TlsStream<TcpStream>>> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let acceptor = tls_acceptor(false)?;
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let stream = acceptor.accept(stream).await.unwrap();
+            YamuxCtrl::new_service(stream, None, service);
+        });
+
+        let stream = TcpStream::connect(addr).await?;
+        let stream = tls_connector(false)?.connect(stream).await?;
+        Ok(KvClient::new(YamuxCtrl::new_client(stream, None)))
+    }
+
+    #[tokio::test]
+    async fn kv_client_should_work() -> Result<()> {
+        let client = start_client().await?;
+        assert_eq!(client.get("t1", "k1").await?, None);
+        assert_eq!(client.set("t1", "k1", "v1").await?, None);
+        assert_eq!(client.set("t1", "k1", "v2").await?, Some("v1".into()));
+        assert_eq!(client.get("t1", "k1").await?, Some("v2".into()));
+        assert!(client.exists("t1", "k1").await?);
+
+        // clone 共享同一个连接，可以并发执行命令
+        let tasks: Vec<_> = (0..10)
+            .map(|i| {
+                let client = client.clone();
+                tokio::spawn(async move { client.set("t2", format!("k{}", i), i).await })
+            })
+            .collect();
+        for task in tasks {
+            assert_eq!(task.await??, None);
+        }
+        assert_eq!(client.get_all("t2").await?.len(), 10);
+
+        assert_eq!(client.del("t1", "k1").await?, Some("v2".into()));
+        assert_eq!(client.del("t1", "k1").await?, None);
+        assert!(!client.exists("t1", "k1").await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn kv_client_should_map_errors_and_stream_messages() -> Result<()> {
+        let client = start_client().await?;
+        let res = client.publish("__keyspace:t1", vec!["v".into()]).await;
+        assert!(
+            matches!(res, Err(KvError::InvalidCommand(msg)) if msg == "topic __keyspace:t1 is reserved")
+        );
+        let res = client.unsubscribe("t1", 9999).await;
+        assert!(matches!(res, Err(KvError::NotFound(_))));
+        let mut cmd = CommandRequest::new_subscribe("t1");
+        if let Some(RequestData::Subscribe(v)) = &mut cmd.request_data {
+            v.overflow = "Unknown".into();
+        }
+        let stream = client.ctrl.clone().open_stream().await?;
+        let res = stream.execute_streaming(&cmd).await.map(|v| v.id);
+        assert!(matches!(res, Err(KvError::InvalidCommand(_))));
+
+        let mut sub = client.subscribe("t1").await?;
+        client.publish("t1", vec!["hello".into()]).await?;
+        let data = sub.next().await.unwrap()?;
+        assert_res_ok(&data, &["hello".into()], &[]);
+
+        client.unsubscribe("t1", sub.id).await?;
+        // 服务端结束订阅后不会再收到消息
+        assert!(!matches!(sub.next().await, Some(Ok(_))));
+        Ok(())
+    }
+}
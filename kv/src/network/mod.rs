@@ -1,25 +1,33 @@
 mod frame;
+mod kv_client;
 mod multiplex;
+mod resilient;
 mod stream;
 mod stream_result;
 mod tls;
 
+pub(crate) use frame::read_frame_blocking;
 pub use frame::FrameCoder;
-use futures::{SinkExt, StreamExt};
+use futures::{SinkExt, Stream, StreamExt};
+pub use kv_client::KvClient;
 pub use multiplex::YamuxCtrl;
+pub use resilient::{ResilientSubscription, SubscriptionEvent};
+use std::io;
 use stream::ProstStream;
 
 pub use stream_result::StreamResult;
 pub use tls::{TlsClientConnector, TlsServerAcceptor};
 use tokio::io::{AsyncRead, AsyncWrite};
-use tracing::info;
+use tracing::{info, warn};
 
-use crate::{CommandRequest, CommandResponse, KvError, Service, Storage};
+use crate::{CommandRequest, CommandResponse, KvError, Service, Storage, Subscriptions};
 
 pub struct ProstServerStream<S, Store> {
     // inner: S,
     inner: ProstStream<S, CommandRequest, CommandResponse>,
     service: Service<Store>,
+    /// 记录这个 stream 上创建的 subscription，可以和同一个连接上的其他 stream 共享
+    subscriptions: Subscriptions,
 }
 
 pub struct ProstClientStream<S> {
@@ -30,26 +38,83 @@ pub struct ProstClientStream<S> {
 impl<S, Store> ProstServerStream<S, Store>
 where
     S: AsyncRead + AsyncWrite + Unpin + Send,
-    Store: Storage,
+    Store: Storage + 'static,
 {
     pub fn new(stream: S, service: Service<Store>) -> Self {
         Self {
             // inner: stream,
             inner: ProstStream::new(stream),
+            subscriptions: service.subscriptions(),
             service,
         }
     }
 
+    /// 使用连接共享的 Subscriptions，这样连接断开时可以删除所有 stream 上的订阅
+    pub fn with_subscriptions(mut self, subscriptions: Subscriptions) -> Self {
+        self.subscriptions = subscriptions;
+        self
+    }
+
     pub async fn process(mut self) -> Result<(), KvError> {
         let stream = &mut self.inner;
+        // 这个 stream 上创建的 subscription，stream 结束时删除
+        let mut created = vec![];
         // while let Ok(cmd) = self.recv().await {
-        while let Some(Ok(cmd)) = stream.next().await {
+        'commands: while let Some(Ok(cmd)) = stream.next().await {
             info!("Got a new command: {:?}", cmd);
+            // 订阅命令的第一条消息是 subscription id
+            let watch = Subscriptions::is_subscribe(&cmd);
+            let mut subscribe = watch.then(|| cmd.clone());
             let mut res = self.service.execute(cmd);
-            while let Some(data) = res.next().await {
-                stream.send(&data).await.unwrap();
+            // 订阅期间 client 不会再发送命令，发送订阅命令后通常会关闭写端，所以读到结束不代表
+            // client 放弃了订阅，之后只发送消息。读出错或者发送失败时才认为 client 已经断开
+            let mut reading = watch;
+            loop {
+                let data = if reading {
+                    tokio::select! {
+                        data = res.next() => data,
+                        cmd = stream.next() => {
+                            match cmd {
+                                Some(Ok(cmd)) => warn!("Ignore command during subscription: {:?}", cmd),
+                                // 读到结束说明 client 关闭了写端
+                                Some(Err(KvError::IoError(e)))
+                                    if e.kind() == io::ErrorKind::UnexpectedEof =>
+                                {
+                                    reading = false
+                                }
+                                Some(Err(e)) => {
+                                    warn!("Failed to read from subscription stream: {:?}", e);
+                                    break 'commands;
+                                }
+                                None => reading = false,
+                            }
+                            continue;
+                        }
+                    }
+                } else {
+                    res.next().await
+                };
+                let Some(data) = data else {
+                    break;
+                };
+                if let Some(cmd) = subscribe.take() {
+                    created.extend(self.subscriptions.track(&cmd, &data));
+                }
+                if let Err(e) = stream.send(&data).await {
+                    warn!("Failed to send response: {:?}", e);
+                    break 'commands;
+                }
+            }
+            // 订阅结束后 stream 也随之结束，client 不会在这个 stream 上发送新的命令
+            if watch {
+                break;
             }
         }
+        for id in created {
+            self.subscriptions.remove(id);
+        }
+        // 主动关闭，让对端马上知道 stream 已经结束
+        let _ = stream.close().await;
         Ok(())
     }
 
@@ -81,33 +146,61 @@ where
     }
 
     pub async fn execute(&mut self, cmd: CommandRequest) -> Result<CommandResponse, KvError> {
-        // self.send(cmd).await?;
-        // Ok(self.recv().await?)
-        let stream = &mut self.inner;
-        stream.send(&cmd).await?;
-        match stream.next().await {
-            Some(v) => v,
-            None => Err(KvError::Internal("Didn't get any response".into())),
-        }
+        self.execute_unary(&cmd).await
     }
 
+    /// 执行命令并返回完整的结果，分块返回的数据会被合并到一个 CommandResponse 中
     pub async fn execute_unary(
         &mut self,
         cmd: &CommandRequest,
     ) -> Result<CommandResponse, KvError> {
+        let mut chunks = self.execute_chunks(cmd).await?;
+        let mut res = match chunks.next().await {
+            Some(v) => v?,
+            None => return Err(KvError::Internal("Didn't get any response".into())),
+        };
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chunk?;
+            if chunk.status != res.status {
+                return Ok(chunk);
+            }
+            res.pairs.extend(chunk.pairs);
+            res.more = chunk.more;
+        }
+        Ok(res)
+    }
+
+    /// 执行命令并逐块返回结果，读到 more 为 false 的结束标记后结束
+    pub async fn execute_chunks(
+        &mut self,
+        cmd: &CommandRequest,
+    ) -> Result<impl Stream<Item = Result<CommandResponse, KvError>> + Unpin + '_, KvError> {
         let stream = &mut self.inner;
         stream.send(cmd).await?;
-        match stream.next().await {
-            Some(v) => v,
-            None => Err(KvError::Internal("Didn't get any response".into())),
-        }
+        let chunks = futures::stream::unfold(Some(stream), |stream| async move {
+            let stream = stream?;
+            match stream.next().await {
+                Some(Ok(res)) if res.more => Some((Ok(res), Some(stream))),
+                Some(v) => Some((v, None)),
+                None => Some((
+                    Err(KvError::Internal(
+                        "Stream closed before the end marker".into(),
+                    )),
+                    None,
+                )),
+            }
+        });
+        Ok(Box::pin(chunks))
     }
 
+    /// 执行订阅之类的流式命令。drop 返回的 StreamResult 之后，服务端向这个 stream
+    /// 发送消息失败时删除订阅，连接断开时删除连接上所有的订阅
     pub async fn execute_streaming(self, cmd: &CommandRequest) -> Result<StreamResult, KvError> {
         let mut stream = self.inner;
 
+        // 不关闭写端：yamux 会重置没有关闭就被 drop 的 stream，服务端之后的发送会失败。
+        // 关闭了写端的 stream 被 drop 时不会通知服务端，订阅要等到连接断开才能删除
         stream.send(cmd).await?;
-        stream.close().await?;
 
         StreamResult::new(stream).await
     }
@@ -186,6 +279,38 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn client_should_collect_chunked_response() -> anyhow::Result<()> {
+        let addr = start_server().await?;
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream);
+        for i in 0..2500 {
+            let cmd = CommandRequest::new_hset("t3", format!("k{:04}", i), i.into());
+            client.execute(cmd).await?;
+        }
+
+        let cmd = CommandRequest::new_hgetall("t3");
+        let mut chunks = client.execute_chunks(&cmd).await?;
+        let mut count = 0;
+        while let Some(res) = chunks.next().await {
+            count += 1;
+            assert_eq!(res?.status, 200);
+        }
+        assert!(count > 1);
+        drop(chunks);
+
+        let res = client.execute(cmd).await?;
+        assert_eq!(res.pairs.len(), 2500);
+        assert!(!res.more);
+
+        // 分块结果读完后连接还能继续使用
+        let res = client
+            .execute(CommandRequest::new_hget("t3", "k0001"))
+            .await?;
+        assert_res_ok(&res, &[1.into()], &[]);
+        Ok(())
+    }
+
     async fn start_server() -> Result<SocketAddr> {
         let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
         let addr = listener.local_addr().unwrap();
@@ -2,16 +2,26 @@ use futures::{future, Future, TryStreamExt};
 use std::marker::PhantomData;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_util::compat::{Compat, FuturesAsyncReadCompatExt, TokioAsyncReadCompatExt};
-use tracing::instrument;
+use tracing::{debug, instrument, warn};
 use yamux::{Config, Connection, ConnectionError, Control, Mode, WindowUpdateMode};
 
-use crate::ProstClientStream;
+use crate::{ProstClientStream, ProstServerStream, Service, Storage};
 
 pub struct YamuxCtrl<S> {
     ctrl: Control,
     _conn: PhantomData<S>,
 }
 
+/// 所有 clone 共享同一个连接，可以在不同的任务中同时打开 stream
+impl<S> Clone for YamuxCtrl<S> {
+    fn clone(&self) -> Self {
+        Self {
+            ctrl: self.ctrl.clone(),
+            _conn: PhantomData,
+        }
+    }
+}
+
 impl<S> YamuxCtrl<S>
 where
     S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
@@ -38,6 +48,41 @@ where
         Self::new(stream, config, false, f)
     }
 
+    /// 用 service 处理连接上的每个 stream，连接断开时删除这个连接上创建的所有 subscription
+    pub fn new_service<Store: Storage + 'static>(
+        stream: S,
+        config: Option<Config>,
+        service: Service<Store>,
+    ) -> Self {
+        let conn = Self::connection(stream, config, false);
+        let ctrl = conn.control();
+        let subscriptions = service.subscriptions();
+        let streams = subscriptions.clone();
+        // 每个 stream 单独 spawn，空闲的订阅不会让连接的任务一直等下去
+        let conn = yamux::into_stream(conn).try_for_each(move |stream| {
+            let stream = ProstServerStream::new(stream.compat(), service.clone())
+                .with_subscriptions(streams.clone());
+            tokio::spawn(async move {
+                if let Err(e) = stream.process().await {
+                    warn!("Failed to process stream: {:?}", e);
+                }
+            });
+            future::ready(Ok(()))
+        });
+        tokio::spawn(async move {
+            if let Err(e) = conn.await {
+                debug!("Connection closed: {:?}", e);
+            }
+            // 删除 subscription 后，还在等待消息的 stream 也会结束
+            subscriptions.clear();
+        });
+
+        Self {
+            ctrl,
+            _conn: PhantomData,
+        }
+    }
+
     #[instrument(name = "yamux_ctrl_new", skip_all)]
     pub fn new<F, Fut>(stream: S, config: Option<Config>, is_client: bool, f: F) -> Self
     where
@@ -45,6 +90,17 @@ where
         F: Send + 'static,
         Fut: Future<Output = Result<(), ConnectionError>> + Send + 'static,
     {
+        let conn = Self::connection(stream, config, is_client);
+        let ctrl = conn.control();
+        tokio::spawn(yamux::into_stream(conn).try_for_each_concurrent(None, f));
+
+        Self {
+            ctrl,
+            _conn: PhantomData,
+        }
+    }
+
+    fn connection(stream: S, config: Option<Config>, is_client: bool) -> Connection<Compat<S>> {
         let mode = if is_client {
             Mode::Client
         } else {
@@ -52,14 +108,12 @@ where
         };
         let mut config = config.unwrap_or_default();
         config.set_window_update_mode(WindowUpdateMode::OnRead);
-        let conn = Connection::new(stream.compat(), config, mode);
-        let ctrl = conn.control();
-        tokio::spawn(yamux::into_stream(conn).try_for_each_concurrent(None, f));
+        Connection::new(stream.compat(), config, mode)
+    }
 
-        Self {
-            ctrl,
-            _conn: PhantomData,
-        }
+    /// 关闭整个连接，所有 stream 都会结束
+    pub async fn close(&mut self) -> Result<(), ConnectionError> {
+        self.ctrl.close().await
     }
 
     #[instrument(skip_all)]
@@ -81,9 +135,10 @@ mod tests {
         network::tls::tls_utils::{tls_acceptor, tls_connector},
         utils::DummyStream,
         CommandRequest, KvError, MemTable, ProstServerStream, Service, ServiceInner, Storage,
-        TlsServerAcceptor,
+        StreamResult, TlsServerAcceptor,
     };
     use anyhow::Result;
+    use futures::{SinkExt, StreamExt};
     use tokio::net::{TcpListener, TcpStream};
     use tokio_rustls::server;
     use tracing::warn;
@@ -174,4 +229,97 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn yamux_service_should_remove_subscriptions_when_connection_closes() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let acceptor = tls_acceptor(false)?;
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        let svc = service.clone();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let stream = acceptor.accept(stream).await.unwrap();
+            YamuxCtrl::new_service(stream, None, svc);
+        });
+
+        let connector = tls_connector(false)?;
+        let stream = TcpStream::connect(addr).await?;
+        let stream = connector.connect(stream).await?;
+        let mut ctrl = YamuxCtrl::new_client(stream, None);
+        let stream = ctrl.open_stream().await?;
+        let res = stream
+            .execute_streaming(&CommandRequest::new_subscribe("t1"))
+            .await?;
+        assert!(res.id > 0);
+
+        let numsub = || async {
+            let cmd = CommandRequest::new_pubsub_numsub(vec!["t1".into()]);
+            let res = service.execute(cmd).next().await.unwrap();
+            let count: i64 = res.pairs[0].value.as_ref().unwrap().try_into().unwrap();
+            count
+        };
+        assert_eq!(numsub().await, 1);
+
+        // 订阅的 topic 上没有新消息，只有连接断开才能清理订阅
+        ctrl.close().await?;
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert_eq!(numsub().await, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn yamux_service_should_remove_subscription_when_stream_dropped() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let acceptor = tls_acceptor(false)?;
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        let svc = service.clone();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let stream = acceptor.accept(stream).await.unwrap();
+            YamuxCtrl::new_service(stream, None, svc);
+        });
+
+        let connector = tls_connector(false)?;
+        let stream = TcpStream::connect(addr).await?;
+        let stream = connector.connect(stream).await?;
+        let mut ctrl = YamuxCtrl::new_client(stream, None);
+        let cmd = CommandRequest::new_subscribe("t1");
+        // 和以前的 client 一样，发送订阅命令后关闭写端
+        let mut stream = ctrl.open_stream().await?.inner;
+        stream.send(&cmd).await?;
+        stream.close().await?;
+        let mut res1 = StreamResult::new(stream).await?;
+        let res2 = ctrl.open_stream().await?.execute_streaming(&cmd).await?;
+        assert_ne!(res1.id, res2.id);
+
+        let numsub = || async {
+            let cmd = CommandRequest::new_pubsub_numsub(vec!["t1".into()]);
+            let res = service.execute(cmd).next().await.unwrap();
+            let count: i64 = res.pairs[0].value.as_ref().unwrap().try_into().unwrap();
+            count
+        };
+        assert_eq!(numsub().await, 2);
+
+        // 关闭了写端的 client 仍然可以收到消息
+        let publish = || async {
+            let cmd = CommandRequest::new_publish("t1", vec!["hello".into()]);
+            service.execute(cmd).next().await.unwrap();
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        };
+        publish().await;
+        assert_eq!(numsub().await, 2);
+
+        // 连接还在，client 在连接上有新的活动时重置 drop 的 stream，服务端之后发送失败时删除订阅
+        drop(res2);
+        publish().await;
+        publish().await;
+        assert_eq!(numsub().await, 1);
+        for _ in 0..3 {
+            let msg = res1.next().await.unwrap()?;
+            assert_res_ok(&msg, &["hello".into()], &[]);
+        }
+        Ok(())
+    }
 }
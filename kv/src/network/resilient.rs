@@ -0,0 +1,278 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::{
+    stream::{self, BoxStream, SelectAll},
+    StreamExt,
+};
+use tokio::{net::TcpStream, sync::mpsc, time};
+use tokio_rustls::client;
+use tracing::{info, warn};
+
+use crate::{CommandRequest, CommandResponse, KvError, TlsClientConnector, YamuxCtrl};
+
+/// 重连的最短和最长间隔，每次失败后间隔翻倍
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+const EVENT_CAPACITY: usize = 128;
+
+/// ResilientSubscription 收到的事件
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubscriptionEvent {
+    /// topic 上发布的消息
+    Message {
+        topic: String,
+        data: Box<CommandResponse>,
+    },
+    /// 断线后重新订阅了 topic，id 是新的 subscription id，断开期间发布的消息可能已经丢失
+    Gap { topic: String, id: u32 },
+}
+
+/// 断线后自动重连并重新订阅的 subscription，服务重启后可以继续收到 topic 上的消息
+pub struct ResilientSubscription {
+    events: mpsc::Receiver<SubscriptionEvent>,
+    /// topic -> 当前的 subscription id
+    ids: Arc<Mutex<HashMap<String, u32>>>,
+}
+
+/// 一个 topic 的消息，订阅结束时最后一项是 None
+type TopicStream = BoxStream<'static, (String, Option<Result<CommandResponse, KvError>>)>;
+
+/// 一次连接，以及在这个连接上订阅的所有 topic
+struct Connection {
+    ctrl: YamuxCtrl<client::TlsStream<TcpStream>>,
+    streams: SelectAll<TopicStream>,
+}
+
+impl ResilientSubscription {
+    /// 连接 addr 并订阅 topics。第一次连接失败时返回错误，之后断线会在后台自动重连
+    pub async fn new(
+        addr: impl Into<String>,
+        connector: TlsClientConnector,
+        topics: Vec<String>,
+    ) -> Result<Self, KvError> {
+        let addr = addr.into();
+        let ids = Arc::new(Mutex::new(HashMap::new()));
+        let conn = subscribe(&addr, &connector, &topics, &ids).await?;
+        let (tx, events) = mpsc::channel(EVENT_CAPACITY);
+        let resubscriber = Resubscriber {
+            addr,
+            connector,
+            topics,
+            ids: ids.clone(),
+            tx,
+        };
+        tokio::spawn(resubscriber.run(conn));
+        Ok(Self { events, ids })
+    }
+
+    /// topic 当前的 subscription id，重新订阅后会变化
+    pub fn id(&self, topic: &str) -> Option<u32> {
+        self.ids.lock().unwrap().get(topic).copied()
+    }
+
+    /// 等待下一个事件
+    pub async fn next(&mut self) -> Option<SubscriptionEvent> {
+        self.events.recv().await
+    }
+}
+
+/// 在后台转发消息，断线后重新连接并订阅
+struct Resubscriber {
+    addr: String,
+    connector: TlsClientConnector,
+    topics: Vec<String>,
+    ids: Arc<Mutex<HashMap<String, u32>>>,
+    tx: mpsc::Sender<SubscriptionEvent>,
+}
+
+impl Resubscriber {
+    /// ResilientSubscription 被 drop 之后关闭连接并退出
+    async fn run(self, mut conn: Connection) {
+        loop {
+            let closed = self.forward(&mut conn).await;
+            let _ = conn.ctrl.close().await;
+            if closed {
+                return;
+            }
+            conn = match self.reconnect().await {
+                Some(conn) => conn,
+                None => return,
+            };
+            for topic in &self.topics {
+                let id = self
+                    .ids
+                    .lock()
+                    .unwrap()
+                    .get(topic)
+                    .copied()
+                    .unwrap_or_default();
+                let gap = SubscriptionEvent::Gap {
+                    topic: topic.clone(),
+                    id,
+                };
+                if self.tx.send(gap).await.is_err() {
+                    let _ = conn.ctrl.close().await;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// 转发消息，直到有一个 topic 的订阅结束。ResilientSubscription 被 drop 时返回 true
+    async fn forward(&self, conn: &mut Connection) -> bool {
+        loop {
+            let item = tokio::select! {
+                _ = self.tx.closed() => return true,
+                item = conn.streams.next() => item,
+            };
+            match item {
+                Some((topic, Some(Ok(data)))) => {
+                    let data = Box::new(data);
+                    let event = SubscriptionEvent::Message { topic, data };
+                    if self.tx.send(event).await.is_err() {
+                        return true;
+                    }
+                }
+                Some((topic, Some(Err(e)))) => {
+                    warn!("Subscription of {} failed: {:?}", topic, e);
+                    return false;
+                }
+                Some((topic, None)) => {
+                    warn!("Subscription of {} ended", topic);
+                    return false;
+                }
+                None => return false,
+            }
+        }
+    }
+
+    /// 不断重试直到重新订阅成功，ResilientSubscription 被 drop 时返回 None
+    async fn reconnect(&self) -> Option<Connection> {
+        let mut backoff = MIN_BACKOFF;
+        loop {
+            tokio::select! {
+                _ = self.tx.closed() => return None,
+                _ = time::sleep(backoff) => {}
+            }
+            match subscribe(&self.addr, &self.connector, &self.topics, &self.ids).await {
+                Ok(conn) => {
+                    info!("Resubscribed to {:?}", self.topics);
+                    return Some(conn);
+                }
+                Err(e) => {
+                    warn!("Failed to resubscribe to {}: {:?}", self.addr, e);
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+}
+
+/// 建立连接，每个 topic 在单独的 yamux stream 上订阅，成功后更新 ids
+async fn subscribe(
+    addr: &str,
+    connector: &TlsClientConnector,
+    topics: &[String],
+    ids: &Mutex<HashMap<String, u32>>,
+) -> Result<Connection, KvError> {
+    let stream = TcpStream::connect(addr).await?;
+    let stream = connector.connect(stream).await?;
+    let mut ctrl = YamuxCtrl::new_client(stream, None);
+    let mut streams = SelectAll::new();
+    let mut new_ids = HashMap::new();
+    for topic in topics {
+        let cmd = CommandRequest::new_subscribe(topic);
+        let res = ctrl.open_stream().await?.execute_streaming(&cmd).await?;
+        new_ids.insert(topic.clone(), res.id);
+        let topic = topic.clone();
+        let messages = stream::unfold(
+            res,
+            |mut res| async move { res.next().await.map(|v| (v, res)) },
+        );
+        let stream: TopicStream = messages
+            .map(Some)
+            .chain(stream::once(async { None }))
+            .map(move |v| (topic.clone(), v))
+            .boxed();
+        streams.push(stream);
+    }
+    *ids.lock().unwrap() = new_ids;
+    Ok(Connection { ctrl, streams })
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::{
+        assert_res_ok,
+        network::tls::tls_utils::{tls_acceptor, tls_connector},
+        MemTable, Service, ServiceInner,
+    };
+
+    #[tokio::test]
+    async fn resilient_subscription_should_resubscribe_after_subscription_ends(
+    ) -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let acceptor = tls_acceptor(false)?;
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        let svc = service.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let stream = acceptor.accept(stream).await.unwrap();
+                YamuxCtrl::new_service(stream, None, svc.clone());
+            }
+        });
+
+        let topics = vec!["t1".to_string(), "t2".to_string()];
+        let mut sub =
+            ResilientSubscription::new(addr.to_string(), tls_connector(false)?, topics).await?;
+        let old = sub.id("t1").unwrap();
+        assert!(sub.id("t2").is_some());
+
+        let publish = |topic: &str, v: &str| {
+            let cmd = CommandRequest::new_publish(topic, vec![v.into()]);
+            drop(service.execute(cmd));
+        };
+        publish("t1", "hello");
+        match sub.next().await.unwrap() {
+            SubscriptionEvent::Message { topic, data } => {
+                assert_eq!(topic, "t1");
+                assert_res_ok(&data, &["hello".into()], &[]);
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+
+        // 服务端结束订阅后，客户端重新订阅所有 topic
+        drop(service.execute(CommandRequest::new_unsubscribe("t1", old)));
+        for topic in ["t1", "t2"] {
+            let event = sub.next().await.unwrap();
+            let id = sub.id(topic).unwrap();
+            assert_eq!(
+                event,
+                SubscriptionEvent::Gap {
+                    topic: topic.into(),
+                    id
+                }
+            );
+        }
+        assert_ne!(sub.id("t1"), Some(old));
+
+        publish("t2", "world");
+        match sub.next().await.unwrap() {
+            SubscriptionEvent::Message { topic, data } => {
+                assert_eq!(topic, "t2");
+                assert_res_ok(&data, &["world".into()], &[]);
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+        Ok(())
+    }
+}
@@ -35,7 +35,7 @@ where
         mut self: std::pin::Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Option<Self::Item>> {
-        assert!(self.rbuf.len() == 0);
+        assert!(self.rbuf.is_empty());
         let mut rest = self.rbuf.split_off(0);
         let fut = read_frame(&mut self.stream, &mut rest);
         ready!(Box::pin(fut).poll_unpin(cx))?;
@@ -33,6 +33,11 @@ impl StreamResult {
                 let id: i64 = (&v[0]).try_into().unwrap();
                 Ok(id as u32)
             }
+            // 订阅失败时按照状态码返回对应的错误
+            Some(Ok(res)) if res.status != 200 => {
+                res.into_result()?;
+                Err(KvError::Internal("Invalid stream".into()))
+            }
             _ => {
                 println!("Invalid stream aa");
                 Err(KvError::Internal("Invalid stream".into()))
@@ -5,7 +5,7 @@
 pub struct CommandRequest {
     #[prost(
         oneof = "command_request::RequestData",
-        tags = "1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12"
+        tags = "1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40, 41, 42, 43, 44, 45, 46, 47, 48, 49, 50"
     )]
     pub request_data: ::core::option::Option<command_request::RequestData>,
 }
@@ -39,6 +39,82 @@ pub mod command_request {
         Unsubscribe(super::Unsubscribe),
         #[prost(message, tag = "12")]
         Publish(super::Publish),
+        #[prost(message, tag = "13")]
+        Hsetex(super::Hsetex),
+        #[prost(message, tag = "14")]
+        Hexpire(super::Hexpire),
+        #[prost(message, tag = "15")]
+        Httl(super::Httl),
+        #[prost(message, tag = "16")]
+        Hpersist(super::Hpersist),
+        #[prost(message, tag = "17")]
+        Hincrby(super::Hincrby),
+        #[prost(message, tag = "18")]
+        Hincrbyfloat(super::Hincrbyfloat),
+        #[prost(message, tag = "19")]
+        Hsetnx(super::Hsetnx),
+        #[prost(message, tag = "20")]
+        Hcas(super::Hcas),
+        #[prost(message, tag = "21")]
+        Txn(super::Txn),
+        #[prost(message, tag = "22")]
+        Hscan(super::Hscan),
+        #[prost(message, tag = "23")]
+        ListTables(super::ListTables),
+        #[prost(message, tag = "24")]
+        DropTable(super::DropTable),
+        #[prost(message, tag = "25")]
+        TableLen(super::TableLen),
+        #[prost(message, tag = "26")]
+        RenameTable(super::RenameTable),
+        #[prost(message, tag = "27")]
+        Backup(super::Backup),
+        #[prost(message, tag = "28")]
+        Restore(super::Restore),
+        #[prost(message, tag = "29")]
+        Lpush(super::Lpush),
+        #[prost(message, tag = "30")]
+        Rpush(super::Rpush),
+        #[prost(message, tag = "31")]
+        Lpop(super::Lpop),
+        #[prost(message, tag = "32")]
+        Rpop(super::Rpop),
+        #[prost(message, tag = "33")]
+        Lrange(super::Lrange),
+        #[prost(message, tag = "34")]
+        Sadd(super::Sadd),
+        #[prost(message, tag = "35")]
+        Srem(super::Srem),
+        #[prost(message, tag = "36")]
+        Smembers(super::Smembers),
+        #[prost(message, tag = "37")]
+        Sismember(super::Sismember),
+        #[prost(message, tag = "38")]
+        Zadd(super::Zadd),
+        #[prost(message, tag = "39")]
+        Zrem(super::Zrem),
+        #[prost(message, tag = "40")]
+        Zscore(super::Zscore),
+        #[prost(message, tag = "41")]
+        Zrank(super::Zrank),
+        #[prost(message, tag = "42")]
+        Zrange(super::Zrange),
+        #[prost(message, tag = "43")]
+        Zrangebyscore(super::Zrangebyscore),
+        #[prost(message, tag = "44")]
+        Psubscribe(super::Psubscribe),
+        #[prost(message, tag = "45")]
+        Punsubscribe(super::Punsubscribe),
+        #[prost(message, tag = "46")]
+        Ack(super::Ack),
+        #[prost(message, tag = "47")]
+        Nack(super::Nack),
+        #[prost(message, tag = "48")]
+        PubsubTopics(super::PubsubTopics),
+        #[prost(message, tag = "49")]
+        PubsubNumsub(super::PubsubNumsub),
+        #[prost(message, tag = "50")]
+        PubsubSubscription(super::PubsubSubscription),
     }
 }
 #[derive(PartialOrd)]
@@ -47,6 +123,85 @@ pub mod command_request {
 pub struct Subscribe {
     #[prost(string, tag = "1")]
     pub topic: ::prost::alloc::string::String,
+    /// 不为空时以消费组成员的身份订阅，每条消息只投递给组中的一个成员，需要用 Ack 确认
+    #[prost(string, tag = "4")]
+    pub group: ::prost::alloc::string::String,
+    /// 缓冲区满了之后的处理策略：Block、DropOldest、DropNewest 或 Disconnect，为空时使用服务端的配置
+    #[prost(string, tag = "5")]
+    pub overflow: ::prost::alloc::string::String,
+    /// 从哪里开始接收消息，为空时只接收订阅之后发布的消息
+    #[prost(oneof = "subscribe::Start", tags = "2, 3")]
+    pub start: ::core::option::Option<subscribe::Start>,
+}
+/// Nested message and enum types in `Subscribe`.
+pub mod subscribe {
+    /// 从哪里开始接收消息，为空时只接收订阅之后发布的消息
+    #[derive(PartialOrd)]
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, Copy, PartialEq, ::prost::Oneof)]
+    pub enum Start {
+        /// 先重放 topic 保留的所有消息
+        #[prost(bool, tag = "2")]
+        Earliest(bool),
+        /// 先重放 topic 保留的 offset 不小于它的消息
+        #[prost(uint64, tag = "3")]
+        Offset(u64),
+    }
+}
+/// 确认消费组中 offset 对应的消息已经处理完成
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Ack {
+    #[prost(string, tag = "1")]
+    pub topic: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub group: ::prost::alloc::string::String,
+    #[prost(uint64, tag = "3")]
+    pub offset: u64,
+    /// 收到这条消息的成员的 subscription id，只有当前负责这条消息的成员可以确认
+    #[prost(uint32, tag = "4")]
+    pub id: u32,
+}
+/// 消息处理失败，马上重新投递给组中的下一个成员
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Nack {
+    #[prost(string, tag = "1")]
+    pub topic: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub group: ::prost::alloc::string::String,
+    #[prost(uint64, tag = "3")]
+    pub offset: u64,
+    /// 收到这条消息的成员的 subscription id
+    #[prost(uint32, tag = "4")]
+    pub id: u32,
+}
+/// 列出有订阅者的 topic，返回 topic -> 订阅者数量
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PubsubTopics {
+    /// glob 模式，为空时返回所有 topic
+    #[prost(string, tag = "1")]
+    pub pattern: ::prost::alloc::string::String,
+}
+/// 返回每个 topic 的订阅者数量，不包括模式订阅
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PubsubNumsub {
+    #[prost(string, repeated, tag = "1")]
+    pub topics: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// 返回 subscription 订阅的 topic 或模式、溢出策略、缓冲的和丢掉的消息数量
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct PubsubSubscription {
+    #[prost(uint32, tag = "1")]
+    pub id: u32,
 }
 #[derive(PartialOrd)]
 #[allow(clippy::derive_partial_eq_without_eq)]
@@ -66,6 +221,26 @@ pub struct Publish {
     #[prost(message, repeated, tag = "2")]
     pub dat: ::prost::alloc::vec::Vec<Value>,
 }
+/// 订阅所有名字匹配 glob 模式的 topic，支持 *、? 和 \[...\]
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Psubscribe {
+    #[prost(string, tag = "1")]
+    pub pattern: ::prost::alloc::string::String,
+    /// 同 Subscribe.overflow
+    #[prost(string, tag = "2")]
+    pub overflow: ::prost::alloc::string::String,
+}
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Punsubscribe {
+    #[prost(string, tag = "1")]
+    pub pattern: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "2")]
+    pub id: u32,
+}
 #[derive(PartialOrd)]
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -78,12 +253,48 @@ pub struct CommandResponse {
     pub values: ::prost::alloc::vec::Vec<Value>,
     #[prost(message, repeated, tag = "4")]
     pub pairs: ::prost::alloc::vec::Vec<Kvpair>,
+    /// Txn 中每个子命令的结果
+    #[prost(message, repeated, tag = "5")]
+    pub responses: ::prost::alloc::vec::Vec<CommandResponse>,
+    /// Hscan 下一页的游标，为空时表示没有更多数据
+    #[prost(string, tag = "6")]
+    pub cursor: ::prost::alloc::string::String,
+    /// 分块返回时，除最后一块外都为 true，最后一块作为结束标记
+    #[prost(bool, tag = "7")]
+    pub more: bool,
+    /// keyspace 通知中的变更事件
+    #[prost(message, optional, tag = "8")]
+    pub event: ::core::option::Option<KeyspaceEvent>,
+    /// 通过模式订阅收到的消息来自的 topic
+    #[prost(string, tag = "9")]
+    pub topic: ::prost::alloc::string::String,
+    /// 发布到 topic 的消息的 offset，每个 topic 从 1 开始递增
+    #[prost(uint64, tag = "10")]
+    pub offset: u64,
+}
+/// key 的变更事件，发布在 `__keyspace:<table>` topic 上
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct KeyspaceEvent {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    /// set、del、expire、persist、expired、evicted、incr，修改列表和集合时是命令名，比如 lpush、sadd
+    #[prost(string, tag = "3")]
+    pub op: ::prost::alloc::string::String,
+    /// 修改前和修改后的 value，不存在或者没有记录时为空
+    #[prost(message, optional, tag = "4")]
+    pub old_value: ::core::option::Option<Value>,
+    #[prost(message, optional, tag = "5")]
+    pub new_value: ::core::option::Option<Value>,
 }
 #[derive(PartialOrd)]
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Value {
-    #[prost(oneof = "value::Value", tags = "1, 2, 3, 4, 5")]
+    #[prost(oneof = "value::Value", tags = "1, 2, 3, 4, 5, 6, 7, 8")]
     pub value: ::core::option::Option<value::Value>,
 }
 /// Nested message and enum types in `Value`.
@@ -102,8 +313,30 @@ pub mod value {
         Float(f64),
         #[prost(bool, tag = "5")]
         Bool(bool),
+        #[prost(message, tag = "6")]
+        List(super::ValueList),
+        #[prost(message, tag = "7")]
+        Set(super::ValueSet),
+        #[prost(message, tag = "8")]
+        Zset(super::ValueZset),
     }
 }
+/// 列表，元素按插入的位置排列
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ValueList {
+    #[prost(message, repeated, tag = "1")]
+    pub values: ::prost::alloc::vec::Vec<Value>,
+}
+/// 集合，元素不重复，按加入的顺序排列
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ValueSet {
+    #[prost(message, repeated, tag = "1")]
+    pub values: ::prost::alloc::vec::Vec<Value>,
+}
 #[derive(PartialOrd)]
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -192,3 +425,513 @@ pub struct Hmexists {
     #[prost(string, repeated, tag = "2")]
     pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
 }
+/// 设置 key 的同时指定过期时间（毫秒）
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hsetex {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "2")]
+    pub pair: ::core::option::Option<Kvpair>,
+    #[prost(uint64, tag = "3")]
+    pub ttl_ms: u64,
+}
+/// 为已存在的 key 设置过期时间（毫秒）
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hexpire {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(uint64, tag = "3")]
+    pub ttl_ms: u64,
+}
+/// 查询 key 的剩余存活时间（毫秒），没有过期时间时返回 -1
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Httl {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+}
+/// 去掉 key 的过期时间
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hpersist {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+}
+/// 原子地给整数加上 delta，key 不存在时从 0 开始
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hincrby {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(int64, tag = "3")]
+    pub delta: i64,
+}
+/// 原子地给浮点数加上 delta，key 不存在时从 0 开始
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hincrbyfloat {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(double, tag = "3")]
+    pub delta: f64,
+}
+/// key 不存在时才写入
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hsetnx {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "2")]
+    pub pair: ::core::option::Option<Kvpair>,
+}
+/// 当前值等于 expected 时才写入 value，expected 为空表示 key 不存在
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hcas {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "3")]
+    pub expected: ::core::option::Option<Value>,
+    #[prost(message, optional, tag = "4")]
+    pub value: ::core::option::Option<Value>,
+}
+/// 原子地执行一批表命令，每个子命令返回一个 CommandResponse
+/// 任意子命令失败（404 Not Found 除外）时，所有写入都会被回滚
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Txn {
+    #[prost(message, repeated, tag = "1")]
+    pub commands: ::prost::alloc::vec::Vec<CommandRequest>,
+}
+/// 按 key 的顺序分页扫描 table
+/// start 和 end 为空时表示不限制，范围是 [start, end)
+/// limit 为 0 时使用默认的分页大小，cursor 为上一页返回的游标，只能用于同样的 table 和范围
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hscan {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub prefix: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub start: ::prost::alloc::string::String,
+    #[prost(string, tag = "4")]
+    pub end: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "5")]
+    pub limit: u32,
+    #[prost(string, tag = "6")]
+    pub cursor: ::prost::alloc::string::String,
+}
+/// Hscan 的游标，编码后返回给客户端：扫描的 table 和范围，以及上一页的最后一个 key
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct HscanCursor {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub prefix: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub start: ::prost::alloc::string::String,
+    #[prost(string, tag = "4")]
+    pub end: ::prost::alloc::string::String,
+    #[prost(string, tag = "5")]
+    pub key: ::prost::alloc::string::String,
+}
+/// 列出所有有数据的 table
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct ListTables {}
+/// 删除 table 及其所有数据
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DropTable {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+}
+/// 返回 table 中 key 的数量
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TableLen {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+}
+/// 重命名 table，to 已经有数据时失败
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RenameTable {
+    #[prost(string, tag = "1")]
+    pub from: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub to: ::prost::alloc::string::String,
+}
+/// 把所有 table 备份到服务器上的文件，返回备份的 key 数量
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Backup {
+    #[prost(string, tag = "1")]
+    pub path: ::prost::alloc::string::String,
+}
+/// 从服务器上的备份文件恢复数据，已有的同名 key 会被覆盖，返回恢复的 key 数量
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Restore {
+    #[prost(string, tag = "1")]
+    pub path: ::prost::alloc::string::String,
+}
+/// 有序集合，member 不重复，按 (score, member) 从小到大排列
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ValueZset {
+    #[prost(message, repeated, tag = "1")]
+    pub members: ::prost::alloc::vec::Vec<ScoredMember>,
+}
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ScoredMember {
+    #[prost(string, tag = "1")]
+    pub member: ::prost::alloc::string::String,
+    #[prost(double, tag = "2")]
+    pub score: f64,
+}
+/// 把 values 依次插入列表头部，key 不存在时创建列表，返回列表的长度
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Lpush {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag = "3")]
+    pub values: ::prost::alloc::vec::Vec<Value>,
+}
+/// 把 values 依次插入列表尾部，key 不存在时创建列表，返回列表的长度
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Rpush {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag = "3")]
+    pub values: ::prost::alloc::vec::Vec<Value>,
+}
+/// 从列表头部弹出最多 count 个元素，count 为 0 时弹出一个，列表为空时删除 key
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Lpop {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "3")]
+    pub count: u32,
+}
+/// 从列表尾部弹出最多 count 个元素，count 为 0 时弹出一个，列表为空时删除 key
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Rpop {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "3")]
+    pub count: u32,
+}
+/// 返回列表中下标在 \[start, stop\] 之间的元素，负数表示从尾部开始计数，-1 是最后一个元素
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Lrange {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(int64, tag = "3")]
+    pub start: i64,
+    #[prost(int64, tag = "4")]
+    pub stop: i64,
+}
+/// 把 values 加入集合，key 不存在时创建集合，返回新加入的元素数量
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Sadd {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag = "3")]
+    pub values: ::prost::alloc::vec::Vec<Value>,
+}
+/// 从集合中删除 values，集合为空时删除 key，返回删除的元素数量
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Srem {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag = "3")]
+    pub values: ::prost::alloc::vec::Vec<Value>,
+}
+/// 返回集合中的所有元素
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Smembers {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+}
+/// 判断 value 是否在集合中
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Sismember {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "3")]
+    pub value: ::core::option::Option<Value>,
+}
+/// 把 members 加入有序集合，已有的 member 更新 score，返回新加入的 member 数量
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Zadd {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag = "3")]
+    pub members: ::prost::alloc::vec::Vec<ScoredMember>,
+}
+/// 从有序集合中删除 members，有序集合为空时删除 key，返回删除的 member 数量
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Zrem {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag = "3")]
+    pub members: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// 返回 member 的 score
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Zscore {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub member: ::prost::alloc::string::String,
+}
+/// 返回 member 按 score 从小到大的排名（从 0 开始），rev 为 true 时从大到小
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Zrank {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub member: ::prost::alloc::string::String,
+    #[prost(bool, tag = "4")]
+    pub rev: bool,
+}
+/// 按排名返回 \[start, stop\] 之间的 member 和 score，负数表示从最后一名开始计数，
+/// rev 为 true 时按 score 从大到小排名。结果放在 pairs 中，key 是 member，value 是 score
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Zrange {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(int64, tag = "3")]
+    pub start: i64,
+    #[prost(int64, tag = "4")]
+    pub stop: i64,
+    #[prost(bool, tag = "5")]
+    pub rev: bool,
+}
+/// 按 score 从小到大返回 score 在 \[min, max\] 之间的 member 和 score，结果格式和 Zrange 相同
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Zrangebyscore {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(double, tag = "3")]
+    pub min: f64,
+    #[prost(double, tag = "4")]
+    pub max: f64,
+}
+/// 备份文件由 FrameCoder 编码的 BackupRecord 组成：第一条是 header，
+/// 之后每个 table 先是一条 table 记录，再是它的所有 pair，最后一条是 footer
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct BackupRecord {
+    /// pair 剩余的存活时间（毫秒），0 表示不过期
+    #[prost(uint64, tag = "5")]
+    pub ttl: u64,
+    #[prost(oneof = "backup_record::Record", tags = "1, 2, 3, 4")]
+    pub record: ::core::option::Option<backup_record::Record>,
+}
+/// Nested message and enum types in `BackupRecord`.
+pub mod backup_record {
+    #[derive(PartialOrd)]
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Oneof)]
+    pub enum Record {
+        #[prost(message, tag = "1")]
+        Header(super::BackupHeader),
+        #[prost(string, tag = "2")]
+        Table(::prost::alloc::string::String),
+        #[prost(message, tag = "3")]
+        Pair(super::Kvpair),
+        #[prost(message, tag = "4")]
+        Footer(super::BackupFooter),
+    }
+}
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct BackupHeader {
+    #[prost(uint32, tag = "1")]
+    pub version: u32,
+}
+/// 用来检查备份文件是否完整
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct BackupFooter {
+    #[prost(uint64, tag = "1")]
+    pub count: u64,
+}
+/// MemTable 的 WAL 记录，快照文件也由同样的记录组成
+/// seq 单调递增，一条记录中的 ops 需要原子地生效
+/// 快照的第一条记录没有 ops，它的 seq 是快照包含的最后一条 WAL 记录
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct WalRecord {
+    #[prost(uint64, tag = "1")]
+    pub seq: u64,
+    #[prost(message, repeated, tag = "2")]
+    pub ops: ::prost::alloc::vec::Vec<WalOp>,
+}
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct WalOp {
+    #[prost(oneof = "wal_op::Op", tags = "1, 2, 3, 4, 5, 6, 7")]
+    pub op: ::core::option::Option<wal_op::Op>,
+}
+/// Nested message and enum types in `WalOp`.
+pub mod wal_op {
+    #[derive(PartialOrd)]
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Oneof)]
+    pub enum Op {
+        #[prost(message, tag = "1")]
+        Set(super::WalSet),
+        #[prost(message, tag = "2")]
+        Del(super::WalDel),
+        #[prost(message, tag = "3")]
+        Expire(super::WalExpire),
+        #[prost(message, tag = "4")]
+        DropTable(super::DropTable),
+        #[prost(message, tag = "5")]
+        RenameTable(super::RenameTable),
+        /// 有序集合的修改只记录变化的 member，不需要编码整个有序集合
+        #[prost(message, tag = "6")]
+        Zadd(super::Zadd),
+        #[prost(message, tag = "7")]
+        Zrem(super::Zrem),
+    }
+}
+/// expire_at 是 unix 毫秒时间戳，0 表示不过期
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct WalSet {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "3")]
+    pub value: ::core::option::Option<Value>,
+    #[prost(uint64, tag = "4")]
+    pub expire_at: u64,
+}
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct WalDel {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+}
+/// expire_at 为 0 时表示去掉过期时间
+#[derive(PartialOrd)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct WalExpire {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(uint64, tag = "3")]
+    pub expire_at: u64,
+}
@@ -1,12 +1,17 @@
 use abi::{
-    command_request::RequestData, value, CommandRequest, CommandResponse, Hget, Hgetall, Hset,
-    Kvpair, Publish, Subscribe, Unsubscribe, Value,
+    command_request::RequestData, subscribe, value, Ack, Backup, CommandRequest, CommandResponse,
+    DropTable, Hcas, Hdel, Hexists, Hexpire, Hget, Hgetall, Hincrby, Hincrbyfloat, Hmdel, Hmexists,
+    Hmget, Hmset, Hpersist, Hscan, Hset, Hsetex, Hsetnx, Httl, KeyspaceEvent, Kvpair, ListTables,
+    Lpop, Lpush, Lrange, Nack, Psubscribe, Publish, PubsubNumsub, PubsubSubscription, PubsubTopics,
+    Punsubscribe, RenameTable, Restore, Rpop, Rpush, Sadd, ScoredMember, Sismember, Smembers, Srem,
+    Subscribe, TableLen, Txn, Unsubscribe, Value, ValueList, ValueSet, ValueZset, Zadd, Zrange,
+    Zrangebyscore, Zrank, Zrem, Zscore,
 };
 use bytes::Bytes;
 use http::StatusCode;
 use prost::Message;
 
-use crate::KvError;
+use crate::{KvError, OverflowPolicy};
 
 pub mod abi;
 
@@ -37,9 +42,496 @@ impl CommandRequest {
         }
     }
 
+    pub fn new_hmget(table: impl Into<String>, keys: Vec<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hmget(Hmget {
+                table: table.into(),
+                keys,
+            })),
+        }
+    }
+
+    pub fn new_hmset(table: impl Into<String>, pairs: Vec<Kvpair>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hmset(Hmset {
+                table: table.into(),
+                pairs,
+            })),
+        }
+    }
+
+    pub fn new_hdel(table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hdel(Hdel {
+                table: table.into(),
+                key: key.into(),
+            })),
+        }
+    }
+
+    pub fn new_hmdel(table: impl Into<String>, keys: Vec<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hmdel(Hmdel {
+                table: table.into(),
+                keys,
+            })),
+        }
+    }
+
+    pub fn new_hexists(table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hexists(Hexists {
+                table: table.into(),
+                key: key.into(),
+            })),
+        }
+    }
+
+    pub fn new_hmexists(table: impl Into<String>, keys: Vec<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hmexists(Hmexists {
+                table: table.into(),
+                keys,
+            })),
+        }
+    }
+
+    pub fn new_hsetex(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        value: Value,
+        ttl_ms: u64,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Hsetex(Hsetex {
+                table: table.into(),
+                pair: Some(Kvpair::new(key, value)),
+                ttl_ms,
+            })),
+        }
+    }
+
+    pub fn new_hexpire(table: impl Into<String>, key: impl Into<String>, ttl_ms: u64) -> Self {
+        Self {
+            request_data: Some(RequestData::Hexpire(Hexpire {
+                table: table.into(),
+                key: key.into(),
+                ttl_ms,
+            })),
+        }
+    }
+
+    pub fn new_httl(table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Httl(Httl {
+                table: table.into(),
+                key: key.into(),
+            })),
+        }
+    }
+
+    pub fn new_hpersist(table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hpersist(Hpersist {
+                table: table.into(),
+                key: key.into(),
+            })),
+        }
+    }
+
+    pub fn new_hincrby(table: impl Into<String>, key: impl Into<String>, delta: i64) -> Self {
+        Self {
+            request_data: Some(RequestData::Hincrby(Hincrby {
+                table: table.into(),
+                key: key.into(),
+                delta,
+            })),
+        }
+    }
+
+    pub fn new_hincrbyfloat(table: impl Into<String>, key: impl Into<String>, delta: f64) -> Self {
+        Self {
+            request_data: Some(RequestData::Hincrbyfloat(Hincrbyfloat {
+                table: table.into(),
+                key: key.into(),
+                delta,
+            })),
+        }
+    }
+
+    pub fn new_hsetnx(table: impl Into<String>, key: impl Into<String>, value: Value) -> Self {
+        Self {
+            request_data: Some(RequestData::Hsetnx(Hsetnx {
+                table: table.into(),
+                pair: Some(Kvpair::new(key, value)),
+            })),
+        }
+    }
+
+    pub fn new_hcas(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        expected: Option<Value>,
+        value: Value,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Hcas(Hcas {
+                table: table.into(),
+                key: key.into(),
+                expected,
+                value: Some(value),
+            })),
+        }
+    }
+
+    pub fn new_txn(commands: Vec<CommandRequest>) -> Self {
+        Self {
+            request_data: Some(RequestData::Txn(Txn { commands })),
+        }
+    }
+
+    pub fn new_hscan(
+        table: impl Into<String>,
+        prefix: impl Into<String>,
+        limit: u32,
+        cursor: impl Into<String>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Hscan(Hscan {
+                table: table.into(),
+                prefix: prefix.into(),
+                limit,
+                cursor: cursor.into(),
+                ..Default::default()
+            })),
+        }
+    }
+
+    pub fn new_hscan_range(
+        table: impl Into<String>,
+        start: impl Into<String>,
+        end: impl Into<String>,
+        limit: u32,
+        cursor: impl Into<String>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Hscan(Hscan {
+                table: table.into(),
+                start: start.into(),
+                end: end.into(),
+                limit,
+                cursor: cursor.into(),
+                ..Default::default()
+            })),
+        }
+    }
+
+    pub fn new_list_tables() -> Self {
+        Self {
+            request_data: Some(RequestData::ListTables(ListTables {})),
+        }
+    }
+
+    pub fn new_drop_table(table: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::DropTable(DropTable {
+                table: table.into(),
+            })),
+        }
+    }
+
+    pub fn new_table_len(table: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::TableLen(TableLen {
+                table: table.into(),
+            })),
+        }
+    }
+
+    pub fn new_rename_table(from: impl Into<String>, to: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::RenameTable(RenameTable {
+                from: from.into(),
+                to: to.into(),
+            })),
+        }
+    }
+
+    pub fn new_backup(path: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Backup(Backup { path: path.into() })),
+        }
+    }
+
+    pub fn new_restore(path: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Restore(Restore { path: path.into() })),
+        }
+    }
+
+    pub fn new_lpush(table: impl Into<String>, key: impl Into<String>, values: Vec<Value>) -> Self {
+        Self {
+            request_data: Some(RequestData::Lpush(Lpush {
+                table: table.into(),
+                key: key.into(),
+                values,
+            })),
+        }
+    }
+
+    pub fn new_rpush(table: impl Into<String>, key: impl Into<String>, values: Vec<Value>) -> Self {
+        Self {
+            request_data: Some(RequestData::Rpush(Rpush {
+                table: table.into(),
+                key: key.into(),
+                values,
+            })),
+        }
+    }
+
+    pub fn new_lpop(table: impl Into<String>, key: impl Into<String>, count: u32) -> Self {
+        Self {
+            request_data: Some(RequestData::Lpop(Lpop {
+                table: table.into(),
+                key: key.into(),
+                count,
+            })),
+        }
+    }
+
+    pub fn new_rpop(table: impl Into<String>, key: impl Into<String>, count: u32) -> Self {
+        Self {
+            request_data: Some(RequestData::Rpop(Rpop {
+                table: table.into(),
+                key: key.into(),
+                count,
+            })),
+        }
+    }
+
+    pub fn new_lrange(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        start: i64,
+        stop: i64,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Lrange(Lrange {
+                table: table.into(),
+                key: key.into(),
+                start,
+                stop,
+            })),
+        }
+    }
+
+    pub fn new_sadd(table: impl Into<String>, key: impl Into<String>, values: Vec<Value>) -> Self {
+        Self {
+            request_data: Some(RequestData::Sadd(Sadd {
+                table: table.into(),
+                key: key.into(),
+                values,
+            })),
+        }
+    }
+
+    pub fn new_srem(table: impl Into<String>, key: impl Into<String>, values: Vec<Value>) -> Self {
+        Self {
+            request_data: Some(RequestData::Srem(Srem {
+                table: table.into(),
+                key: key.into(),
+                values,
+            })),
+        }
+    }
+
+    pub fn new_smembers(table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Smembers(Smembers {
+                table: table.into(),
+                key: key.into(),
+            })),
+        }
+    }
+
+    pub fn new_sismember(table: impl Into<String>, key: impl Into<String>, value: Value) -> Self {
+        Self {
+            request_data: Some(RequestData::Sismember(Sismember {
+                table: table.into(),
+                key: key.into(),
+                value: Some(value),
+            })),
+        }
+    }
+
+    pub fn new_zadd(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        members: Vec<ScoredMember>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Zadd(Zadd {
+                table: table.into(),
+                key: key.into(),
+                members,
+            })),
+        }
+    }
+
+    pub fn new_zrem(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        members: Vec<String>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Zrem(Zrem {
+                table: table.into(),
+                key: key.into(),
+                members,
+            })),
+        }
+    }
+
+    pub fn new_zscore(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        member: impl Into<String>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Zscore(Zscore {
+                table: table.into(),
+                key: key.into(),
+                member: member.into(),
+            })),
+        }
+    }
+
+    pub fn new_zrank(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        member: impl Into<String>,
+        rev: bool,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Zrank(Zrank {
+                table: table.into(),
+                key: key.into(),
+                member: member.into(),
+                rev,
+            })),
+        }
+    }
+
+    pub fn new_zrange(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        start: i64,
+        stop: i64,
+        rev: bool,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Zrange(Zrange {
+                table: table.into(),
+                key: key.into(),
+                start,
+                stop,
+                rev,
+            })),
+        }
+    }
+
+    pub fn new_zrangebyscore(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        min: f64,
+        max: f64,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Zrangebyscore(Zrangebyscore {
+                table: table.into(),
+                key: key.into(),
+                min,
+                max,
+            })),
+        }
+    }
+
     pub fn new_subscribe(name: impl Into<String>) -> Self {
         Self {
-            request_data: Some(RequestData::Subscribe(Subscribe { topic: name.into() })),
+            request_data: Some(RequestData::Subscribe(Subscribe {
+                topic: name.into(),
+                start: None,
+                group: String::new(),
+                overflow: String::new(),
+            })),
+        }
+    }
+
+    /// 订阅 topic，先重放 topic 保留的 offset 不小于 offset 的消息
+    pub fn new_subscribe_from(name: impl Into<String>, offset: u64) -> Self {
+        Self {
+            request_data: Some(RequestData::Subscribe(Subscribe {
+                topic: name.into(),
+                start: Some(subscribe::Start::Offset(offset)),
+                group: String::new(),
+                overflow: String::new(),
+            })),
+        }
+    }
+
+    /// 订阅 topic，先重放 topic 保留的所有消息
+    pub fn new_subscribe_earliest(name: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Subscribe(Subscribe {
+                topic: name.into(),
+                start: Some(subscribe::Start::Earliest(true)),
+                group: String::new(),
+                overflow: String::new(),
+            })),
+        }
+    }
+
+    /// 以消费组 group 成员的身份订阅 topic
+    pub fn new_subscribe_group(name: impl Into<String>, group: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Subscribe(Subscribe {
+                topic: name.into(),
+                start: None,
+                group: group.into(),
+                overflow: String::new(),
+            })),
+        }
+    }
+
+    pub fn new_ack(
+        name: impl Into<String>,
+        group: impl Into<String>,
+        id: u32,
+        offset: u64,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Ack(Ack {
+                topic: name.into(),
+                group: group.into(),
+                offset,
+                id,
+            })),
+        }
+    }
+
+    pub fn new_nack(
+        name: impl Into<String>,
+        group: impl Into<String>,
+        id: u32,
+        offset: u64,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Nack(Nack {
+                topic: name.into(),
+                group: group.into(),
+                offset,
+                id,
+            })),
         }
     }
 
@@ -60,6 +552,55 @@ impl CommandRequest {
             })),
         }
     }
+
+    pub fn new_psubscribe(pattern: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Psubscribe(Psubscribe {
+                pattern: pattern.into(),
+                overflow: String::new(),
+            })),
+        }
+    }
+
+    pub fn new_punsubscribe(pattern: impl Into<String>, id: u32) -> Self {
+        Self {
+            request_data: Some(RequestData::Punsubscribe(Punsubscribe {
+                pattern: pattern.into(),
+                id,
+            })),
+        }
+    }
+
+    /// 列出名字匹配 glob 模式的 topic，pattern 为空时列出所有 topic
+    pub fn new_pubsub_topics(pattern: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::PubsubTopics(PubsubTopics {
+                pattern: pattern.into(),
+            })),
+        }
+    }
+
+    pub fn new_pubsub_numsub(topics: Vec<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::PubsubNumsub(PubsubNumsub { topics })),
+        }
+    }
+
+    pub fn new_pubsub_subscription(id: u32) -> Self {
+        Self {
+            request_data: Some(RequestData::PubsubSubscription(PubsubSubscription { id })),
+        }
+    }
+
+    /// 设置 Subscribe 或 Psubscribe 缓冲区满了之后的处理策略，其他命令不受影响
+    pub fn with_overflow(mut self, policy: OverflowPolicy) -> Self {
+        match &mut self.request_data {
+            Some(RequestData::Subscribe(v)) => v.overflow = policy.as_str().into(),
+            Some(RequestData::Psubscribe(v)) => v.overflow = policy.as_str().into(),
+            _ => {}
+        }
+        self
+    }
 }
 
 impl Kvpair {
@@ -71,6 +612,48 @@ impl Kvpair {
     }
 }
 
+impl ScoredMember {
+    pub fn new(member: impl Into<String>, score: f64) -> Self {
+        Self {
+            member: member.into(),
+            score,
+        }
+    }
+}
+
+impl KeyspaceEvent {
+    pub fn new(
+        table: &str,
+        key: &str,
+        op: &str,
+        old_value: Option<Value>,
+        new_value: Option<Value>,
+    ) -> Self {
+        Self {
+            table: table.into(),
+            key: key.into(),
+            op: op.into(),
+            old_value,
+            new_value,
+        }
+    }
+}
+
+impl From<KeyspaceEvent> for CommandResponse {
+    fn from(v: KeyspaceEvent) -> Self {
+        Self {
+            event: Some(v),
+            ..CommandResponse::ok()
+        }
+    }
+}
+
+impl From<ScoredMember> for Kvpair {
+    fn from(v: ScoredMember) -> Self {
+        Kvpair::new(v.member, v.score.into())
+    }
+}
+
 impl From<String> for Value {
     fn from(s: String) -> Self {
         Self {
@@ -87,6 +670,22 @@ impl From<i64> for Value {
     }
 }
 
+impl From<f64> for Value {
+    fn from(f: f64) -> Self {
+        Self {
+            value: Some(abi::value::Value::Float(f)),
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Self {
+            value: Some(abi::value::Value::Bool(b)),
+        }
+    }
+}
+
 impl From<&str> for Value {
     fn from(s: &str) -> Self {
         Self {
@@ -95,6 +694,30 @@ impl From<&str> for Value {
     }
 }
 
+impl From<ValueList> for Value {
+    fn from(list: ValueList) -> Self {
+        Self {
+            value: Some(value::Value::List(list)),
+        }
+    }
+}
+
+impl From<ValueSet> for Value {
+    fn from(set: ValueSet) -> Self {
+        Self {
+            value: Some(value::Value::Set(set)),
+        }
+    }
+}
+
+impl From<ValueZset> for Value {
+    fn from(zset: ValueZset) -> Self {
+        Self {
+            value: Some(value::Value::Zset(zset)),
+        }
+    }
+}
+
 impl Value {
     /// 转换成 string 做错误处理
     pub fn format(&self) -> String {
@@ -136,6 +759,28 @@ impl CommandResponse {
     pub fn format(&self) -> String {
         format!("{:?}", self)
     }
+
+    /// 非 200 的响应按照状态码转换成对应的 KvError，和 From<KvError> 相反
+    pub fn into_result(self) -> Result<Self, KvError> {
+        let msg = |prefix: &str| {
+            let msg = self.message.strip_prefix(prefix).unwrap_or(&self.message);
+            msg.to_string()
+        };
+        let err = match StatusCode::from_u16(self.status as u16) {
+            Ok(StatusCode::OK) => return Ok(self),
+            Ok(StatusCode::NOT_FOUND) => KvError::NotFound(msg("Not Found: ")),
+            Ok(StatusCode::BAD_REQUEST) => {
+                let msg = msg("Command is invalid: `");
+                KvError::InvalidCommand(msg.strip_suffix('`').unwrap_or(&msg).into())
+            }
+            Ok(StatusCode::PRECONDITION_FAILED) => {
+                KvError::PreconditionFailed(msg("Precondition failed: "))
+            }
+            Ok(StatusCode::CONFLICT) => KvError::Conflict(msg("Transaction conflict: ")),
+            _ => KvError::Internal(msg("Internal error: ")),
+        };
+        Err(err)
+    }
 }
 
 impl From<Value> for CommandResponse {
@@ -165,16 +810,31 @@ impl From<KvError> for CommandResponse {
             message: e.to_string(),
             values: vec![],
             pairs: vec![],
+            ..Default::default()
         };
         match e {
             KvError::NotFound(_) => result.status = StatusCode::NOT_FOUND.as_u16() as _,
             KvError::InvalidCommand(_) => result.status = StatusCode::BAD_REQUEST.as_u16() as _,
+            KvError::PreconditionFailed(_) => {
+                result.status = StatusCode::PRECONDITION_FAILED.as_u16() as _
+            }
+            KvError::Conflict(_) => result.status = StatusCode::CONFLICT.as_u16() as _,
             _ => {}
         }
         result
     }
 }
 
+impl From<Vec<CommandResponse>> for CommandResponse {
+    fn from(v: Vec<CommandResponse>) -> Self {
+        Self {
+            status: StatusCode::OK.as_u16() as _,
+            responses: v,
+            ..Default::default()
+        }
+    }
+}
+
 impl From<Vec<Value>> for CommandResponse {
     fn from(v: Vec<Value>) -> Self {
         Self {
@@ -217,7 +877,7 @@ impl TryFrom<&CommandResponse> for i64 {
         if value.status != StatusCode::OK.as_u16() as u32 {
             return Err(KvError::ConvertError(value.format(), "CommandResponse"));
         }
-        match value.values.get(0) {
+        match value.values.first() {
             Some(v) => v.try_into(),
             None => Err(KvError::ConvertError(value.format(), "CommandResponse")),
         }
@@ -234,3 +894,59 @@ impl TryFrom<&Value> for i64 {
         }
     }
 }
+
+impl TryFrom<&Value> for bool {
+    type Error = KvError;
+
+    fn try_from(v: &Value) -> Result<Self, Self::Error> {
+        match v.value {
+            Some(value::Value::Bool(b)) => Ok(b),
+            _ => Err(KvError::ConvertError(v.format(), "Bool")),
+        }
+    }
+}
+
+impl TryFrom<&Value> for f64 {
+    type Error = KvError;
+
+    fn try_from(v: &Value) -> Result<Self, Self::Error> {
+        match v.value {
+            Some(value::Value::Float(f)) => Ok(f),
+            Some(value::Value::Integer(i)) => Ok(i as f64),
+            _ => Err(KvError::ConvertError(v.format(), "Float")),
+        }
+    }
+}
+
+impl TryFrom<&Value> for ValueList {
+    type Error = KvError;
+
+    fn try_from(v: &Value) -> Result<Self, Self::Error> {
+        match &v.value {
+            Some(value::Value::List(list)) => Ok(list.clone()),
+            _ => Err(KvError::ConvertError(v.format(), "List")),
+        }
+    }
+}
+
+impl TryFrom<&Value> for ValueSet {
+    type Error = KvError;
+
+    fn try_from(v: &Value) -> Result<Self, Self::Error> {
+        match &v.value {
+            Some(value::Value::Set(set)) => Ok(set.clone()),
+            _ => Err(KvError::ConvertError(v.format(), "Set")),
+        }
+    }
+}
+
+impl TryFrom<&Value> for ValueZset {
+    type Error = KvError;
+
+    fn try_from(v: &Value) -> Result<Self, Self::Error> {
+        match &v.value {
+            Some(value::Value::Zset(zset)) => Ok(zset.clone()),
+            _ => Err(KvError::ConvertError(v.format(), "Zset")),
+        }
+    }
+}
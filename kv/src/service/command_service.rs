@@ -1,11 +1,26 @@
+use std::{ops::Bound, time::Duration};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use http::StatusCode;
+use prost::Message;
+
 use crate::{
+    command_request::RequestData,
     error::KvError,
-    pb::abi::{CommandResponse, Hget},
-    storage::Storage,
-    Hgetall, Hset, Value,
+    pb::abi::{CommandResponse, Hget, HscanCursor, Kvpair},
+    storage::{backup, is_reserved_table, restore, Storage, TxnStore},
+    Backup, DropTable, Hcas, Hdel, Hexists, Hexpire, Hgetall, Hincrby, Hincrbyfloat, Hmdel,
+    Hmexists, Hmget, Hmset, Hpersist, Hscan, Hset, Hsetex, Hsetnx, Httl, ListTables, Lpop, Lpush,
+    Lrange, RenameTable, Restore, Rpop, Rpush, Sadd, Sismember, Smembers, Srem, TableLen, Txn,
+    Value, Zadd, Zrange, Zrangebyscore, Zrank, Zrem, Zscore,
 };
 
-use super::CommandService;
+use super::{dispatch_command, CommandService};
+
+/// 事务提交时发生冲突的最大重试次数
+const TXN_MAX_RETRIES: usize = 8;
+/// Hscan 没有指定 limit 时每页返回的条数
+const HSCAN_DEFAULT_LIMIT: usize = 100;
 
 impl CommandService for Hget {
     fn execute(self, store: &impl Storage) -> CommandResponse {
@@ -39,9 +54,551 @@ impl CommandService for Hset {
     }
 }
 
+impl CommandService for Hmget {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        self.keys
+            .iter()
+            .map(|key| store.get(&self.table, key).map(Option::unwrap_or_default))
+            .collect::<Result<Vec<_>, _>>()
+            .map_or_else(|e| e.into(), |v| v.into())
+    }
+}
+
+impl CommandService for Hmset {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let table = self.table;
+        self.pairs
+            .into_iter()
+            .map(|pair| {
+                store
+                    .set(&table, pair.key, pair.value.unwrap_or_default())
+                    .map(Option::unwrap_or_default)
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_or_else(|e| e.into(), |v| v.into())
+    }
+}
+
+impl CommandService for Hdel {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.del(&self.table, &self.key) {
+            Ok(Some(v)) => v.into(),
+            Ok(None) => Value::default().into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hmdel {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        self.keys
+            .iter()
+            .map(|key| store.del(&self.table, key).map(Option::unwrap_or_default))
+            .collect::<Result<Vec<_>, _>>()
+            .map_or_else(|e| e.into(), |v| v.into())
+    }
+}
+
+impl CommandService for Hexists {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.contains(&self.table, &self.key) {
+            Ok(v) => Value::from(v).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hmexists {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        self.keys
+            .iter()
+            .map(|key| store.contains(&self.table, key).map(Value::from))
+            .collect::<Result<Vec<_>, _>>()
+            .map_or_else(|e| e.into(), |v| v.into())
+    }
+}
+
+impl CommandService for Hsetex {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let ttl = Duration::from_millis(self.ttl_ms);
+        match self.pair {
+            Some(v) => {
+                match store.set_with_ttl(&self.table, v.key, v.value.unwrap_or_default(), ttl) {
+                    Ok(Some(v)) => v.into(),
+                    Ok(None) => Value::default().into(),
+                    Err(e) => e.into(),
+                }
+            }
+            None => KvError::InvalidCommand("Hsetex has no pair".into()).into(),
+        }
+    }
+}
+
+impl CommandService for Hexpire {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let ttl = Duration::from_millis(self.ttl_ms);
+        match store.expire(&self.table, &self.key, ttl) {
+            Ok(v) => Value::from(v).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Httl {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.contains(&self.table, &self.key) {
+            Ok(true) => {}
+            Ok(false) => {
+                return KvError::NotFound(format!("table {}, key {}", self.table, self.key)).into()
+            }
+            Err(e) => return e.into(),
+        }
+        match store.ttl(&self.table, &self.key) {
+            Ok(Some(v)) => Value::from(v.as_millis() as i64).into(),
+            Ok(None) => Value::from(-1).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hpersist {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.persist(&self.table, &self.key) {
+            Ok(v) => Value::from(v).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hincrby {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.incr(&self.table, &self.key, self.delta) {
+            Ok(v) => Value::from(v).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hincrbyfloat {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.incr_float(&self.table, &self.key, self.delta) {
+            Ok(v) => Value::from(v).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hsetnx {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match self.pair {
+            Some(v) => {
+                let value = v.value.unwrap_or_default();
+                match store.compare_and_swap(&self.table, &v.key, None, value) {
+                    Ok(true) => Value::from(true).into(),
+                    Ok(false) => KvError::PreconditionFailed(format!(
+                        "table {}, key {} already exists",
+                        self.table, v.key
+                    ))
+                    .into(),
+                    Err(e) => e.into(),
+                }
+            }
+            None => KvError::InvalidCommand("Hsetnx has no pair".into()).into(),
+        }
+    }
+}
+
+impl CommandService for Hcas {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let value = self.value.unwrap_or_default();
+        match store.compare_and_swap(&self.table, &self.key, self.expected, value) {
+            Ok(true) => Value::from(true).into(),
+            Ok(false) => KvError::PreconditionFailed(format!(
+                "table {}, key {} doesn't match the expected value",
+                self.table, self.key
+            ))
+            .into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hscan {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let limit = match self.limit {
+            0 => HSCAN_DEFAULT_LIMIT,
+            n => n as usize,
+        };
+        // 有游标时从上一页最后一个 key 之后开始，否则从 start 和 prefix 中靠后的位置开始扫描
+        let start = match decode_cursor(&self) {
+            Ok(Some(key)) => Bound::Excluded(key),
+            Ok(None) => Bound::Included(self.start.as_str().max(self.prefix.as_str()).to_owned()),
+            Err(e) => return e.into(),
+        };
+        let end = match self.end.is_empty() {
+            true => Bound::Unbounded,
+            false => Bound::Excluded(self.end.clone()),
+        };
+        let iter = match store.get_range(&self.table, start, end) {
+            Ok(iter) => iter,
+            Err(e) => return e.into(),
+        };
+
+        // 多取一条，用来判断是否还有下一页
+        let mut pairs: Vec<_> = iter
+            .take_while(|v| v.key.starts_with(&self.prefix))
+            .take(limit + 1)
+            .collect();
+        let cursor = match pairs.len() > limit {
+            true => {
+                pairs.truncate(limit);
+                pairs
+                    .last()
+                    .map(|v| encode_cursor(&self, &v.key))
+                    .unwrap_or_default()
+            }
+            false => String::new(),
+        };
+        CommandResponse {
+            cursor,
+            ..pairs.into()
+        }
+    }
+}
+
+/// 把扫描的 table、范围和这一页的最后一个 key 编码成返回给客户端的游标
+fn encode_cursor(scan: &Hscan, key: &str) -> String {
+    let cursor = HscanCursor {
+        table: scan.table.clone(),
+        prefix: scan.prefix.clone(),
+        start: scan.start.clone(),
+        end: scan.end.clone(),
+        key: key.to_owned(),
+    };
+    URL_SAFE_NO_PAD.encode(cursor.encode_to_vec())
+}
+
+/// 解码客户端传回的游标，返回上一页的最后一个 key，游标不属于这次扫描时返回 InvalidCommand
+fn decode_cursor(scan: &Hscan) -> Result<Option<String>, KvError> {
+    if scan.cursor.is_empty() {
+        return Ok(None);
+    }
+    let invalid = || KvError::InvalidCommand(format!("invalid cursor {}", scan.cursor));
+    let data = URL_SAFE_NO_PAD
+        .decode(&scan.cursor)
+        .map_err(|_| invalid())?;
+    let cursor = HscanCursor::decode(data.as_slice()).map_err(|_| invalid())?;
+    let bounds = (&cursor.table, &cursor.prefix, &cursor.start, &cursor.end);
+    if bounds != (&scan.table, &scan.prefix, &scan.start, &scan.end) {
+        return Err(invalid());
+    }
+    Ok(Some(cursor.key))
+}
+
+impl CommandService for ListTables {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.list_tables() {
+            Ok(tables) => tables
+                .into_iter()
+                .filter(|table| !is_reserved_table(table))
+                .map(Value::from)
+                .collect::<Vec<_>>()
+                .into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for DropTable {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.drop_table(&self.table) {
+            Ok(true) => Value::from(true).into(),
+            Ok(false) => KvError::NotFound(format!("table {}", self.table)).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for TableLen {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.table_len(&self.table) {
+            Ok(len) => Value::from(len as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for RenameTable {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.rename_table(&self.from, &self.to) {
+            Ok(true) => Value::from(true).into(),
+            Ok(false) => KvError::NotFound(format!("table {}", self.from)).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Backup {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match backup(store, &self.path) {
+            Ok(count) => Value::from(count as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Restore {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match restore(store, &self.path) {
+            Ok(count) => Value::from(count as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Lpush {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        if self.values.is_empty() {
+            return KvError::InvalidCommand("Lpush has no values".into()).into();
+        }
+        match store.lpush(&self.table, &self.key, self.values) {
+            Ok(len) => Value::from(len as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Rpush {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        if self.values.is_empty() {
+            return KvError::InvalidCommand("Rpush has no values".into()).into();
+        }
+        match store.rpush(&self.table, &self.key, self.values) {
+            Ok(len) => Value::from(len as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Lpop {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let count = self.count.max(1) as usize;
+        match store.lpop(&self.table, &self.key, count) {
+            Ok(v) if v.is_empty() => {
+                KvError::NotFound(format!("table {}, key {}", self.table, self.key)).into()
+            }
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Rpop {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let count = self.count.max(1) as usize;
+        match store.rpop(&self.table, &self.key, count) {
+            Ok(v) if v.is_empty() => {
+                KvError::NotFound(format!("table {}, key {}", self.table, self.key)).into()
+            }
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Lrange {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.lrange(&self.table, &self.key, self.start, self.stop) {
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Sadd {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        if self.values.is_empty() {
+            return KvError::InvalidCommand("Sadd has no values".into()).into();
+        }
+        match store.sadd(&self.table, &self.key, self.values) {
+            Ok(added) => Value::from(added as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Srem {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.srem(&self.table, &self.key, self.values) {
+            Ok(removed) => Value::from(removed as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Smembers {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.smembers(&self.table, &self.key) {
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Sismember {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let value = self.value.unwrap_or_default();
+        match store.sismember(&self.table, &self.key, &value) {
+            Ok(v) => Value::from(v).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Zadd {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        if self.members.is_empty() {
+            return KvError::InvalidCommand("Zadd has no members".into()).into();
+        }
+        match store.zadd(&self.table, &self.key, self.members) {
+            Ok(added) => Value::from(added as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Zrem {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.zrem(&self.table, &self.key, self.members) {
+            Ok(removed) => Value::from(removed as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Zscore {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.zscore(&self.table, &self.key, &self.member) {
+            Ok(Some(score)) => Value::from(score).into(),
+            Ok(None) => KvError::NotFound(format!(
+                "table {}, key {}, member {}",
+                self.table, self.key, self.member
+            ))
+            .into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Zrank {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.zrank(&self.table, &self.key, &self.member, self.rev) {
+            Ok(Some(rank)) => Value::from(rank as i64).into(),
+            Ok(None) => KvError::NotFound(format!(
+                "table {}, key {}, member {}",
+                self.table, self.key, self.member
+            ))
+            .into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Zrange {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.zrange(&self.table, &self.key, self.start, self.stop, self.rev) {
+            Ok(members) => members
+                .into_iter()
+                .map(Kvpair::from)
+                .collect::<Vec<_>>()
+                .into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Zrangebyscore {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.zrange_by_score(&self.table, &self.key, self.min, self.max) {
+            Ok(members) => members
+                .into_iter()
+                .map(Kvpair::from)
+                .collect::<Vec<_>>()
+                .into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Txn {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        for (i, cmd) in self.commands.iter().enumerate() {
+            match cmd.request_data {
+                Some(RequestData::Txn(_))
+                | Some(RequestData::ListTables(_))
+                | Some(RequestData::DropTable(_))
+                | Some(RequestData::TableLen(_))
+                | Some(RequestData::RenameTable(_))
+                | Some(RequestData::Backup(_))
+                | Some(RequestData::Restore(_))
+                | Some(RequestData::Subscribe(_))
+                | Some(RequestData::Unsubscribe(_))
+                | Some(RequestData::Publish(_))
+                | Some(RequestData::Psubscribe(_))
+                | Some(RequestData::Punsubscribe(_))
+                | Some(RequestData::Ack(_))
+                | Some(RequestData::Nack(_))
+                | Some(RequestData::PubsubTopics(_))
+                | Some(RequestData::PubsubNumsub(_))
+                | Some(RequestData::PubsubSubscription(_))
+                | None => {
+                    return KvError::InvalidCommand(format!(
+                        "Command {} is not allowed in transaction",
+                        i
+                    ))
+                    .into()
+                }
+                _ => {}
+            }
+        }
+
+        for _ in 0..TXN_MAX_RETRIES {
+            let txn = TxnStore::new(store);
+            let mut responses = Vec::with_capacity(self.commands.len());
+            for (i, cmd) in self.commands.iter().enumerate() {
+                let res = dispatch_command(cmd.clone(), &txn);
+                // 读不到 key 不算失败，其他错误都会让整个事务回滚
+                if res.status != StatusCode::OK.as_u16() as u32
+                    && res.status != StatusCode::NOT_FOUND.as_u16() as u32
+                {
+                    return CommandResponse {
+                        message: format!("Transaction aborted at command {}: {}", i, res.message),
+                        ..res
+                    };
+                }
+                responses.push(res);
+            }
+            match txn.commit() {
+                Ok(true) => return responses.into(),
+                Ok(false) => continue,
+                Err(e) => return e.into(),
+            }
+        }
+        KvError::Conflict(format!(
+            "Transaction failed after {} retries",
+            TXN_MAX_RETRIES
+        ))
+        .into()
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{dispatch, CommandRequest, Kvpair, MemTable};
+    use std::thread;
+
+    use tempfile::tempdir;
+
+    use crate::{dispatch, CommandRequest, Kvpair, MemTable, ScoredMember, SledDb};
 
     use super::*;
 
@@ -96,6 +653,590 @@ mod tests {
         assert_res_ok(res, &[], pairs);
     }
 
+    #[test]
+    fn memtable_hmget_should_work() {
+        test_hmget(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_hmget_should_work() {
+        test_hmget(SledDb::new(tempdir().unwrap()));
+    }
+
+    #[test]
+    fn memtable_hmset_should_work() {
+        test_hmset(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_hmset_should_work() {
+        test_hmset(SledDb::new(tempdir().unwrap()));
+    }
+
+    #[test]
+    fn memtable_hdel_should_work() {
+        test_hdel(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_hdel_should_work() {
+        test_hdel(SledDb::new(tempdir().unwrap()));
+    }
+
+    #[test]
+    fn memtable_hmdel_should_work() {
+        test_hmdel(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_hmdel_should_work() {
+        test_hmdel(SledDb::new(tempdir().unwrap()));
+    }
+
+    #[test]
+    fn memtable_hexists_should_work() {
+        test_hexists(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_hexists_should_work() {
+        test_hexists(SledDb::new(tempdir().unwrap()));
+    }
+
+    #[test]
+    fn memtable_hmexists_should_work() {
+        test_hmexists(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_hmexists_should_work() {
+        test_hmexists(SledDb::new(tempdir().unwrap()));
+    }
+
+    fn test_hmget(store: impl Storage) {
+        set_key_pairs("user", vec![("u1", "Tyr"), ("u2", "Lindsey")], &store);
+        let cmd = CommandRequest::new_hmget("user", vec!["u1".into(), "u3".into(), "u2".into()]);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(
+            res,
+            &["Tyr".into(), Value::default(), "Lindsey".into()],
+            &[],
+        );
+    }
+
+    fn test_hmset(store: impl Storage) {
+        set_key_pairs("t1", vec![("u1", "world")], &store);
+        let pairs = vec![Kvpair::new("u1", 10.into()), Kvpair::new("u2", 8.into())];
+        let cmd = CommandRequest::new_hmset("t1", pairs);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &["world".into(), Value::default()], &[]);
+
+        let cmd = CommandRequest::new_hmget("t1", vec!["u1".into(), "u2".into()]);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[10.into(), 8.into()], &[]);
+    }
+
+    fn test_hdel(store: impl Storage) {
+        set_key_pairs("score", vec![("u1", 10)], &store);
+        let cmd = CommandRequest::new_hdel("score", "u2");
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[Value::default()], &[]);
+
+        let cmd = CommandRequest::new_hdel("score", "u1");
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[10.into()], &[]);
+
+        let cmd = CommandRequest::new_hget("score", "u1");
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 404, "Not Found");
+    }
+
+    fn test_hmdel(store: impl Storage) {
+        set_key_pairs("score", vec![("u1", 10), ("u2", 8)], &store);
+        let cmd = CommandRequest::new_hmdel("score", vec!["u1".into(), "u3".into(), "u2".into()]);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[10.into(), Value::default(), 8.into()], &[]);
+
+        let cmd = CommandRequest::new_hmexists("score", vec!["u1".into(), "u2".into()]);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[false.into(), false.into()], &[]);
+    }
+
+    fn test_hexists(store: impl Storage) {
+        set_key_pairs("score", vec![("u1", 10)], &store);
+        let cmd = CommandRequest::new_hexists("score", "u1");
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[true.into()], &[]);
+
+        let cmd = CommandRequest::new_hexists("score", "u2");
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[false.into()], &[]);
+    }
+
+    fn test_hmexists(store: impl Storage) {
+        set_key_pairs("user", vec![("u1", "Tyr"), ("u2", "Lindsey")], &store);
+        let cmd = CommandRequest::new_hmexists("user", vec!["u1".into(), "u3".into(), "u2".into()]);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[true.into(), false.into(), true.into()], &[]);
+    }
+
+    #[test]
+    fn memtable_hsetex_should_expire() {
+        test_hsetex(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_hsetex_should_expire() {
+        test_hsetex(SledDb::new(tempdir().unwrap()));
+    }
+
+    #[test]
+    fn memtable_hexpire_httl_hpersist_should_work() {
+        test_hexpire_httl_hpersist(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_hexpire_httl_hpersist_should_work() {
+        test_hexpire_httl_hpersist(SledDb::new(tempdir().unwrap()));
+    }
+
+    fn test_hsetex(store: impl Storage) {
+        let cmd = CommandRequest::new_hsetex("session", "s1", "token".into(), 50);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[Value::default()], &[]);
+
+        let cmd = CommandRequest::new_hget("session", "s1");
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &["token".into()], &[]);
+
+        thread::sleep(Duration::from_millis(60));
+        let cmd = CommandRequest::new_hget("session", "s1");
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 404, "Not Found");
+
+        let cmd = CommandRequest::new_hgetall("session");
+        let res = dispatch(cmd, &store);
+        assert!(res.pairs.is_empty());
+
+        let mut cmd = CommandRequest::new_hsetex("session", "s1", "token".into(), 50);
+        if let Some(RequestData::Hsetex(v)) = &mut cmd.request_data {
+            v.pair = None;
+        }
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 400, "Hsetex has no pair");
+    }
+
+    fn test_hexpire_httl_hpersist(store: impl Storage) {
+        set_key_pairs("session", vec![("s1", "token")], &store);
+        let cmd = CommandRequest::new_httl("session", "s1");
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[(-1).into()], &[]);
+
+        let cmd = CommandRequest::new_hexpire("session", "s1", 10_000);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[true.into()], &[]);
+
+        let cmd = CommandRequest::new_httl("session", "s1");
+        let res = dispatch(cmd, &store);
+        let ttl: i64 = (&res).try_into().unwrap();
+        assert!(ttl > 0 && ttl <= 10_000);
+
+        let cmd = CommandRequest::new_hpersist("session", "s1");
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[true.into()], &[]);
+
+        let cmd = CommandRequest::new_httl("session", "s1");
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[(-1).into()], &[]);
+
+        let cmd = CommandRequest::new_hexpire("session", "s2", 10_000);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[false.into()], &[]);
+
+        let cmd = CommandRequest::new_httl("session", "s2");
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 404, "Not Found");
+    }
+
+    #[test]
+    fn memtable_hincrby_should_work() {
+        test_hincrby(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_hincrby_should_work() {
+        test_hincrby(SledDb::new(tempdir().unwrap()));
+    }
+
+    fn test_hincrby(store: impl Storage) {
+        let cmd = CommandRequest::new_hincrby("score", "u1", 10);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[10.into()], &[]);
+
+        let cmd = CommandRequest::new_hincrby("score", "u1", -3);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[7.into()], &[]);
+
+        let cmd = CommandRequest::new_hincrbyfloat("score", "u1", 0.5);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[7.5.into()], &[]);
+
+        set_key_pairs("user", vec![("u1", "Tyr")], &store);
+        let cmd = CommandRequest::new_hincrby("user", "u1", 1);
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 500, "Cannot convert value");
+    }
+
+    #[test]
+    fn memtable_hsetnx_hcas_should_work() {
+        test_hsetnx_hcas(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_hsetnx_hcas_should_work() {
+        test_hsetnx_hcas(SledDb::new(tempdir().unwrap()));
+    }
+
+    fn test_hsetnx_hcas(store: impl Storage) {
+        let cmd = CommandRequest::new_hsetnx("config", "c1", "v1".into());
+        let res = dispatch(cmd.clone(), &store);
+        assert_res_ok(res, &[true.into()], &[]);
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 412, "Precondition failed");
+
+        let cmd = CommandRequest::new_hcas("config", "c1", Some("v0".into()), "v2".into());
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 412, "Precondition failed");
+
+        let cmd = CommandRequest::new_hcas("config", "c1", Some("v1".into()), "v2".into());
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[true.into()], &[]);
+
+        let cmd = CommandRequest::new_hget("config", "c1");
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &["v2".into()], &[]);
+    }
+
+    #[test]
+    fn memtable_txn_should_work() {
+        test_txn(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_txn_should_work() {
+        test_txn(SledDb::new(tempdir().unwrap()));
+    }
+
+    #[test]
+    fn memtable_txn_should_rollback_on_error() {
+        test_txn_rollback(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_txn_should_rollback_on_error() {
+        test_txn_rollback(SledDb::new(tempdir().unwrap()));
+    }
+
+    #[test]
+    fn memtable_hscan_should_work() {
+        test_hscan(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_hscan_should_work() {
+        test_hscan(SledDb::new(tempdir().unwrap()));
+    }
+
+    #[test]
+    fn memtable_table_commands_should_work() {
+        test_table_commands(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_table_commands_should_work() {
+        test_table_commands(SledDb::new(tempdir().unwrap()));
+    }
+
+    #[test]
+    fn txn_with_pubsub_command_should_be_rejected() {
+        let store = MemTable::new();
+        let cmd = CommandRequest::new_txn(vec![
+            CommandRequest::new_hset("t1", "k1", "v1".into()),
+            CommandRequest::new_publish("lobby", vec!["hello".into()]),
+        ]);
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 400, "not allowed in transaction");
+        assert!(!store.contains("t1", "k1").unwrap());
+    }
+
+    fn test_txn(store: impl Storage) {
+        set_key_pairs("account", vec![("alice", 100), ("bob", 20)], &store);
+        let cmd = CommandRequest::new_txn(vec![
+            CommandRequest::new_hincrby("account", "alice", -30),
+            CommandRequest::new_hincrby("account", "bob", 30),
+            CommandRequest::new_hset("audit", "t1", "alice->bob".into()),
+            CommandRequest::new_hget("audit", "t1"),
+            CommandRequest::new_hget("audit", "t2"),
+        ]);
+        let res = dispatch(cmd, &store);
+        assert_eq!(res.status, 200);
+        let values: Vec<_> = res.responses.iter().map(|r| r.values.clone()).collect();
+        assert_eq!(
+            values,
+            vec![
+                vec![70.into()],
+                vec![50.into()],
+                vec![Value::default()],
+                vec!["alice->bob".into()],
+                vec![],
+            ]
+        );
+        assert_eq!(res.responses[4].status, 404);
+
+        let cmd = CommandRequest::new_hmget("account", vec!["alice".into(), "bob".into()]);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[70.into(), 50.into()], &[]);
+    }
+
+    fn test_txn_rollback(store: impl Storage) {
+        set_key_pairs("config", vec![("version", 1)], &store);
+        let cmd = CommandRequest::new_txn(vec![
+            CommandRequest::new_hset("config", "name", "kv".into()),
+            CommandRequest::new_hdel("config", "version"),
+            CommandRequest::new_hsetnx("config", "name", "kv2".into()),
+        ]);
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 412, "Transaction aborted at command 2");
+
+        assert!(!store.contains("config", "name").unwrap());
+        assert_eq!(store.get("config", "version").unwrap(), Some(1.into()));
+    }
+
+    fn test_hscan(store: impl Storage) {
+        let keys = ["user:1", "user:2", "user:3", "user:4", "user:5", "zone:1"];
+        set_key_pairs("t1", keys.iter().map(|k| (*k, *k)).collect(), &store);
+        let scan_keys = |res: &CommandResponse| -> Vec<String> {
+            res.pairs.iter().map(|v| v.key.clone()).collect()
+        };
+
+        // 按前缀分页扫描
+        let mut cursor = String::new();
+        let mut pages = vec![];
+        loop {
+            let cmd = CommandRequest::new_hscan("t1", "user:", 2, cursor);
+            let res = dispatch(cmd, &store);
+            assert_eq!(res.status, 200);
+            pages.push(scan_keys(&res));
+            if res.cursor.is_empty() {
+                break;
+            }
+            cursor = res.cursor;
+        }
+        assert_eq!(
+            pages,
+            vec![
+                vec!["user:1", "user:2"],
+                vec!["user:3", "user:4"],
+                vec!["user:5"]
+            ]
+        );
+
+        // 按范围扫描，end 不包含在内
+        let cmd = CommandRequest::new_hscan_range("t1", "user:2", "user:4", 0, "");
+        let res = dispatch(cmd, &store);
+        assert_eq!(scan_keys(&res), ["user:2", "user:3"]);
+        assert!(res.cursor.is_empty());
+
+        let cmd = CommandRequest::new_hscan_range("t1", "user:5", "", 0, "");
+        let res = dispatch(cmd, &store);
+        assert_eq!(scan_keys(&res), ["user:5", "zone:1"]);
+
+        // 游标只能用于同一个 table 和范围，不能直接传 key
+        let cmd = CommandRequest::new_hscan("t1", "user:", 2, "");
+        let cursor = dispatch(cmd, &store).cursor;
+        let cmd = CommandRequest::new_hscan_range("t1", "user:", "", 2, cursor.clone());
+        assert_res_error(dispatch(cmd, &store), 400, "invalid cursor");
+        let cmd = CommandRequest::new_hscan("t2", "user:", 2, cursor);
+        assert_res_error(dispatch(cmd, &store), 400, "invalid cursor");
+        let cmd = CommandRequest::new_hscan("t1", "user:", 2, "user:3");
+        assert_res_error(dispatch(cmd, &store), 400, "invalid cursor");
+
+        let cmd = CommandRequest::new_hscan("t2", "", 0, "");
+        let res = dispatch(cmd, &store);
+        assert_eq!(res.status, 200);
+        assert!(res.pairs.is_empty());
+    }
+
+    fn test_table_commands(store: impl Storage) {
+        set_key_pairs("t1", vec![("k1", "v1"), ("k2", "v2")], &store);
+        set_key_pairs("t2", vec![("k1", "v1")], &store);
+
+        let res = dispatch(CommandRequest::new_list_tables(), &store);
+        assert_res_ok(res, &["t1".into(), "t2".into()], &[]);
+
+        let res = dispatch(CommandRequest::new_table_len("t1"), &store);
+        assert_res_ok(res, &[2.into()], &[]);
+
+        let res = dispatch(CommandRequest::new_rename_table("t1", "t2"), &store);
+        assert_res_error(res, 412, "already exists");
+
+        let res = dispatch(CommandRequest::new_drop_table("t2"), &store);
+        assert_res_ok(res, &[true.into()], &[]);
+        let res = dispatch(CommandRequest::new_drop_table("t2"), &store);
+        assert_res_error(res, 404, "Not Found");
+
+        let res = dispatch(CommandRequest::new_rename_table("t1", "t2"), &store);
+        assert_res_ok(res, &[true.into()], &[]);
+        let res = dispatch(CommandRequest::new_rename_table("t1", "t3"), &store);
+        assert_res_error(res, 404, "Not Found");
+
+        let res = dispatch(CommandRequest::new_list_tables(), &store);
+        assert_res_ok(res, &["t2".into()], &[]);
+
+        let cmd = CommandRequest::new_txn(vec![CommandRequest::new_drop_table("t2")]);
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 400, "not allowed in transaction");
+    }
+
+    #[test]
+    fn backup_restore_should_work() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("kv.backup");
+        let path = path.to_str().unwrap();
+        let store = MemTable::new();
+        set_key_pairs("t1", vec![("k1", "v1"), ("k2", "v2")], &store);
+
+        let res = dispatch(CommandRequest::new_backup(path), &store);
+        assert_res_ok(res, &[2.into()], &[]);
+
+        let target = SledDb::new(dir.path().join("sled"));
+        let res = dispatch(CommandRequest::new_restore(path), &target);
+        assert_res_ok(res, &[2.into()], &[]);
+        let res = dispatch(CommandRequest::new_hget("t1", "k2"), &target);
+        assert_res_ok(res, &["v2".into()], &[]);
+
+        let res = dispatch(CommandRequest::new_restore("/not/exist"), &target);
+        assert_res_error(res, 500, "I/O error");
+
+        let cmd = CommandRequest::new_txn(vec![CommandRequest::new_backup(path)]);
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 400, "not allowed in transaction");
+    }
+
+    #[test]
+    fn list_commands_should_work() {
+        let store = MemTable::new();
+        let cmd = CommandRequest::new_rpush("q", "jobs", vec!["j1".into(), "j2".into()]);
+        assert_res_ok(dispatch(cmd, &store), &[2.into()], &[]);
+        let cmd = CommandRequest::new_lpush("q", "jobs", vec!["j0".into()]);
+        assert_res_ok(dispatch(cmd, &store), &[3.into()], &[]);
+        let cmd = CommandRequest::new_lrange("q", "jobs", 0, -1);
+        let all = ["j0".into(), "j1".into(), "j2".into()];
+        assert_res_ok(dispatch(cmd, &store), &all, &[]);
+
+        // 在事务中弹出任务并记录到另一个列表
+        let cmd = CommandRequest::new_txn(vec![
+            CommandRequest::new_lpop("q", "jobs", 0),
+            CommandRequest::new_rpush("q", "running", vec!["j0".into()]),
+        ]);
+        let res = dispatch(cmd, &store);
+        assert_eq!(res.responses[0].values, &all[..1]);
+        let cmd = CommandRequest::new_rpop("q", "jobs", 5);
+        assert_res_ok(dispatch(cmd, &store), &["j2".into(), "j1".into()], &[]);
+        let cmd = CommandRequest::new_lpop("q", "jobs", 1);
+        assert_res_error(dispatch(cmd, &store), 404, "Not Found");
+        let cmd = CommandRequest::new_lrange("q", "running", 0, -1);
+        assert_res_ok(dispatch(cmd, &store), &all[..1], &[]);
+
+        let cmd = CommandRequest::new_lpush("q", "jobs", vec![]);
+        assert_res_error(dispatch(cmd, &store), 400, "no values");
+        dispatch(CommandRequest::new_hset("q", "k1", "v1".into()), &store);
+        let cmd = CommandRequest::new_rpush("q", "k1", vec!["j0".into()]);
+        assert_res_error(dispatch(cmd, &store), 500, "Cannot convert");
+    }
+
+    #[test]
+    fn set_commands_should_work() {
+        let store = MemTable::new();
+        let cmd = CommandRequest::new_sadd("t1", "tags", vec!["a".into(), "b".into(), "a".into()]);
+        assert_res_ok(dispatch(cmd, &store), &[2.into()], &[]);
+        let cmd = CommandRequest::new_sismember("t1", "tags", "a".into());
+        assert_res_ok(dispatch(cmd, &store), &[true.into()], &[]);
+        let cmd = CommandRequest::new_srem("t1", "tags", vec!["a".into()]);
+        assert_res_ok(dispatch(cmd, &store), &[1.into()], &[]);
+        let cmd = CommandRequest::new_sismember("t1", "tags", "a".into());
+        assert_res_ok(dispatch(cmd, &store), &[false.into()], &[]);
+        let cmd = CommandRequest::new_smembers("t1", "tags");
+        assert_res_ok(dispatch(cmd, &store), &["b".into()], &[]);
+        let cmd = CommandRequest::new_smembers("t1", "none");
+        assert_res_ok(dispatch(cmd, &store), &[], &[]);
+    }
+
+    #[test]
+    fn memtable_zset_commands_should_work() {
+        test_zset_commands(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_zset_commands_should_work() {
+        test_zset_commands(SledDb::new(tempdir().unwrap()));
+    }
+
+    fn test_zset_commands(store: impl Storage) {
+        let members = vec![
+            ScoredMember::new("u1", 10.0),
+            ScoredMember::new("u2", 8.0),
+            ScoredMember::new("u3", 11.0),
+        ];
+        let cmd = CommandRequest::new_zadd("score", "board", members);
+        assert_res_ok(dispatch(cmd, &store), &[3.into()], &[]);
+        let cmd = CommandRequest::new_zadd("score", "board", vec![ScoredMember::new("u4", 6.0)]);
+        assert_res_ok(dispatch(cmd, &store), &[1.into()], &[]);
+
+        let cmd = CommandRequest::new_zscore("score", "board", "u3");
+        assert_res_ok(dispatch(cmd, &store), &[11.0.into()], &[]);
+        let cmd = CommandRequest::new_zrank("score", "board", "u1", true);
+        assert_res_ok(dispatch(cmd, &store), &[1.into()], &[]);
+        let cmd = CommandRequest::new_zrank("score", "board", "u9", false);
+        assert_res_error(dispatch(cmd, &store), 404, "Not Found");
+
+        let cmd = CommandRequest::new_zrange("score", "board", 0, 1, true);
+        let res = dispatch(cmd, &store);
+        let pairs = [
+            Kvpair::new("u3", 11.0.into()),
+            Kvpair::new("u1", 10.0.into()),
+        ];
+        assert_eq!(res.pairs, pairs);
+        let cmd = CommandRequest::new_zrangebyscore("score", "board", 7.0, 10.0);
+        let res = dispatch(cmd, &store);
+        let pairs = [
+            Kvpair::new("u2", 8.0.into()),
+            Kvpair::new("u1", 10.0.into()),
+        ];
+        assert_eq!(res.pairs, pairs);
+
+        let cmd = CommandRequest::new_zrem("score", "board", vec!["u1".into(), "u9".into()]);
+        assert_res_ok(dispatch(cmd, &store), &[1.into()], &[]);
+        let cmd = CommandRequest::new_zrange("score", "board", -1, -1, false);
+        let res = dispatch(cmd, &store);
+        assert_eq!(res.pairs, [Kvpair::new("u3", 11.0.into())]);
+
+        let cmd = CommandRequest::new_zadd("score", "board", vec![]);
+        assert_res_error(dispatch(cmd, &store), 400, "no members");
+        let cmd =
+            CommandRequest::new_zadd("score", "board", vec![ScoredMember::new("u5", f64::NAN)]);
+        assert_res_error(dispatch(cmd, &store), 400, "NaN");
+    }
+
+    fn set_key_pairs<T: Into<Value>>(table: &str, pairs: Vec<(&str, T)>, store: &impl Storage) {
+        pairs
+            .into_iter()
+            .map(|(k, v)| CommandRequest::new_hset(table, k, v.into()))
+            .for_each(|cmd| {
+                dispatch(cmd, store);
+            });
+    }
+
     // fn dispath(cmd: CommandRequest, store: &impl Storage) -> CommandResponse {
     //     match cmd.request_data.unwrap() {
     //         RequestData::Hget(v) => v.execute(store),
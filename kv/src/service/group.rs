@@ -0,0 +1,225 @@
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::CommandResponse;
+
+/// 死信 topic 的前缀，消费组中投递次数达到上限的消息发布到 `__dlq:<topic>:<group>`
+pub const DEAD_LETTER_TOPIC_PREFIX: &str = "__dlq:";
+
+/// 返回消费组的死信 topic
+pub fn dead_letter_topic(topic: &str, group: &str) -> String {
+    format!("{}{}:{}", DEAD_LETTER_TOPIC_PREFIX, topic, group)
+}
+
+/// 消费组：topic 的每条消息只轮流投递给组中的一个成员，成员确认后才算处理完成
+#[derive(Debug, Default)]
+pub(super) struct ConsumerGroup {
+    members: Vec<u32>,
+    /// 下一条消息投递给 members 中的哪个成员
+    next: usize,
+    /// 已经投递还没有确认的消息，按 offset 排序
+    pending: BTreeMap<u64, Pending>,
+    /// 所有成员都离开的时间，有成员时为 None
+    empty_since: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct Pending {
+    msg: Arc<CommandResponse>,
+    /// 负责处理这条消息的成员，成员离开或者发布时组中没有成员时为 None，等待重新投递
+    member: Option<u32>,
+    /// 超过这个时间还没有确认就重新投递
+    deadline: Instant,
+    /// 已经投递的次数
+    deliveries: u32,
+}
+
+/// 重新投递一条消息的结果
+#[derive(Debug)]
+pub(super) enum Retry {
+    /// 投递给 subscription id 对应的成员
+    Deliver(u32, Arc<CommandResponse>),
+    /// 投递次数达到上限，需要放进死信 topic
+    Dead(Arc<CommandResponse>),
+}
+
+impl ConsumerGroup {
+    pub fn join(&mut self, id: u32) {
+        self.members.push(id);
+        self.empty_since = None;
+    }
+
+    /// 成员离开，它还没有确认的消息会被重新投递给其他成员
+    pub fn leave(&mut self, id: u32) {
+        self.members.retain(|v| *v != id);
+        for pending in self.pending.values_mut() {
+            if pending.member == Some(id) {
+                pending.member = None;
+            }
+        }
+        if self.members.is_empty() && self.empty_since.is_none() {
+            self.empty_since = Some(Instant::now());
+        }
+    }
+
+    /// 所有成员离开的时间超过 timeout，可以删除这个消费组
+    pub fn is_abandoned(&self, now: Instant, timeout: Duration) -> bool {
+        matches!(self.empty_since, Some(at) if now.saturating_duration_since(at) >= timeout)
+    }
+
+    /// 删除消费组时取出还没有确认的消息
+    pub fn into_pending(self) -> impl Iterator<Item = Arc<CommandResponse>> {
+        self.pending.into_values().map(|v| v.msg)
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn contains(&self, id: u32) -> bool {
+        self.members.contains(&id)
+    }
+
+    /// 把新消息投递给下一个成员，返回这个成员的 subscription id。
+    /// 组中没有成员时消息等待确认，有成员加入后重新投递
+    pub fn deliver(&mut self, msg: Arc<CommandResponse>, timeout: Duration) -> Option<u32> {
+        let member = self.next_member();
+        let pending = Pending {
+            msg,
+            member,
+            deadline: Instant::now() + timeout,
+            deliveries: member.map_or(0, |_| 1),
+        };
+        self.pending.insert(pending.msg.offset, pending);
+        member
+    }
+
+    /// 成员 id 确认消息处理完成，消息不是由这个成员负责时返回 false
+    pub fn ack(&mut self, id: u32, offset: u64) -> bool {
+        let held = self.is_held_by(id, offset);
+        if held {
+            self.pending.remove(&offset);
+        }
+        held
+    }
+
+    /// 消息是否正在由成员 id 处理
+    pub fn is_held_by(&self, id: u32, offset: u64) -> bool {
+        matches!(self.pending.get(&offset), Some(v) if v.member == Some(id))
+    }
+
+    /// 需要重新投递的消息：超时没有确认的，以及负责的成员已经离开的
+    pub fn expired(&self, now: Instant) -> Vec<u64> {
+        self.pending
+            .iter()
+            .filter(|(_, v)| v.member.is_none() || v.deadline <= now)
+            .map(|(offset, _)| *offset)
+            .collect()
+    }
+
+    /// 把消息重新投递给下一个成员，投递次数达到 max_deliveries 时把它移出消费组
+    pub fn retry(&mut self, offset: u64, timeout: Duration, max_deliveries: u32) -> Option<Retry> {
+        let pending = self.pending.get(&offset)?;
+        if pending.deliveries >= max_deliveries {
+            let pending = self.pending.remove(&offset)?;
+            return Some(Retry::Dead(pending.msg));
+        }
+        let member = self.next_member()?;
+        let pending = self.pending.get_mut(&offset)?;
+        pending.member = Some(member);
+        pending.deadline = Instant::now() + timeout;
+        pending.deliveries += 1;
+        Some(Retry::Deliver(member, pending.msg.clone()))
+    }
+
+    /// 重新投递的消息没有发送出去，撤销这次投递，等待下一次重新投递
+    pub fn cancel(&mut self, offset: u64) {
+        if let Some(pending) = self.pending.get_mut(&offset) {
+            pending.member = None;
+            pending.deliveries = pending.deliveries.saturating_sub(1);
+        }
+    }
+
+    fn next_member(&mut self) -> Option<u32> {
+        if self.members.is_empty() {
+            return None;
+        }
+        let member = self.members[self.next % self.members.len()];
+        self.next = (self.next + 1) % self.members.len();
+        Some(member)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(offset: u64) -> Arc<CommandResponse> {
+        Arc::new(CommandResponse {
+            offset,
+            ..CommandResponse::ok()
+        })
+    }
+
+    #[test]
+    fn consumer_group_should_deliver_round_robin_and_retry() {
+        let timeout = Duration::from_secs(10);
+        let mut group = ConsumerGroup::default();
+        // 没有成员时消息等待成员加入
+        assert_eq!(group.deliver(message(1), timeout), None);
+        assert_eq!(group.expired(Instant::now()), [1]);
+        group.join(1);
+        group.join(2);
+        assert!(matches!(
+            group.retry(1, timeout, 1),
+            Some(Retry::Deliver(1, _))
+        ));
+        assert!(group.ack(1, 1));
+        assert_eq!(group.deliver(message(2), timeout), Some(2));
+        assert_eq!(group.deliver(message(3), timeout), Some(1));
+        assert_eq!(group.deliver(message(4), timeout), Some(2));
+        // 只有负责这条消息的成员可以确认
+        assert!(!group.ack(2, 3));
+        assert!(group.ack(1, 3));
+        assert!(!group.ack(1, 3));
+        assert!(group.expired(Instant::now()).is_empty());
+
+        // 成员离开后，它负责的消息交给其他成员
+        group.leave(2);
+        assert_eq!(group.expired(Instant::now()), [2, 4]);
+        assert!(matches!(
+            group.retry(2, timeout, 2),
+            Some(Retry::Deliver(1, _))
+        ));
+        assert!(matches!(group.retry(2, timeout, 2), Some(Retry::Dead(_))));
+        assert!(!group.is_held_by(1, 2));
+        assert!(!group.is_held_by(1, 4));
+        assert_eq!(group.expired(Instant::now() + timeout), [4]);
+
+        // 撤销的投递不计入投递次数
+        assert!(matches!(
+            group.retry(4, timeout, 2),
+            Some(Retry::Deliver(1, _))
+        ));
+        assert!(group.is_held_by(1, 4));
+        group.cancel(4);
+        assert_eq!(group.expired(Instant::now()), [4]);
+        assert!(matches!(
+            group.retry(4, timeout, 2),
+            Some(Retry::Deliver(1, _))
+        ));
+
+        // 成员都离开超过 timeout 之后才能删除消费组，等待确认的消息交给调用者处理
+        group.leave(1);
+        assert_eq!(group.len(), 0);
+        assert!(!group.is_abandoned(Instant::now(), timeout));
+        assert!(group.is_abandoned(Instant::now() + timeout, timeout));
+        group.join(3);
+        assert!(!group.is_abandoned(Instant::now() + timeout, timeout));
+        let pending: Vec<_> = group.into_pending().map(|v| v.offset).collect();
+        assert_eq!(pending, [4]);
+    }
+}
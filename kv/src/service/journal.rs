@@ -0,0 +1,117 @@
+use std::{
+    sync::{mpsc, Arc, Mutex, Weak},
+    thread::{self, JoinHandle},
+};
+
+use bytes::Bytes;
+use prost::Message;
+use tracing::warn;
+
+use super::{topic::Journal, Broadcaster, ServiceInner};
+use crate::{value, CommandResponse, KvError, Storage, Value, WriteBatch, WriteOp};
+
+/// 保存 topic 消息的 table 的前缀，完整的 table 是 `__pubsub:<topic>`
+pub const PUBSUB_TABLE_PREFIX: &str = "__pubsub:";
+
+/// 把 topic 保留的消息保存在 Storage 中：每个 topic 一个 table，
+/// key 是补零到 20 位的 offset，这样 key 的顺序就是 offset 的顺序，value 是编码后的 CommandResponse。
+/// 发布消息时只把写操作交给后台线程，由它批量写入 Storage，不在 tokio 的工作线程中做磁盘 I/O
+pub struct StorageJournal {
+    tx: Option<Mutex<mpsc::Sender<WriteOp>>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl StorageJournal {
+    /// 启动写入 Storage 的后台线程，Service 释放或者 journal 释放后线程退出
+    fn new<Store: Storage + 'static>(inner: Weak<ServiceInner<Store>>) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let writer = thread::spawn(move || {
+            while let Ok(op) = rx.recv() {
+                // 一次取出所有积压的写操作，合并成一个 batch
+                let mut batch = WriteBatch::default();
+                batch.writes.push(op);
+                batch.writes.extend(rx.try_iter());
+                // Storage 的过期回调会持有 Broadcaster，这里用 Weak 避免循环引用
+                let Some(inner) = inner.upgrade() else {
+                    break;
+                };
+                if let Err(e) = inner.store.commit(batch) {
+                    warn!("Failed to save topic messages: {:?}", e);
+                }
+            }
+        });
+        Self {
+            tx: Some(Mutex::new(tx)),
+            writer: Some(writer),
+        }
+    }
+
+    fn send(&self, op: WriteOp) {
+        if let Some(tx) = &self.tx {
+            let _ = tx.lock().unwrap().send(op);
+        }
+    }
+}
+
+impl Drop for StorageJournal {
+    /// 等待积压的消息写完，这样 Service 释放后 Storage 也随之关闭
+    fn drop(&mut self) {
+        self.tx.take();
+        if let Some(writer) = self.writer.take() {
+            // 后台线程释放 Service 时也会释放 journal，这时不能等待自己
+            if writer.thread().id() != thread::current().id() {
+                let _ = writer.join();
+            }
+        }
+    }
+}
+
+impl Journal for StorageJournal {
+    fn append(&self, topic: &str, msg: &CommandResponse) {
+        let value: Value = Bytes::from(msg.encode_to_vec()).into();
+        self.send(WriteOp::Set {
+            table: journal_table(topic),
+            key: offset_key(msg.offset),
+            value,
+            ttl: None,
+        });
+    }
+
+    fn remove(&self, topic: &str, offset: u64) {
+        self.send(WriteOp::Del {
+            table: journal_table(topic),
+            key: offset_key(offset),
+        });
+    }
+}
+
+fn journal_table(topic: &str) -> String {
+    format!("{}{}", PUBSUB_TABLE_PREFIX, topic)
+}
+
+fn offset_key(offset: u64) -> String {
+    format!("{:020}", offset)
+}
+
+/// 从 Storage 中恢复所有 topic 保存的消息，之后 broadcaster 保留的消息也会保存到 Storage 中
+pub(super) fn attach<Store: Storage + 'static>(
+    inner: &Arc<ServiceInner<Store>>,
+    broadcaster: &Broadcaster,
+) -> Result<(), KvError> {
+    broadcaster.set_journal(Arc::new(StorageJournal::new(Arc::downgrade(inner))));
+    for table in inner.store.list_tables()? {
+        let Some(topic) = table.strip_prefix(PUBSUB_TABLE_PREFIX) else {
+            continue;
+        };
+        let messages = inner
+            .store
+            .get_iter(&table)?
+            .filter_map(|pair| match pair.value?.value? {
+                value::Value::Binary(data) => CommandResponse::decode(data).ok(),
+                _ => None,
+            })
+            .collect();
+        broadcaster.restore(topic, messages);
+    }
+    Ok(())
+}
@@ -0,0 +1,208 @@
+use std::{collections::HashSet, sync::Arc};
+
+use http::StatusCode;
+
+use crate::{
+    command_request::RequestData, Broadcaster, CommandRequest, CommandResponse, KeyspaceEvent,
+    Value,
+};
+
+/// keyspace 通知的 topic 前缀，完整的 topic 是 `__keyspace:<table>`，客户端不能往这些 topic 发布消息
+pub const KEYSPACE_TOPIC_PREFIX: &str = "__keyspace:";
+
+/// 返回 table 的 keyspace 通知 topic
+pub fn keyspace_topic(table: &str) -> String {
+    format!("{}{}", KEYSPACE_TOPIC_PREFIX, table)
+}
+
+/// 记录哪些 table 需要发布 keyspace 通知，并根据执行的命令生成变更事件
+#[derive(Debug, Default)]
+pub struct Keyspace {
+    all: bool,
+    tables: HashSet<String>,
+}
+
+impl Keyspace {
+    /// tables 中的 "*" 表示所有 table
+    pub fn new(tables: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        let tables: HashSet<String> = tables.into_iter().map(Into::into).collect();
+        Self {
+            all: tables.contains("*"),
+            tables,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.all || !self.tables.is_empty()
+    }
+
+    /// table 是否需要发布 keyspace 通知
+    pub fn watches_table(&self, table: &str) -> bool {
+        self.all || self.tables.contains(table)
+    }
+
+    /// 命令执行后是否需要生成变更事件，只读命令不需要
+    pub fn watches(&self, cmd: &CommandRequest) -> bool {
+        self.is_enabled()
+            && matches!(
+                cmd.request_data,
+                Some(
+                    RequestData::Hset(_)
+                        | RequestData::Hmset(_)
+                        | RequestData::Hdel(_)
+                        | RequestData::Hmdel(_)
+                        | RequestData::Hsetex(_)
+                        | RequestData::Hexpire(_)
+                        | RequestData::Hpersist(_)
+                        | RequestData::Hincrby(_)
+                        | RequestData::Hincrbyfloat(_)
+                        | RequestData::Hsetnx(_)
+                        | RequestData::Hcas(_)
+                        | RequestData::Txn(_)
+                        | RequestData::Lpush(_)
+                        | RequestData::Rpush(_)
+                        | RequestData::Lpop(_)
+                        | RequestData::Rpop(_)
+                        | RequestData::Sadd(_)
+                        | RequestData::Srem(_)
+                        | RequestData::Zadd(_)
+                        | RequestData::Zrem(_)
+                )
+            )
+    }
+
+    /// 根据执行的命令和它的结果生成变更事件，失败或者没有修改数据的命令不生成事件
+    pub fn events(&self, cmd: &CommandRequest, res: &CommandResponse) -> Vec<KeyspaceEvent> {
+        let mut events = vec![];
+        if let Some(data) = &cmd.request_data {
+            self.collect(data, res, &mut events);
+        }
+        events
+    }
+
+    fn collect(&self, data: &RequestData, res: &CommandResponse, events: &mut Vec<KeyspaceEvent>) {
+        if res.status != StatusCode::OK.as_u16() as u32 {
+            return;
+        }
+        // 命令返回的第 i 个 value，空的 Value 表示 key 原本不存在
+        let value = |i: usize| res.values.get(i).filter(|v| v.value.is_some()).cloned();
+        let changed = || value(0) != Some(Value::from(0));
+        let succeeded = || value(0) == Some(true.into());
+        let mut push = |table: &str, key: &str, op: &str, old, new| {
+            if self.watches_table(table) {
+                events.push(KeyspaceEvent::new(table, key, op, old, new));
+            }
+        };
+        match data {
+            RequestData::Hset(v) => {
+                if let Some(pair) = &v.pair {
+                    let new = pair.value.clone().unwrap_or_default();
+                    push(&v.table, &pair.key, "set", value(0), Some(new));
+                }
+            }
+            RequestData::Hsetex(v) => {
+                if let Some(pair) = &v.pair {
+                    let new = pair.value.clone().unwrap_or_default();
+                    push(&v.table, &pair.key, "set", value(0), Some(new));
+                }
+            }
+            RequestData::Hmset(v) => {
+                for (i, pair) in v.pairs.iter().enumerate() {
+                    let new = pair.value.clone().unwrap_or_default();
+                    push(&v.table, &pair.key, "set", value(i), Some(new));
+                }
+            }
+            RequestData::Hsetnx(v) => {
+                if let Some(pair) = &v.pair {
+                    let new = pair.value.clone().unwrap_or_default();
+                    push(&v.table, &pair.key, "set", None, Some(new));
+                }
+            }
+            RequestData::Hcas(v) => {
+                let new = v.value.clone().unwrap_or_default();
+                push(&v.table, &v.key, "set", v.expected.clone(), Some(new));
+            }
+            RequestData::Hdel(v) => {
+                if let Some(old) = value(0) {
+                    push(&v.table, &v.key, "del", Some(old), None);
+                }
+            }
+            RequestData::Hmdel(v) => {
+                for (i, key) in v.keys.iter().enumerate() {
+                    if let Some(old) = value(i) {
+                        push(&v.table, key, "del", Some(old), None);
+                    }
+                }
+            }
+            RequestData::Hexpire(v) if succeeded() => push(&v.table, &v.key, "expire", None, None),
+            RequestData::Hpersist(v) if succeeded() => {
+                push(&v.table, &v.key, "persist", None, None)
+            }
+            RequestData::Hincrby(v) => push(&v.table, &v.key, "incr", None, value(0)),
+            RequestData::Hincrbyfloat(v) => push(&v.table, &v.key, "incr", None, value(0)),
+            RequestData::Lpush(v) => push(&v.table, &v.key, "lpush", None, None),
+            RequestData::Rpush(v) => push(&v.table, &v.key, "rpush", None, None),
+            RequestData::Lpop(v) => push(&v.table, &v.key, "lpop", None, None),
+            RequestData::Rpop(v) => push(&v.table, &v.key, "rpop", None, None),
+            RequestData::Sadd(v) if changed() => push(&v.table, &v.key, "sadd", None, None),
+            RequestData::Srem(v) if changed() => push(&v.table, &v.key, "srem", None, None),
+            RequestData::Zadd(v) => push(&v.table, &v.key, "zadd", None, None),
+            RequestData::Zrem(v) if changed() => push(&v.table, &v.key, "zrem", None, None),
+            RequestData::Txn(v) => {
+                for (cmd, res) in v.commands.iter().zip(&res.responses) {
+                    if let Some(data) = &cmd.request_data {
+                        self.collect(data, res, events);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// 把事件发布到 table 的 keyspace topic 上，没有订阅者时什么都不做
+    pub fn publish(&self, broadcaster: &Broadcaster, event: KeyspaceEvent) {
+        let topic = keyspace_topic(&event.table);
+        if broadcaster.has_subscribers(&topic) {
+            broadcaster.try_publish(&topic, Arc::new(event.into()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Kvpair;
+
+    #[test]
+    fn events_should_only_contain_changes_of_watched_tables() {
+        let keyspace = Keyspace::new(["t1"]);
+        let cmd = CommandRequest::new_txn(vec![
+            CommandRequest::new_hmset(
+                "t1",
+                vec![Kvpair::new("k1", 1.into()), Kvpair::new("k2", 2.into())],
+            ),
+            CommandRequest::new_srem("t1", "s1", vec!["a".into()]),
+            CommandRequest::new_hdel("t2", "k1"),
+        ]);
+        assert!(keyspace.watches(&cmd));
+        let res = CommandResponse {
+            responses: vec![
+                vec![Value::default(), 3.into()].into(),
+                Value::from(0).into(),
+                Value::from("v1").into(),
+            ],
+            ..CommandResponse::ok()
+        };
+        let events = keyspace.events(&cmd, &res);
+        assert_eq!(
+            events,
+            [
+                KeyspaceEvent::new("t1", "k1", "set", None, Some(1.into())),
+                KeyspaceEvent::new("t1", "k2", "set", Some(3.into()), Some(2.into())),
+            ]
+        );
+        assert!(!keyspace.watches(&CommandRequest::new_hget("t1", "k1")));
+        assert!(!Keyspace::default().watches(&cmd));
+        assert!(Keyspace::new(["*"]).watches_table("t2"));
+    }
+}
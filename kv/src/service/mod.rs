@@ -1,22 +1,41 @@
 mod command_service;
+mod group;
+mod journal;
+mod keyspace;
+mod subscriber;
 mod topic;
 mod topic_service;
 
-use std::sync::Arc;
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
 
-use futures::stream;
-pub use topic::{Broadcaster, Topic};
+use futures::{stream, StreamExt};
+pub use group::{dead_letter_topic, DEAD_LETTER_TOPIC_PREFIX};
+pub use journal::{StorageJournal, PUBSUB_TABLE_PREFIX};
+pub use keyspace::{keyspace_topic, Keyspace, KEYSPACE_TOPIC_PREFIX};
+pub use topic::{Broadcaster, Journal, SubscriptionInfo, Subscriptions, Topic};
 pub use topic_service::{StreamingResponse, TopicService};
 
-use tracing::{debug, instrument};
+use tracing::{debug, instrument, warn};
 
 use crate::{
     error::KvError,
-    pb::abi::{command_request::RequestData, CommandRequest, CommandResponse},
-    storage::Storage,
-    MemTable,
+    pb::abi::{
+        command_request::RequestData, Backup, CommandRequest, CommandResponse, DropTable, Hcas,
+        Hdel, Hexists, Hexpire, Hget, Hgetall, Hincrby, Hincrbyfloat, Hmdel, Hmexists, Hmget,
+        Hmset, Hpersist, Hscan, Hset, Hsetex, Hsetnx, Httl, KeyspaceEvent, Kvpair, Lpop, Lpush,
+        Lrange, RenameTable, Restore, Rpop, Rpush, Sadd, Sismember, Smembers, Srem, TableLen, Zadd,
+        Zrange, Zrangebyscore, Zrank, Zrem, Zscore,
+    },
+    storage::{is_reserved_table, ExpiredHook, Storage},
+    MemTable, PubsubConfig,
 };
 
+/// Hgetall 分块返回时每块包含的 kv 数量
+const HGETALL_CHUNK_SIZE: usize = 1000;
+
 pub trait CommandService {
     fn execute(self, store: &impl Storage) -> CommandResponse;
 }
@@ -47,27 +66,178 @@ impl<Arg> NotifyMut<Arg> for Vec<fn(&mut Arg)> {
 }
 
 pub fn dispatch(cmd: CommandRequest, store: &impl Storage) -> CommandResponse {
+    match cmd.request_data {
+        Some(RequestData::Txn(param)) => param.execute(store),
+        _ => dispatch_command(cmd, store),
+    }
+}
+
+/// 执行单个表命令，Txn 中的子命令也通过这里执行
+fn dispatch_command(cmd: CommandRequest, store: &impl Storage) -> CommandResponse {
+    if let Some(table) = cmd.request_data.as_ref().and_then(reserved_table) {
+        return reserved_error(table).into();
+    }
     match cmd.request_data {
         Some(RequestData::Hget(param)) => param.execute(store),
         Some(RequestData::Hgetall(param)) => param.execute(store),
         Some(RequestData::Hset(param)) => param.execute(store),
-        // Some(RequestData::Hmget(param)) => param.execute(store),
-        // Some(RequestData::Hmset(param)) => param.execute(store),
-        // Some(RequestData::Hdel(param)) => param.execute(store),
-        // Some(RequestData::Hmdel(param)) => param.execute(store),
-        // Some(RequestData::Hexist(param)) => param.execute(store),
-        // Some(RequestData::Hmexist(param)) => param.execute(store),
+        Some(RequestData::Hmget(param)) => param.execute(store),
+        Some(RequestData::Hmset(param)) => param.execute(store),
+        Some(RequestData::Hdel(param)) => param.execute(store),
+        Some(RequestData::Hmdel(param)) => param.execute(store),
+        Some(RequestData::Hexists(param)) => param.execute(store),
+        Some(RequestData::Hmexists(param)) => param.execute(store),
+        Some(RequestData::Hsetex(param)) => param.execute(store),
+        Some(RequestData::Hexpire(param)) => param.execute(store),
+        Some(RequestData::Httl(param)) => param.execute(store),
+        Some(RequestData::Hpersist(param)) => param.execute(store),
+        Some(RequestData::Hincrby(param)) => param.execute(store),
+        Some(RequestData::Hincrbyfloat(param)) => param.execute(store),
+        Some(RequestData::Hsetnx(param)) => param.execute(store),
+        Some(RequestData::Hcas(param)) => param.execute(store),
+        Some(RequestData::Hscan(param)) => param.execute(store),
+        Some(RequestData::ListTables(param)) => param.execute(store),
+        Some(RequestData::DropTable(param)) => param.execute(store),
+        Some(RequestData::TableLen(param)) => param.execute(store),
+        Some(RequestData::RenameTable(param)) => param.execute(store),
+        Some(RequestData::Backup(param)) => param.execute(store),
+        Some(RequestData::Restore(param)) => param.execute(store),
+        Some(RequestData::Lpush(param)) => param.execute(store),
+        Some(RequestData::Rpush(param)) => param.execute(store),
+        Some(RequestData::Lpop(param)) => param.execute(store),
+        Some(RequestData::Rpop(param)) => param.execute(store),
+        Some(RequestData::Lrange(param)) => param.execute(store),
+        Some(RequestData::Sadd(param)) => param.execute(store),
+        Some(RequestData::Srem(param)) => param.execute(store),
+        Some(RequestData::Smembers(param)) => param.execute(store),
+        Some(RequestData::Sismember(param)) => param.execute(store),
+        Some(RequestData::Zadd(param)) => param.execute(store),
+        Some(RequestData::Zrem(param)) => param.execute(store),
+        Some(RequestData::Zscore(param)) => param.execute(store),
+        Some(RequestData::Zrank(param)) => param.execute(store),
+        Some(RequestData::Zrange(param)) => param.execute(store),
+        Some(RequestData::Zrangebyscore(param)) => param.execute(store),
         None => KvError::InvalidCommand("Request has no data".into()).into(),
         // _ => KvError::InvalidCommand("Not Unimplemented".into()).into(),
         _ => CommandResponse::default(),
     }
 }
 
+/// 命令访问的内部 table，client 不能直接读写这些 table
+fn reserved_table(data: &RequestData) -> Option<&str> {
+    let tables = match data {
+        RequestData::Hget(Hget { table, .. })
+        | RequestData::Hgetall(Hgetall { table, .. })
+        | RequestData::Hmget(Hmget { table, .. })
+        | RequestData::Hset(Hset { table, .. })
+        | RequestData::Hmset(Hmset { table, .. })
+        | RequestData::Hdel(Hdel { table, .. })
+        | RequestData::Hmdel(Hmdel { table, .. })
+        | RequestData::Hexists(Hexists { table, .. })
+        | RequestData::Hmexists(Hmexists { table, .. })
+        | RequestData::Hsetex(Hsetex { table, .. })
+        | RequestData::Hexpire(Hexpire { table, .. })
+        | RequestData::Httl(Httl { table, .. })
+        | RequestData::Hpersist(Hpersist { table, .. })
+        | RequestData::Hincrby(Hincrby { table, .. })
+        | RequestData::Hincrbyfloat(Hincrbyfloat { table, .. })
+        | RequestData::Hsetnx(Hsetnx { table, .. })
+        | RequestData::Hcas(Hcas { table, .. })
+        | RequestData::Hscan(Hscan { table, .. })
+        | RequestData::DropTable(DropTable { table, .. })
+        | RequestData::TableLen(TableLen { table, .. })
+        | RequestData::Lpush(Lpush { table, .. })
+        | RequestData::Rpush(Rpush { table, .. })
+        | RequestData::Lpop(Lpop { table, .. })
+        | RequestData::Rpop(Rpop { table, .. })
+        | RequestData::Lrange(Lrange { table, .. })
+        | RequestData::Sadd(Sadd { table, .. })
+        | RequestData::Srem(Srem { table, .. })
+        | RequestData::Smembers(Smembers { table, .. })
+        | RequestData::Sismember(Sismember { table, .. })
+        | RequestData::Zadd(Zadd { table, .. })
+        | RequestData::Zrem(Zrem { table, .. })
+        | RequestData::Zscore(Zscore { table, .. })
+        | RequestData::Zrank(Zrank { table, .. })
+        | RequestData::Zrange(Zrange { table, .. })
+        | RequestData::Zrangebyscore(Zrangebyscore { table, .. }) => vec![table],
+        RequestData::RenameTable(RenameTable { from, to }) => vec![from, to],
+        _ => vec![],
+    };
+    tables
+        .into_iter()
+        .find(|table| is_reserved_table(table))
+        .map(String::as_str)
+}
+
+fn reserved_error(table: &str) -> KvError {
+    KvError::InvalidCommand(format!("table {} is reserved", table))
+}
+
+/// 把 Backup 和 Restore 的 path 换成 dir 下的路径。
+/// path 只能是不含 `..` 的相对路径，没有配置 dir 时不允许备份和恢复
+fn resolve_backup_path(
+    mut cmd: CommandRequest,
+    dir: Option<&Path>,
+) -> Result<CommandRequest, KvError> {
+    let (Some(RequestData::Backup(Backup { path })) | Some(RequestData::Restore(Restore { path }))) =
+        &mut cmd.request_data
+    else {
+        return Ok(cmd);
+    };
+    let Some(dir) = dir else {
+        return Err(KvError::InvalidCommand(
+            "backup dir is not configured".into(),
+        ));
+    };
+    let relative = Path::new(path.as_str());
+    let normal = relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)));
+    if path.is_empty() || !normal {
+        return Err(KvError::InvalidCommand(format!(
+            "backup path {} must be relative to the backup dir",
+            path
+        )));
+    }
+    *path = dir.join(relative).to_string_lossy().into_owned();
+    Ok(cmd)
+}
+
+/// 把数据按 chunk_size 分块返回，最后一块是不带数据、more 为 false 的结束标记
+fn into_chunks(
+    mut iter: impl Iterator<Item = Kvpair> + Send,
+    chunk_size: usize,
+) -> impl Iterator<Item = CommandResponse> + Send {
+    let mut done = false;
+    std::iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        let pairs: Vec<_> = iter.by_ref().take(chunk_size).collect();
+        if pairs.is_empty() {
+            done = true;
+            return Some(CommandResponse::ok());
+        }
+        Some(CommandResponse {
+            more: true,
+            ..pairs.into()
+        })
+    })
+}
+
 pub fn dispatch_stream(cmd: CommandRequest, topic: impl Topic) -> StreamingResponse {
     match cmd.request_data {
         Some(RequestData::Publish(param)) => param.execute(topic),
         Some(RequestData::Subscribe(param)) => param.execute(topic),
         Some(RequestData::Unsubscribe(param)) => param.execute(topic),
+        Some(RequestData::Psubscribe(param)) => param.execute(topic),
+        Some(RequestData::Punsubscribe(param)) => param.execute(topic),
+        Some(RequestData::Ack(param)) => param.execute(topic),
+        Some(RequestData::Nack(param)) => param.execute(topic),
+        Some(RequestData::PubsubTopics(param)) => param.execute(topic),
+        Some(RequestData::PubsubNumsub(param)) => param.execute(topic),
+        Some(RequestData::PubsubSubscription(param)) => param.execute(topic),
         _ => unreachable!(),
     }
 }
@@ -86,34 +256,129 @@ impl<Store> Clone for Service<Store> {
     }
 }
 
-impl<Store: Storage> Service<Store> {
+impl<Store: Storage + 'static> Service<Store> {
     // pub fn new(store: Store) -> Self {
     //     Self {
     //         inner: Arc::new(ServiceInner { store }),
     //     }
     // }
 
+    /// 创建一个记录 subscription 的 Subscriptions，用来在连接断开时删除连接上的订阅
+    pub fn subscriptions(&self) -> Subscriptions {
+        Subscriptions::new(Arc::clone(&self.broadcaster))
+    }
+
     #[instrument(name = "service_execute", skip_all)]
     pub fn execute(&self, cmd: CommandRequest) -> StreamingResponse {
         debug!("Got request: {:?}", cmd);
         self.inner.on_received.notify(&cmd);
-        let mut res = dispatch(cmd.clone(), &self.inner.store);
-        if res == CommandResponse::default() {
-            dispatch_stream(cmd, Arc::clone(&self.broadcaster))
-        } else {
+        let blocking = match &cmd.request_data {
+            Some(RequestData::Hgetall(param)) if !is_reserved_table(&param.table) => {
+                return self.execute_chunked(param.table.clone())
+            }
+            Some(
+                RequestData::Publish(_)
+                | RequestData::Subscribe(_)
+                | RequestData::Unsubscribe(_)
+                | RequestData::Psubscribe(_)
+                | RequestData::Punsubscribe(_)
+                | RequestData::Ack(_)
+                | RequestData::Nack(_)
+                | RequestData::PubsubTopics(_)
+                | RequestData::PubsubNumsub(_)
+                | RequestData::PubsubSubscription(_),
+            ) => return dispatch_stream(cmd, Arc::clone(&self.broadcaster)),
+            // 备份和恢复总是要读写文件，事务和 table 管理命令可能长时间持有 Storage 的写锁
+            Some(
+                RequestData::Backup(_)
+                | RequestData::Restore(_)
+                | RequestData::Txn(_)
+                | RequestData::DropTable(_)
+                | RequestData::RenameTable(_),
+            ) => true,
+            _ => self.inner.store.is_blocking(),
+        };
+        let inner = Arc::clone(&self.inner);
+        let broadcaster = Arc::clone(&self.broadcaster);
+        // 需要发布 keyspace 通知时留一份命令，执行后根据它生成变更事件
+        let watched = inner.keyspace.watches(&cmd).then(|| cmd.clone());
+        Box::pin(stream::once(async move {
+            let store = Arc::clone(&inner);
+            let mut res = run(blocking, move || {
+                match resolve_backup_path(cmd, store.backup_dir.as_deref()) {
+                    Ok(cmd) => dispatch(cmd, &store.store),
+                    Err(e) => e.into(),
+                }
+            })
+            .await
+            .unwrap_or_else(CommandResponse::from);
             debug!("Executed response: {:?}", res);
-            self.inner.on_executed.notify(&res);
-            self.inner.on_before_send.notify(&mut res);
-            if !self.inner.on_before_send.is_empty() {
+            if let Some(cmd) = watched {
+                for event in inner.keyspace.events(&cmd, &res) {
+                    inner.keyspace.publish(&broadcaster, event);
+                }
+            }
+            inner.on_executed.notify(&res);
+            inner.on_before_send.notify(&mut res);
+            if !inner.on_before_send.is_empty() {
                 debug!("Modified response: {:?}", res);
             }
-            Box::pin(stream::once(async { Arc::new(res) }))
-        }
+            Arc::new(res)
+        }))
+    }
+
+    /// 分块返回整个 table 的数据，避免大 table 生成一个超大的 frame
+    fn execute_chunked(&self, table: String) -> StreamingResponse {
+        let blocking = self.inner.store.is_blocking();
+        let store = Arc::clone(&self.inner);
+        // 第一次读取时才打开 table，这样它也可以在阻塞线程池中执行
+        let chunks = std::iter::once_with(move || match store.store.get_iter(&table) {
+            Ok(iter) => Box::new(into_chunks(iter, HGETALL_CHUNK_SIZE)) as Chunks,
+            Err(e) => Box::new(std::iter::once(e.into())),
+        })
+        .flatten();
+        let chunks: Chunks = Box::new(chunks);
+        let on_executed = self.inner.on_executed.clone();
+        let on_before_send = self.inner.on_before_send.clone();
+        let stream = stream::unfold(Some(chunks), move |chunks| async move {
+            let mut chunks = chunks?;
+            let res = run(blocking, move || (chunks.next(), chunks)).await;
+            match res {
+                Ok((Some(res), chunks)) => Some((res, Some(chunks))),
+                Ok((None, _)) => None,
+                Err(e) => Some((e.into(), None)),
+            }
+        })
+        .map(move |mut res| {
+            on_executed.notify(&res);
+            on_before_send.notify(&mut res);
+            Arc::new(res)
+        });
+        Box::pin(stream)
+    }
+}
+
+type Chunks = Box<dyn Iterator<Item = CommandResponse> + Send>;
+
+/// blocking 为 true 时在 spawn_blocking 的线程池中执行 f，避免卡住 tokio 的工作线程
+async fn run<T, F>(blocking: bool, f: F) -> Result<T, KvError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    match blocking {
+        true => tokio::task::spawn_blocking(f)
+            .await
+            .map_err(|e| KvError::Internal(e.to_string())),
+        false => Ok(f()),
     }
 }
 
 pub struct ServiceInner<Store> {
     store: Store,
+    keyspace: Arc<Keyspace>,
+    pubsub: PubsubConfig,
+    backup_dir: Option<PathBuf>,
     on_received: Vec<fn(&CommandRequest)>,
     on_executed: Vec<fn(&CommandResponse)>,
     on_before_send: Vec<fn(&mut CommandResponse)>,
@@ -124,6 +389,9 @@ impl<Store> ServiceInner<Store> {
     pub fn new(store: Store) -> Self {
         Self {
             store,
+            keyspace: Default::default(),
+            pubsub: Default::default(),
+            backup_dir: None,
             on_received: vec![],
             on_executed: vec![],
             on_before_send: Vec::new(),
@@ -131,6 +399,24 @@ impl<Store> ServiceInner<Store> {
         }
     }
 
+    /// 修改 tables 中的 key 时在 `__keyspace:<table>` topic 上发布变更事件，"*" 表示所有 table
+    pub fn notify_keyspace(mut self, tables: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.keyspace = Arc::new(Keyspace::new(tables));
+        self
+    }
+
+    /// 设置 topic 的消息保留策略
+    pub fn pubsub(mut self, config: PubsubConfig) -> Self {
+        self.pubsub = config;
+        self
+    }
+
+    /// 设置 Backup 和 Restore 命令可以读写的目录
+    pub fn backup_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.backup_dir = Some(dir.into());
+        self
+    }
+
     pub fn fn_received(mut self, f: fn(&CommandRequest)) -> Self {
         self.on_received.push(f);
         self
@@ -157,23 +443,68 @@ impl<Store> ServiceInner<Store> {
     }
 }
 
-impl<Store: Storage> From<ServiceInner<Store>> for Service<Store> {
+impl<Store: Storage + 'static> From<ServiceInner<Store>> for Service<Store> {
     fn from(value: ServiceInner<Store>) -> Self {
-        Self {
-            inner: Arc::new(value),
-            broadcaster: Default::default(),
+        let broadcaster = Arc::new(Broadcaster::new(value.pubsub.clone()));
+        if value.keyspace.is_enabled() {
+            // 过期和淘汰的 key 可能在 Storage 的后台线程中被清理，所以只能同步地发布
+            let hook = |op: &'static str| -> ExpiredHook {
+                let keyspace = Arc::clone(&value.keyspace);
+                let b = Arc::clone(&broadcaster);
+                Arc::new(move |table, key, old| {
+                    if keyspace.watches_table(table) {
+                        let event = KeyspaceEvent::new(table, key, op, Some(old.clone()), None);
+                        keyspace.publish(&b, event);
+                    }
+                })
+            };
+            value.store.on_expired(hook("expired"));
+            value.store.on_evicted(hook("evicted"));
+        }
+        let inner = Arc::new(value);
+        if inner.pubsub.persist {
+            if let Err(e) = journal::attach(&inner, &broadcaster) {
+                warn!("Failed to restore topics: {:?}", e);
+            }
         }
+        Self { inner, broadcaster }
     }
 }
 
+#[cfg(test)]
+use crate::Value;
+
+#[cfg(test)]
+pub fn assert_res_ok(res: &CommandResponse, values: &[Value], pairs: &[Kvpair]) {
+    let mut sorted_pairs = res.pairs.clone();
+    sorted_pairs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    // res.pairs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    assert_eq!(res.status, 200);
+    assert_eq!(res.message, "");
+    assert_eq!(res.values, values);
+    assert_eq!(res.pairs, pairs);
+}
+
+#[cfg(test)]
+pub fn assert_res_error(res: &CommandResponse, code: u32, msg: &str) {
+    assert_eq!(res.status, code);
+    assert!(res.message.contains(msg));
+    assert_eq!(res.values, &[]);
+    assert_eq!(res.pairs, &[]);
+}
+
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use futures::StreamExt;
 
     use http::StatusCode;
     use tracing::info;
 
-    use crate::{MemTable, Value};
+    use tempfile::tempdir;
+
+    use crate::{EvictionConfig, EvictionPolicy, MemTable, SledDb, Value};
 
     use super::*;
     #[tokio::test]
@@ -201,6 +532,61 @@ mod tests {
         assert_res_ok(&data, &["v1".into()], &[]);
     }
 
+    #[tokio::test]
+    async fn hgetall_should_return_chunks() {
+        let service: Service = ServiceInner::new(MemTable::default()).into();
+        let n = HGETALL_CHUNK_SIZE * 2 + 1;
+        for i in 0..n {
+            let cmd = CommandRequest::new_hset("t1", format!("k{}", i), (i as i64).into());
+            service.execute(cmd).next().await.unwrap();
+        }
+
+        let res = service.execute(CommandRequest::new_hgetall("t1"));
+        let chunks: Vec<_> = res.collect().await;
+        let sizes: Vec<_> = chunks.iter().map(|v| (v.pairs.len(), v.more)).collect();
+        assert_eq!(
+            sizes,
+            vec![
+                (HGETALL_CHUNK_SIZE, true),
+                (HGETALL_CHUNK_SIZE, true),
+                (1, true),
+                (0, false)
+            ]
+        );
+
+        // 空 table 只返回结束标记
+        let res = service.execute(CommandRequest::new_hgetall("t2"));
+        let chunks: Vec<_> = res.collect().await;
+        assert_eq!(chunks.len(), 1);
+        assert_res_ok(&chunks[0], &[], &[]);
+    }
+
+    #[tokio::test]
+    async fn blocking_store_should_work() {
+        let dir = tempdir().unwrap();
+        let service: Service<SledDb> = ServiceInner::new(SledDb::new(dir.path())).into();
+        assert!(service.inner.store.is_blocking());
+        for i in 0..10 {
+            let cmd = CommandRequest::new_hset("t1", format!("k{}", i), (i as i64).into());
+            let data = service.execute(cmd).next().await.unwrap();
+            assert_res_ok(&data, &[Value::default()], &[]);
+        }
+
+        let data = service
+            .execute(CommandRequest::new_hget("t1", "k3"))
+            .next()
+            .await
+            .unwrap();
+        assert_res_ok(&data, &[3.into()], &[]);
+
+        let chunks: Vec<_> = service
+            .execute(CommandRequest::new_hgetall("t1"))
+            .collect()
+            .await;
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].pairs.len(), 10);
+    }
+
     #[tokio::test]
     async fn event_registration_should_work() {
         fn b(cmd: &CommandRequest) {
@@ -233,26 +619,215 @@ mod tests {
         assert_eq!(data.values, vec![Value::default()]);
         // assert_eq!(res.pairs, vec![Value::default()]);
     }
-}
 
-#[cfg(test)]
-use crate::{Kvpair, Value};
+    #[tokio::test]
+    async fn persisted_topics_should_be_replayed_after_restart() {
+        let dir = tempdir().unwrap();
+        let config = PubsubConfig {
+            retention: 2,
+            persist: true,
+            ..Default::default()
+        };
+        {
+            let service: Service<SledDb> = ServiceInner::new(SledDb::new(dir.path()))
+                .pubsub(config.clone())
+                .into();
+            for i in 1..=3 {
+                let cmd = CommandRequest::new_publish("lobby", vec![(i as i64).into()]);
+                service.execute(cmd).next().await.unwrap();
+            }
+            // 等待后台的发布完成
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
 
-#[cfg(test)]
-pub fn assert_res_ok(res: &CommandResponse, values: &[Value], pairs: &[Kvpair]) {
-    let mut sorted_pairs = res.pairs.clone();
-    sorted_pairs.sort_by(|a, b| a.partial_cmp(b).unwrap());
-    // res.pairs.sort_by(|a, b| a.partial_cmp(b).unwrap());
-    assert_eq!(res.status, 200);
-    assert_eq!(res.message, "");
-    assert_eq!(res.values, values);
-    assert_eq!(res.pairs, pairs);
-}
+        let service: Service<SledDb> = ServiceInner::new(SledDb::new(dir.path()))
+            .pubsub(config)
+            .into();
+        let mut res = service.execute(CommandRequest::new_subscribe_earliest("lobby"));
+        res.next().await.unwrap();
+        for i in 2..=3 {
+            let msg = res.next().await.unwrap();
+            assert_eq!(msg.offset, i as u64);
+            assert_res_ok(&msg, &[(i as i64).into()], &[]);
+        }
 
-#[cfg(test)]
-pub fn assert_res_error(res: &CommandResponse, code: u32, msg: &str) {
-    assert_eq!(res.status, code);
-    assert!(res.message.contains(msg));
-    assert_eq!(res.values, &[]);
-    assert_eq!(res.pairs, &[]);
+        // 新消息的 offset 接着之前的继续
+        let cmd = CommandRequest::new_publish("lobby", vec!["hello".into()]);
+        service.execute(cmd).next().await.unwrap();
+        assert_eq!(res.next().await.unwrap().offset, 4);
+    }
+
+    #[tokio::test]
+    async fn pubsub_tables_should_be_reserved() {
+        let store = MemTable::new()
+            .with_eviction(EvictionConfig {
+                policy: EvictionPolicy::Lru,
+                max_entries: Some(1),
+                max_memory: None,
+            })
+            .unwrap();
+        let service: Service = ServiceInner::new(store)
+            .pubsub(PubsubConfig {
+                retention: 3,
+                persist: true,
+                ..Default::default()
+            })
+            .into();
+        for i in 1..=3 {
+            let cmd = CommandRequest::new_publish("lobby", vec![(i as i64).into()]);
+            service.execute(cmd).next().await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // 内部 table 不受淘汰影响，也不会出现在 ListTables 中
+        let store = &service.inner.store;
+        assert_eq!(store.get_all("__pubsub:lobby").unwrap().len(), 3);
+        assert_eq!(store.evicted_count(), 0);
+        let res = service.execute(CommandRequest::new_list_tables());
+        assert_res_ok(&res.collect::<Vec<_>>().await[0], &[], &[]);
+
+        let cmds = [
+            CommandRequest::new_hget("__pubsub:lobby", "00000000000000000001"),
+            CommandRequest::new_hgetall("__pubsub:lobby"),
+            CommandRequest::new_drop_table("__pubsub:lobby"),
+            CommandRequest::new_rename_table("t1", "__pubsub:lobby"),
+            CommandRequest::new_txn(vec![CommandRequest::new_hset(
+                "__pubsub:lobby",
+                "k1",
+                "v1".into(),
+            )]),
+        ];
+        for cmd in cmds {
+            let res = service.execute(cmd).next().await.unwrap();
+            assert_eq!(res.status, StatusCode::BAD_REQUEST.as_u16() as u32);
+        }
+        assert_eq!(store.get_all("__pubsub:lobby").unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn backup_should_be_limited_to_backup_dir() {
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        let res = service
+            .execute(CommandRequest::new_backup("kv.backup"))
+            .next()
+            .await
+            .unwrap();
+        assert_res_error(&res, 400, "not configured");
+
+        let dir = tempdir().unwrap();
+        let service: Service = ServiceInner::new(MemTable::new())
+            .backup_dir(dir.path())
+            .into();
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        service.execute(cmd).next().await.unwrap();
+        let res = service
+            .execute(CommandRequest::new_backup("kv.backup"))
+            .next()
+            .await
+            .unwrap();
+        assert_res_ok(&res, &[1.into()], &[]);
+        assert!(dir.path().join("kv.backup").exists());
+        let res = service
+            .execute(CommandRequest::new_restore("kv.backup"))
+            .next()
+            .await
+            .unwrap();
+        assert_res_ok(&res, &[1.into()], &[]);
+
+        let outside = dir.path().join("outside.backup");
+        let paths = [
+            "",
+            "../kv.backup",
+            "a/../../kv.backup",
+            outside.to_str().unwrap(),
+        ];
+        for path in paths {
+            let res = service
+                .execute(CommandRequest::new_backup(path))
+                .next()
+                .await
+                .unwrap();
+            assert_res_error(&res, 400, "relative");
+            let res = service
+                .execute(CommandRequest::new_restore(path))
+                .next()
+                .await
+                .unwrap();
+            assert_res_error(&res, 400, "relative");
+        }
+        assert!(!outside.exists());
+    }
+
+    #[tokio::test]
+    async fn evicted_keys_should_be_published() {
+        let store = MemTable::new()
+            .with_eviction(EvictionConfig {
+                policy: EvictionPolicy::Lru,
+                max_entries: Some(1),
+                max_memory: None,
+            })
+            .unwrap();
+        let service: Service = ServiceInner::new(store).notify_keyspace(["t1"]).into();
+        let mut events = service.execute(CommandRequest::new_subscribe("__keyspace:t1"));
+        events.next().await.unwrap();
+
+        for key in ["k1", "k2"] {
+            let cmd = CommandRequest::new_hset("t1", key, "v1".into());
+            service.execute(cmd).next().await.unwrap();
+        }
+        assert_eq!(service.inner.store.evicted_count(), 1);
+
+        // 写入 k2 时先淘汰了 k1
+        let expected = [
+            KeyspaceEvent::new("t1", "k1", "set", None, Some("v1".into())),
+            KeyspaceEvent::new("t1", "k1", "evicted", Some("v1".into()), None),
+            KeyspaceEvent::new("t1", "k2", "set", None, Some("v1".into())),
+        ];
+        for event in expected {
+            let res = events.next().await.unwrap();
+            assert_eq!(res.event, Some(event));
+        }
+    }
+
+    #[tokio::test]
+    async fn keyspace_events_should_be_published() {
+        let service: Service = ServiceInner::new(MemTable::default())
+            .notify_keyspace(["t1"])
+            .into();
+        let mut events = service.execute(CommandRequest::new_subscribe("__keyspace:t1"));
+        // 第一条消息是订阅 id
+        events.next().await.unwrap();
+
+        let cmds = [
+            CommandRequest::new_hset("t1", "k1", "v1".into()),
+            CommandRequest::new_hset("t1", "k1", "v2".into()),
+            CommandRequest::new_hset("t2", "k1", "v1".into()),
+            CommandRequest::new_hdel("t1", "k1"),
+            CommandRequest::new_hdel("t1", "k1"),
+            CommandRequest::new_hsetex("t1", "k2", "v3".into(), 10),
+        ];
+        for cmd in cmds {
+            service.execute(cmd).next().await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        // 读到过期的 key 时会删除它并发布 expired 事件
+        service
+            .execute(CommandRequest::new_hget("t1", "k2"))
+            .next()
+            .await
+            .unwrap();
+
+        let expected = [
+            KeyspaceEvent::new("t1", "k1", "set", None, Some("v1".into())),
+            KeyspaceEvent::new("t1", "k1", "set", Some("v1".into()), Some("v2".into())),
+            KeyspaceEvent::new("t1", "k1", "del", Some("v2".into()), None),
+            KeyspaceEvent::new("t1", "k2", "set", None, Some("v3".into())),
+            KeyspaceEvent::new("t1", "k2", "expired", Some("v3".into()), None),
+        ];
+        for event in expected {
+            let res = events.next().await.unwrap();
+            assert_eq!(res.status, 200);
+            assert_eq!(res.event, Some(event));
+        }
+    }
 }
@@ -0,0 +1,240 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    Notify,
+};
+
+use crate::{CommandResponse, OverflowPolicy};
+
+/// 一个 subscription 的发送端，按照溢出策略处理缓冲区满了的情况
+#[derive(Debug)]
+pub(super) struct Subscriber {
+    tx: mpsc::Sender<Arc<CommandResponse>>,
+    overflow: OverflowPolicy,
+    /// DropOldest 策略下的缓冲区，由后台任务按顺序转发到 tx
+    ring: Option<Arc<Ring>>,
+    /// 因为缓冲区满了而丢掉的消息数量
+    dropped: AtomicU64,
+}
+
+/// 不等待地把消息交给 subscription 的结果
+#[derive(Debug)]
+pub(super) enum Offer {
+    Sent,
+    /// 消息被丢掉了，包括 DropOldest 策略下被挤出缓冲区的旧消息
+    Dropped,
+    /// Block 策略下缓冲区满了，需要调用者等待发送
+    Full(Arc<CommandResponse>),
+    /// 订阅者已经断开，或者因为 Disconnect 策略需要断开
+    Closed,
+}
+
+impl Subscriber {
+    /// 创建 subscription 的发送端，preload 中的消息直接放进 channel，排在之后的消息前面
+    pub fn new(
+        overflow: OverflowPolicy,
+        capacity: usize,
+        preload: Vec<Arc<CommandResponse>>,
+    ) -> (Self, mpsc::Receiver<Arc<CommandResponse>>) {
+        let (tx, rx) = match overflow {
+            // 消息都放在 ring 中，channel 只需要放下 preload 的消息
+            OverflowPolicy::DropOldest => mpsc::channel(preload.len().max(1)),
+            _ => mpsc::channel(capacity.max(preload.len())),
+        };
+        for msg in preload {
+            let _ = tx.try_send(msg);
+        }
+        let ring = (overflow == OverflowPolicy::DropOldest).then(|| {
+            let ring = Arc::new(Ring::new(capacity));
+            tokio::spawn(ring.clone().forward(tx.clone()));
+            ring
+        });
+        let subscriber = Self {
+            tx,
+            overflow,
+            ring,
+            dropped: Default::default(),
+        };
+        (subscriber, rx)
+    }
+
+    pub fn overflow(&self) -> OverflowPolicy {
+        self.overflow
+    }
+
+    /// 缓冲区中还没有被订阅者取走的消息数量
+    pub fn buffered(&self) -> usize {
+        let queued = self.tx.max_capacity() - self.tx.capacity();
+        queued + self.ring.as_ref().map(|v| v.len()).unwrap_or(0)
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// 不等待地把消息放进缓冲区
+    pub fn offer(&self, msg: Arc<CommandResponse>) -> Offer {
+        if let Some(ring) = &self.ring {
+            if self.tx.is_closed() {
+                return Offer::Closed;
+            }
+            if ring.push(msg) {
+                self.record_drop();
+                return Offer::Dropped;
+            }
+            return Offer::Sent;
+        }
+        match self.tx.try_send(msg) {
+            Ok(()) => Offer::Sent,
+            Err(TrySendError::Closed(_)) => Offer::Closed,
+            Err(TrySendError::Full(msg)) if self.overflow == OverflowPolicy::Block => {
+                Offer::Full(msg)
+            }
+            Err(TrySendError::Full(_)) => {
+                self.record_drop();
+                match self.overflow {
+                    OverflowPolicy::Disconnect => Offer::Closed,
+                    _ => Offer::Dropped,
+                }
+            }
+        }
+    }
+
+    /// 等待缓冲区有空间后发送，订阅者断开时返回 false
+    pub async fn send(&self, msg: Arc<CommandResponse>) -> bool {
+        self.tx.send(msg).await.is_ok()
+    }
+
+    /// 记录一条因为缓冲区满了而丢掉的消息
+    pub fn record_drop(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        if let Some(ring) = &self.ring {
+            ring.close();
+        }
+    }
+}
+
+/// 固定容量的消息队列，满了之后挤掉最旧的消息
+#[derive(Debug)]
+struct Ring {
+    queue: Mutex<VecDeque<Arc<CommandResponse>>>,
+    capacity: usize,
+    notify: Notify,
+    closed: AtomicBool,
+}
+
+impl Ring {
+    fn new(capacity: usize) -> Self {
+        Self {
+            queue: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity: capacity.max(1),
+            notify: Notify::new(),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    /// 放入消息，返回是否挤掉了一条旧消息
+    fn push(&self, msg: Arc<CommandResponse>) -> bool {
+        let evicted = {
+            let mut queue = self.queue.lock().unwrap();
+            let evicted = queue.len() >= self.capacity && queue.pop_front().is_some();
+            queue.push_back(msg);
+            evicted
+        };
+        self.notify.notify_one();
+        evicted
+    }
+
+    fn len(&self) -> usize {
+        self.queue.lock().unwrap().len()
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.notify.notify_one();
+    }
+
+    /// 把队列中的消息按顺序转发到 channel，subscription 删除或者订阅者断开后退出
+    async fn forward(self: Arc<Self>, tx: mpsc::Sender<Arc<CommandResponse>>) {
+        loop {
+            let msg = self.queue.lock().unwrap().pop_front();
+            match msg {
+                Some(msg) => {
+                    if tx.send(msg).await.is_err() {
+                        break;
+                    }
+                }
+                None if self.closed.load(Ordering::Acquire) => break,
+                None => self.notify.notified().await,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(offset: u64) -> Arc<CommandResponse> {
+        Arc::new(CommandResponse {
+            offset,
+            ..CommandResponse::ok()
+        })
+    }
+
+    fn offsets(rx: &mut mpsc::Receiver<Arc<CommandResponse>>) -> Vec<u64> {
+        let mut offsets = vec![];
+        while let Ok(res) = rx.try_recv() {
+            offsets.push(res.offset);
+        }
+        offsets
+    }
+
+    #[tokio::test]
+    async fn overflow_policies_should_work() {
+        let (block, mut rx) = Subscriber::new(OverflowPolicy::Block, 1, vec![]);
+        assert!(matches!(block.offer(message(1)), Offer::Sent));
+        assert!(matches!(block.offer(message(2)), Offer::Full(_)));
+        assert_eq!(offsets(&mut rx), [1]);
+
+        let (newest, mut rx) = Subscriber::new(OverflowPolicy::DropNewest, 1, vec![]);
+        assert!(matches!(newest.offer(message(1)), Offer::Sent));
+        assert!(matches!(newest.offer(message(2)), Offer::Dropped));
+        assert_eq!(offsets(&mut rx), [1]);
+        assert_eq!(newest.dropped(), 1);
+
+        let (disconnect, _rx) = Subscriber::new(OverflowPolicy::Disconnect, 1, vec![]);
+        assert!(matches!(disconnect.offer(message(1)), Offer::Sent));
+        assert!(matches!(disconnect.offer(message(2)), Offer::Closed));
+        assert_eq!(disconnect.dropped(), 1);
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_should_keep_latest_messages() {
+        let (oldest, mut rx) = Subscriber::new(OverflowPolicy::DropOldest, 2, vec![message(0)]);
+        for i in 1..=4 {
+            oldest.offer(message(i));
+        }
+        // 转发任务还没有运行，channel 中只有 preload 的消息，ring 中保留最新的两条
+        assert_eq!(oldest.dropped(), 2);
+        assert_eq!(rx.recv().await.unwrap().offset, 0);
+        assert_eq!(rx.recv().await.unwrap().offset, 3);
+        assert_eq!(rx.recv().await.unwrap().offset, 4);
+
+        // 删除 subscription 后转发任务退出，订阅者收到结束
+        drop(oldest);
+        assert!(rx.recv().await.is_none());
+    }
+}
@@ -1,14 +1,27 @@
-use std::sync::{
-    atomic::{AtomicU32, Ordering},
-    Arc,
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt, mem,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc, Once, OnceLock,
+    },
+    time::{Duration, Instant},
 };
 
 use anyhow::Result;
 use dashmap::{DashMap, DashSet};
+use futures::future::join_all;
 use tokio::sync::mpsc;
 use tracing::{debug, info, instrument, warn};
 
-use crate::{CommandResponse, KvError, Value};
+use super::{
+    group::{dead_letter_topic, ConsumerGroup, Retry},
+    subscriber::{Offer, Subscriber},
+};
+use crate::{
+    command_request::RequestData, CommandRequest, CommandResponse, KvError, OverflowPolicy,
+    PubsubConfig, Value,
+};
 
 static NEXT_ID: AtomicU32 = AtomicU32::new(1);
 const BROADCAST_CAPACITY: usize = 128;
@@ -17,38 +30,144 @@ fn get_next_subscription_id() -> u32 {
     NEXT_ID.fetch_add(1, Ordering::Relaxed)
 }
 
+/// 消息要发送给的 subscription：subscription id、要发送的消息，以及通过模式订阅时订阅的模式
+type Target = (u32, Arc<CommandResponse>, Option<String>);
+
 pub trait Topic: Send + Sync + 'static {
-    fn subscribe(&self, name: String) -> mpsc::Receiver<Arc<CommandResponse>>;
+    fn subscribe(&self, name: String) -> mpsc::Receiver<Arc<CommandResponse>> {
+        self.subscribe_from(name, None, None)
+    }
+    /// 订阅 topic，from 不为空时先重放 topic 保留的 offset 不小于 from 的消息。
+    /// overflow 为空时使用 Broadcaster 默认的溢出策略
+    fn subscribe_from(
+        &self,
+        name: String,
+        from: Option<u64>,
+        overflow: Option<OverflowPolicy>,
+    ) -> mpsc::Receiver<Arc<CommandResponse>>;
     fn unsubscribe(self, name: String, id: u32) -> Result<u32, KvError>;
+    /// 订阅所有名字匹配 glob 模式的 topic
+    fn psubscribe(
+        &self,
+        pattern: String,
+        overflow: Option<OverflowPolicy>,
+    ) -> mpsc::Receiver<Arc<CommandResponse>>;
+    fn punsubscribe(self, pattern: String, id: u32) -> Result<u32, KvError>;
+    /// 以消费组成员的身份订阅 topic，topic 的每条消息只投递给组中的一个成员
+    fn subscribe_group(
+        &self,
+        name: String,
+        group: String,
+        overflow: Option<OverflowPolicy>,
+    ) -> mpsc::Receiver<Arc<CommandResponse>>;
+    /// 成员 id 确认收到的消息已经处理完成，消息不是由这个成员负责时返回 NotFound
+    fn ack(self, name: String, group: String, id: u32, offset: u64) -> Result<(), KvError>;
+    /// 成员 id 处理消息失败，立即重新投递给组中的下一个成员
+    fn nack(self, name: String, group: String, id: u32, offset: u64) -> Result<(), KvError>;
     fn publish(self, name: String, value: Arc<CommandResponse>);
+    /// 有订阅者的 topic 和它们的订阅者数量，按名字排序，pattern 不为空时只返回名字匹配的 topic
+    fn topics(&self, pattern: &str) -> Vec<(String, usize)>;
+    /// topic 的订阅者数量，包括消费组成员，不包括模式订阅
+    fn numsub(&self, name: &str) -> usize;
+    fn subscription(&self, id: u32) -> Option<SubscriptionInfo>;
+}
+
+/// subscription 的状态
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubscriptionInfo {
+    pub id: u32,
+    /// 订阅的 topic，消费组成员订阅的也是 topic
+    pub topic: Option<String>,
+    pub pattern: Option<String>,
+    pub group: Option<String>,
+    pub overflow: OverflowPolicy,
+    /// 缓冲区中还没有被订阅者取走的消息数量
+    pub buffered: usize,
+    /// 因为缓冲区满了而丢掉的消息数量
+    pub dropped: u64,
+}
+
+/// 保存 topic 的消息，让重启后的服务可以继续重放它们
+pub trait Journal: Send + Sync + 'static {
+    fn append(&self, topic: &str, msg: &CommandResponse);
+    /// 删除 topic 中 offset 对应的消息
+    fn remove(&self, topic: &str, offset: u64);
+}
+
+/// topic 保留的最近的消息
+#[derive(Debug, Default)]
+struct TopicLog {
+    /// 最后一条消息的 offset，还没有消息时为 0
+    last: u64,
+    messages: VecDeque<Arc<CommandResponse>>,
 }
 
-#[derive(Default, Debug)]
 pub struct Broadcaster {
     topics: DashMap<String, DashSet<u32>>,
-    subscriptions: DashMap<u32, mpsc::Sender<Arc<CommandResponse>>>,
+    /// glob 模式 -> 通过这个模式订阅的 subscription id
+    patterns: DashMap<String, DashSet<u32>>,
+    subscriptions: DashMap<u32, Arc<Subscriber>>,
+    /// 订阅时没有指定溢出策略时使用的策略
+    overflow: OverflowPolicy,
+    /// 已经删除的 subscription 丢掉的消息数量
+    retired_dropped: AtomicU64,
+    /// 每个 topic 保留的消息，新的订阅者可以从指定的 offset 开始重放。
+    /// 不保留消息时只记录有直接订阅者或者消费组的 topic 的 offset
+    logs: DashMap<String, TopicLog>,
+    /// 每个 topic 最多保留的消息数量，为 0 时不保留，但仍然会分配 offset
+    retention: usize,
+    journal: OnceLock<Arc<dyn Journal>>,
+    /// topic -> 消费组名字 -> 消费组
+    groups: DashMap<String, HashMap<String, ConsumerGroup>>,
+    /// 消费组的消息超过这个时间没有确认就重新投递
+    ack_timeout: Duration,
+    /// 消息最多投递的次数，超过之后放进死信 topic
+    max_deliveries: u32,
+    /// 成员全部离开超过这个时间的消费组会被删除
+    group_idle_timeout: Duration,
+    /// 第一次有消费组订阅时启动重新投递的后台任务
+    redeliverer: Once,
+}
+
+impl Default for Broadcaster {
+    fn default() -> Self {
+        Self::new(PubsubConfig::default())
+    }
+}
+
+impl fmt::Debug for Broadcaster {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Broadcaster")
+            .field("topics", &self.topics)
+            .field("patterns", &self.patterns)
+            .field("groups", &self.groups)
+            .field("retention", &self.retention)
+            .field("overflow", &self.overflow)
+            .finish()
+    }
 }
 
 impl Topic for Arc<Broadcaster> {
     #[instrument(name = "topic_subscribe", skip_all)]
-    fn subscribe(&self, name: String) -> mpsc::Receiver<Arc<CommandResponse>> {
-        let id = {
-            let entry = self.topics.entry(name).or_default();
-            let id = get_next_subscription_id();
-            entry.value().insert(id);
-            id
+    fn subscribe_from(
+        &self,
+        name: String,
+        from: Option<u64>,
+        overflow: Option<OverflowPolicy>,
+    ) -> mpsc::Receiver<Arc<CommandResponse>> {
+        // 持有 topic 日志的锁，保证重放的消息和之后发布的消息既不重复也不遗漏
+        let log = self.logs.entry(name.clone()).or_default();
+        let backlog = match from {
+            Some(offset) => log
+                .messages
+                .iter()
+                .filter(|v| v.offset >= offset)
+                .cloned()
+                .collect(),
+            None => vec![],
         };
-
-        let (tx, rx) = mpsc::channel(BROADCAST_CAPACITY);
-        let v: Value = (id as i64).into();
-        let tx1 = tx.clone();
-        tokio::spawn(async move {
-            if let Err(e) = tx1.send(Arc::new(v.into())).await {
-                warn!("Failed tosend subscription id: {}. Error: {:?}", id, e);
-            }
-        });
-        self.subscriptions.insert(id, tx);
-        debug!("Subscription {} is added", id);
+        let rx = self.add_subscription(&self.topics, name, backlog, overflow);
+        drop(log);
         rx
     }
 
@@ -60,6 +179,71 @@ impl Topic for Arc<Broadcaster> {
         }
     }
 
+    #[instrument(name = "topic_psubscribe", skip_all)]
+    fn psubscribe(
+        &self,
+        pattern: String,
+        overflow: Option<OverflowPolicy>,
+    ) -> mpsc::Receiver<Arc<CommandResponse>> {
+        self.add_subscription(&self.patterns, pattern, vec![], overflow)
+    }
+
+    #[instrument(name = "topic_punsubscribe", skip_all)]
+    fn punsubscribe(self, pattern: String, id: u32) -> Result<u32, KvError> {
+        match self.remove_pattern_subscription(pattern, id) {
+            Some(id) => Ok(id),
+            None => Err(KvError::NotFound(format!("subscription  {}", id))),
+        }
+    }
+
+    #[instrument(name = "topic_subscribe_group", skip_all)]
+    fn subscribe_group(
+        &self,
+        name: String,
+        group: String,
+        overflow: Option<OverflowPolicy>,
+    ) -> mpsc::Receiver<Arc<CommandResponse>> {
+        let (id, rx) = self.new_subscription(vec![], overflow);
+        self.groups
+            .entry(name)
+            .or_default()
+            .entry(group)
+            .or_default()
+            .join(id);
+        self.start_redeliverer();
+        rx
+    }
+
+    #[instrument(name = "topic_ack", skip_all)]
+    fn ack(self, name: String, group: String, id: u32, offset: u64) -> Result<(), KvError> {
+        let acked = self
+            .groups
+            .get_mut(&name)
+            .and_then(|mut groups| groups.get_mut(&group).map(|v| v.ack(id, offset)));
+        match acked {
+            Some(true) => Ok(()),
+            _ => Err(pending_not_found(&name, &group, id, offset)),
+        }
+    }
+
+    #[instrument(name = "topic_nack", skip_all)]
+    fn nack(self, name: String, group: String, id: u32, offset: u64) -> Result<(), KvError> {
+        let retry = {
+            let mut groups = self.groups.get_mut(&name);
+            match groups.as_mut().and_then(|v| v.get_mut(&group)) {
+                Some(v) if v.is_held_by(id, offset) => {
+                    v.retry(offset, self.ack_timeout, self.max_deliveries)
+                }
+                _ => return Err(pending_not_found(&name, &group, id, offset)),
+            }
+        };
+        // 组中没有成员时消息继续等待，有成员加入后由后台任务重新投递
+        if let Some(retry) = retry {
+            self.send_retry(&name, &group, retry);
+        }
+        Ok(())
+    }
+
     // fn unsubscribe(self, name: String, id: u32) {
     //     if let Some(v) = self.topics.get_mut(&name) {
     //         v.remove(&id);
@@ -75,35 +259,96 @@ impl Topic for Arc<Broadcaster> {
     //
     #[instrument(name = "topic_publish", skip_all)]
     fn publish(self, name: String, value: Arc<CommandResponse>) {
+        // 返回之前分配 offset，同一个 client 先后发布的消息 offset 也是先后的
+        let targets = self.append(&name, value);
         tokio::spawn(async move {
-            let mut ids = vec![];
-            if let Some(topic) = self.topics.get(&name) {
-                // 复制整个 topic 下所有的 subscription id
-                // 这里我们每个 id 是 u32，如果一个 topic 下有 10k 订阅，复制的成本
-                // 也就是 40k 堆内存（外加一些控制结构），所以效率不算差
-                // 这也是为什么我们用 NEXT_ID 来控制 subscription id 的生成
-
-                let subscriptions = topic.value().clone();
-                // 尽快释放锁
-                drop(topic);
-
-                // 循环发送
-                for id in subscriptions.into_iter() {
-                    if let Some(tx) = self.subscriptions.get(&id) {
-                        if let Err(e) = tx.send(value.clone()).await {
-                            warn!("Publish to {} failed! error: {:?}", id, e);
-                            // client 中断连接
-                            ids.push(id);
-                        }
-                    }
+            let mut closed = vec![];
+            let mut blocked = vec![];
+            for (id, value, pattern) in targets {
+                let Some(sub) = self.subscriptions.get(&id).map(|v| v.value().clone()) else {
+                    continue;
+                };
+                match sub.offer(value) {
+                    Offer::Sent | Offer::Dropped => {}
+                    Offer::Full(value) => blocked.push(async move {
+                        let sent = sub.send(value).await;
+                        (sent, id, pattern)
+                    }),
+                    // client 中断连接，或者按照溢出策略断开
+                    Offer::Closed => closed.push((id, pattern)),
                 }
             }
-            for id in ids {
-                self.remove_subscription(name.clone(), id);
+            // 同时等待所有缓冲区满了的订阅者，一个卡住的订阅者不会拖慢其他订阅者
+            for (sent, id, pattern) in join_all(blocked).await {
+                if !sent {
+                    warn!("Publish to {} failed!", id);
+                    closed.push((id, pattern));
+                }
             }
+            self.remove_closed(&name, closed);
         });
     }
 
+    fn topics(&self, pattern: &str) -> Vec<(String, usize)> {
+        let mut names: Vec<String> = self
+            .topics
+            .iter()
+            .map(|v| v.key().clone())
+            .chain(self.groups.iter().map(|v| v.key().clone()))
+            .filter(|v| pattern.is_empty() || glob_match(pattern, v))
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+            .into_iter()
+            .map(|name| {
+                let count = self.numsub(&name);
+                (name, count)
+            })
+            .collect()
+    }
+
+    fn numsub(&self, name: &str) -> usize {
+        let direct = self.topics.get(name).map(|v| v.len()).unwrap_or(0);
+        let members = self
+            .groups
+            .get(name)
+            .map(|groups| groups.values().map(|v| v.len()).sum())
+            .unwrap_or(0);
+        direct + members
+    }
+
+    fn subscription(&self, id: u32) -> Option<SubscriptionInfo> {
+        let sub = self.subscriptions.get(&id)?.value().clone();
+        let mut info = SubscriptionInfo {
+            id,
+            topic: None,
+            pattern: None,
+            group: None,
+            overflow: sub.overflow(),
+            buffered: sub.buffered(),
+            dropped: sub.dropped(),
+        };
+        let find = |index: &DashMap<String, DashSet<u32>>| {
+            index
+                .iter()
+                .find(|v| v.value().contains(&id))
+                .map(|v| v.key().clone())
+        };
+        info.topic = find(&self.topics);
+        info.pattern = find(&self.patterns);
+        if info.topic.is_none() && info.pattern.is_none() {
+            for entry in self.groups.iter() {
+                if let Some(group) = entry.value().iter().find(|(_, v)| v.contains(id)) {
+                    info.topic = Some(entry.key().clone());
+                    info.group = Some(group.0.clone());
+                    break;
+                }
+            }
+        }
+        Some(info)
+    }
+
     // fn publish(self, name: String, value: Arc<CommandResponse>) {
     //     tokio::spawn(async move {
     //         match self.topics.get(&name) {
@@ -124,22 +369,487 @@ impl Topic for Arc<Broadcaster> {
 }
 
 impl Broadcaster {
-    pub fn remove_subscription(&self, name: String, id: u32) -> Option<u32> {
-        if let Some(v) = self.topics.get_mut(&name) {
-            // 在 topics 表里找到 topic 的 subscription id，删除
-            v.remove(&id);
+    pub fn new(config: PubsubConfig) -> Self {
+        Self {
+            topics: Default::default(),
+            patterns: Default::default(),
+            subscriptions: Default::default(),
+            overflow: config.overflow,
+            retired_dropped: Default::default(),
+            logs: Default::default(),
+            retention: config.retention,
+            journal: OnceLock::new(),
+            groups: Default::default(),
+            ack_timeout: Duration::from_millis(config.ack_timeout_ms),
+            max_deliveries: config.max_deliveries.max(1),
+            group_idle_timeout: Duration::from_millis(config.group_idle_timeout_ms),
+            redeliverer: Once::new(),
+        }
+    }
+
+    /// 之后保留的消息都会交给 journal 保存，只有第一次设置的 journal 生效
+    pub fn set_journal(&self, journal: Arc<dyn Journal>) {
+        let _ = self.journal.set(journal);
+    }
+
+    /// 恢复 topic 保留的消息，messages 需要按 offset 排序
+    pub fn restore(&self, name: &str, messages: Vec<CommandResponse>) {
+        let mut log = self.logs.entry(name.into()).or_default();
+        for msg in messages {
+            log.last = log.last.max(msg.offset);
+            log.messages.push_back(Arc::new(msg));
+        }
+        self.trim(name, &mut log);
+    }
 
-            // 如果这个 topic 为空，则也删除 topic
-            if v.is_empty() {
-                info!("Topic: {:?} is deleted", &name);
-                drop(v);
-                self.topics.remove(&name);
+    /// topic 是否有订阅者，包括通过模式订阅的
+    pub fn has_subscribers(&self, name: &str) -> bool {
+        self.topics.contains_key(name)
+            || self.groups.contains_key(name)
+            || self.patterns.iter().any(|v| glob_match(v.key(), name))
+    }
+
+    /// 不等待订阅者，直接把消息放进订阅者的缓冲区，缓冲区满了的订阅者会丢掉这条消息。
+    /// 不需要 tokio runtime，可以在任何线程中调用
+    pub fn try_publish(&self, name: &str, value: Arc<CommandResponse>) {
+        let mut closed = vec![];
+        for (id, value, pattern) in self.append(name, value) {
+            let Some(sub) = self.subscriptions.get(&id).map(|v| v.value().clone()) else {
+                continue;
+            };
+            match sub.offer(value) {
+                Offer::Sent | Offer::Dropped => {}
+                Offer::Full(_) => {
+                    sub.record_drop();
+                    warn!("Subscription {} is full, message dropped", id)
+                }
+                // client 中断连接，或者按照溢出策略断开
+                Offer::Closed => closed.push((id, pattern)),
             }
         }
+        self.remove_closed(name, closed);
+    }
+
+    /// 所有 subscription 因为缓冲区满了而丢掉的消息数量，包括已经删除的 subscription
+    pub fn dropped(&self) -> u64 {
+        let live: u64 = self.subscriptions.iter().map(|v| v.dropped()).sum();
+        self.retired_dropped.load(Ordering::Relaxed) + live
+    }
+
+    /// subscription 因为缓冲区满了而丢掉的消息数量
+    pub fn subscription_dropped(&self, id: u32) -> Option<u64> {
+        self.subscriptions.get(&id).map(|v| v.dropped())
+    }
 
+    pub fn remove_subscription(&self, name: String, id: u32) -> Option<u32> {
+        remove_from(&self.topics, &name, id);
+        self.leave_groups(&name, id);
+        self.evict_log(&name);
         debug!("Subscription {} is removed!", id);
         // 在 subscription 表中同样删除
-        self.subscriptions.remove(&id).map(|(id, _)| id)
+        self.remove_subscriber(id)
+    }
+
+    pub fn remove_pattern_subscription(&self, pattern: String, id: u32) -> Option<u32> {
+        remove_from(&self.patterns, &pattern, id);
+        debug!("Pattern subscription {} is removed!", id);
+        self.remove_subscriber(id)
+    }
+
+    fn remove_subscriber(&self, id: u32) -> Option<u32> {
+        let (id, sub) = self.subscriptions.remove(&id)?;
+        self.retired_dropped
+            .fetch_add(sub.dropped(), Ordering::Relaxed);
+        Some(id)
+    }
+
+    /// 在 topics 或 patterns 中加入一个新的 subscription
+    fn add_subscription(
+        &self,
+        index: &DashMap<String, DashSet<u32>>,
+        name: String,
+        backlog: Vec<Arc<CommandResponse>>,
+        overflow: Option<OverflowPolicy>,
+    ) -> mpsc::Receiver<Arc<CommandResponse>> {
+        let (id, rx) = self.new_subscription(backlog, overflow);
+        index.entry(name).or_default().insert(id);
+        rx
+    }
+
+    /// 创建一个 subscription，第一条消息是 subscription id，之后是要重放的 backlog
+    fn new_subscription(
+        &self,
+        backlog: Vec<Arc<CommandResponse>>,
+        overflow: Option<OverflowPolicy>,
+    ) -> (u32, mpsc::Receiver<Arc<CommandResponse>>) {
+        let id = get_next_subscription_id();
+        // 订阅 id 和 backlog 在订阅生效前直接放进 channel，保证它们排在新消息之前
+        let v: Value = (id as i64).into();
+        let preload = std::iter::once(Arc::new(v.into())).chain(backlog).collect();
+        let overflow = overflow.unwrap_or(self.overflow);
+        let (sub, rx) = Subscriber::new(overflow, BROADCAST_CAPACITY, preload);
+        self.subscriptions.insert(id, Arc::new(sub));
+        debug!("Subscription {} is added", id);
+        (id, rx)
+    }
+
+    /// 给消息分配 offset 并保留下来，返回消息要发送给的 subscription。
+    /// 持有 topic 日志的锁时找出 subscription，这样每个订阅者要么从 backlog 要么从 channel 收到这条消息
+    fn append(&self, name: &str, value: Arc<CommandResponse>) -> Vec<Target> {
+        let mut log = match self.logs.get_mut(name) {
+            Some(log) => log,
+            None => self.logs.entry(name.into()).or_default(),
+        };
+        log.last += 1;
+        let mut msg = Arc::unwrap_or_clone(value);
+        msg.offset = log.last;
+        let value = Arc::new(msg);
+        if self.retention > 0 {
+            if let Some(journal) = self.journal.get() {
+                journal.append(name, &value);
+            }
+            log.messages.push_back(value.clone());
+            self.trim(name, &mut log);
+        }
+        let targets = self.targets(name, value);
+        drop(log);
+        self.evict_log(name);
+        targets
+    }
+
+    /// 不保留消息时，没有直接订阅者和消费组的 topic 不需要记录 offset，删除它的日志，
+    /// 否则发布过的每个 topic 都会一直占用内存。模式订阅可能匹配任意多的 topic，不计算在内
+    fn evict_log(&self, name: &str) {
+        if self.retention == 0 && !self.topics.contains_key(name) && !self.groups.contains_key(name)
+        {
+            self.logs.remove(name);
+        }
+    }
+
+    /// 丢掉超出 retention 的旧消息
+    fn trim(&self, name: &str, log: &mut TopicLog) {
+        while log.messages.len() > self.retention {
+            let Some(msg) = log.messages.pop_front() else {
+                break;
+            };
+            if let Some(journal) = self.journal.get() {
+                journal.remove(name, msg.offset);
+            }
+        }
+    }
+
+    /// 找出发布到 topic 的消息要发送给哪些 subscription。
+    /// 通过模式订阅的 subscription 收到的是带有 topic 名字的副本
+    fn targets(&self, name: &str, value: Arc<CommandResponse>) -> Vec<Target> {
+        let mut targets = vec![];
+        if let Some(topic) = self.topics.get(name) {
+            // 复制整个 topic 下所有的 subscription id
+            // 这里我们每个 id 是 u32，如果一个 topic 下有 10k 订阅，复制的成本
+            // 也就是 40k 堆内存（外加一些控制结构），所以效率不算差
+            // 这也是为什么我们用 NEXT_ID 来控制 subscription id 的生成
+            let subscriptions = topic.value().clone();
+            // 尽快释放锁
+            drop(topic);
+            targets.extend(
+                subscriptions
+                    .into_iter()
+                    .map(|id| (id, value.clone(), None)),
+            );
+        }
+
+        let mut with_topic = None;
+        for entry in self.patterns.iter() {
+            if !glob_match(entry.key(), name) {
+                continue;
+            }
+            let value = with_topic.get_or_insert_with(|| {
+                Arc::new(CommandResponse {
+                    topic: name.into(),
+                    ..value.as_ref().clone()
+                })
+            });
+            let pattern = entry.key();
+            targets.extend(
+                entry
+                    .value()
+                    .iter()
+                    .map(|id| (*id, value.clone(), Some(pattern.clone()))),
+            );
+        }
+
+        // 每个消费组只投递给一个成员，没有成员的消费组把消息留到有成员加入后再投递
+        if let Some(mut groups) = self.groups.get_mut(name) {
+            for group in groups.values_mut() {
+                if let Some(id) = group.deliver(value.clone(), self.ack_timeout) {
+                    targets.push((id, value.clone(), None));
+                }
+            }
+        }
+        targets
+    }
+
+    /// 删除发送失败的 subscription
+    fn remove_closed(&self, name: &str, closed: Vec<(u32, Option<String>)>) {
+        for (id, pattern) in closed {
+            match pattern {
+                Some(pattern) => self.remove_pattern_subscription(pattern, id),
+                None => self.remove_subscription(name.into(), id),
+            };
+        }
+    }
+
+    /// subscription 离开 topic 的所有消费组。成员全部离开的消费组会保留 group_idle_timeout，
+    /// 还没有确认的消息和期间发布的消息等有成员加入后重新投递
+    fn leave_groups(&self, name: &str, id: u32) {
+        if let Some(mut groups) = self.groups.get_mut(name) {
+            for group in groups.values_mut() {
+                group.leave(id);
+            }
+        }
+    }
+
+    /// 启动后台任务，定期重新投递消费组中超时没有确认的消息，Broadcaster 释放后任务退出
+    fn start_redeliverer(self: &Arc<Self>) {
+        self.redeliverer.call_once(|| {
+            let broadcaster = Arc::downgrade(self);
+            let period =
+                (self.ack_timeout / 2).clamp(Duration::from_millis(10), Duration::from_secs(1));
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(period).await;
+                    match broadcaster.upgrade() {
+                        Some(broadcaster) => broadcaster.redeliver(),
+                        None => break,
+                    }
+                }
+            });
+        });
+    }
+
+    /// 重新投递所有消费组中超时没有确认，或者负责的成员已经离开的消息。
+    /// 成员全部离开超过 group_idle_timeout 的消费组被删除，还没有确认的消息放进死信 topic
+    fn redeliver(&self) {
+        let now = Instant::now();
+        let mut retries = vec![];
+        let mut emptied = vec![];
+        // 持有 groups 的锁时只收集要发送的消息，释放锁之后再发送
+        for mut entry in self.groups.iter_mut() {
+            let name = entry.key().clone();
+            entry.value_mut().retain(|group_name, group| {
+                if !group.is_abandoned(now, self.group_idle_timeout) {
+                    return true;
+                }
+                info!("Consumer group {} of {} is removed", group_name, name);
+                let dead = mem::take(group).into_pending().map(Retry::Dead);
+                retries.extend(dead.map(|v| (name.clone(), group_name.clone(), v)));
+                false
+            });
+            if entry.value().is_empty() {
+                emptied.push(name.clone());
+            }
+            for (group_name, group) in entry.value_mut() {
+                for offset in group.expired(now) {
+                    if let Some(retry) = group.retry(offset, self.ack_timeout, self.max_deliveries)
+                    {
+                        retries.push((name.clone(), group_name.clone(), retry));
+                    }
+                }
+            }
+        }
+        for name in emptied {
+            self.groups.remove_if(&name, |_, groups| groups.is_empty());
+            self.evict_log(&name);
+        }
+        for (name, group, retry) in retries {
+            self.send_retry(&name, &group, retry);
+        }
+    }
+
+    /// 把消息重新投递给成员，或者放进消费组的死信 topic。发送失败的消息会在超时后再次投递
+    fn send_retry(&self, name: &str, group: &str, retry: Retry) {
+        match retry {
+            Retry::Deliver(id, msg) => {
+                let Some(sub) = self.subscriptions.get(&id).map(|v| v.value().clone()) else {
+                    return;
+                };
+                let offset = msg.offset;
+                match sub.offer(msg) {
+                    Offer::Sent | Offer::Dropped => {}
+                    // 没有发送出去的投递不计入次数
+                    Offer::Full(_) => {
+                        warn!("Subscription {} is full, redelivery delayed", id);
+                        let mut groups = self.groups.get_mut(name);
+                        if let Some(group) = groups.as_mut().and_then(|v| v.get_mut(group)) {
+                            group.cancel(offset);
+                        }
+                    }
+                    Offer::Closed => {
+                        self.remove_subscription(name.into(), id);
+                    }
+                }
+            }
+            Retry::Dead(msg) => {
+                warn!(
+                    "Message {} of {} exceeded max deliveries in group {}",
+                    msg.offset, name, group
+                );
+                // 死信带上原来的 topic，offset 由死信 topic 重新分配
+                let msg = CommandResponse {
+                    topic: name.into(),
+                    ..msg.as_ref().clone()
+                };
+                self.try_publish(&dead_letter_topic(name, group), Arc::new(msg));
+            }
+        }
+    }
+}
+
+fn pending_not_found(name: &str, group: &str, id: u32, offset: u64) -> KvError {
+    KvError::NotFound(format!(
+        "pending message {} of topic {} in group {} for subscription {}",
+        offset, name, group, id
+    ))
+}
+
+/// 记录一个连接上创建的 subscription，stream 或者连接结束时把它们从 Broadcaster 中删除，
+/// 不用等到下一次发布失败才清理
+#[derive(Debug, Clone)]
+pub struct Subscriptions {
+    broadcaster: Arc<Broadcaster>,
+    /// subscription id -> 它订阅的 topic 或模式
+    ids: Arc<DashMap<u32, Subscribed>>,
+}
+
+#[derive(Debug)]
+enum Subscribed {
+    Topic(String),
+    Pattern(String),
+}
+
+impl Subscriptions {
+    pub fn new(broadcaster: Arc<Broadcaster>) -> Self {
+        Self {
+            broadcaster,
+            ids: Default::default(),
+        }
+    }
+
+    /// 是否是会创建 subscription 的命令
+    pub fn is_subscribe(cmd: &CommandRequest) -> bool {
+        matches!(
+            cmd.request_data,
+            Some(RequestData::Subscribe(_) | RequestData::Psubscribe(_))
+        )
+    }
+
+    /// 根据订阅命令返回的第一条消息记录 subscription id，订阅失败时返回 None
+    pub fn track(&self, cmd: &CommandRequest, res: &CommandResponse) -> Option<u32> {
+        let subscribed = match &cmd.request_data {
+            Some(RequestData::Subscribe(v)) => Subscribed::Topic(v.topic.clone()),
+            Some(RequestData::Psubscribe(v)) => Subscribed::Pattern(v.pattern.clone()),
+            _ => return None,
+        };
+        let id: i64 = res.try_into().ok()?;
+        self.ids.insert(id as u32, subscribed);
+        Some(id as u32)
+    }
+
+    /// 从 Broadcaster 中删除 subscription，订阅者会收到 stream 的结束
+    pub fn remove(&self, id: u32) {
+        let Some((id, subscribed)) = self.ids.remove(&id) else {
+            return;
+        };
+        match subscribed {
+            Subscribed::Topic(name) => self.broadcaster.remove_subscription(name, id),
+            Subscribed::Pattern(pattern) => {
+                self.broadcaster.remove_pattern_subscription(pattern, id)
+            }
+        };
+    }
+
+    /// 删除记录的所有 subscription
+    pub fn clear(&self) {
+        let ids: Vec<u32> = self.ids.iter().map(|v| *v.key()).collect();
+        for id in ids {
+            self.remove(id);
+        }
+    }
+}
+
+/// 从 topics 或 patterns 中删除 subscription id
+fn remove_from(index: &DashMap<String, DashSet<u32>>, name: &str, id: u32) {
+    if let Some(v) = index.get_mut(name) {
+        // 在表里找到 topic 的 subscription id，删除
+        v.remove(&id);
+
+        // 如果这个 topic 为空，则也删除 topic
+        if v.is_empty() {
+            info!("Topic: {:?} is deleted", name);
+            drop(v);
+            index.remove(name);
+        }
+    }
+}
+
+/// 用 glob 模式匹配 topic 名字：`*` 匹配任意多个字符，`?` 匹配一个字符，
+/// `[abc]` 和 `[a-z]` 匹配其中的一个字符，`[^abc]` 匹配不在其中的字符，`\` 转义下一个字符
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let s: Vec<char> = name.chars().collect();
+    let (mut pi, mut si) = (0, 0);
+    // 最近一个 * 在模式中的位置，以及它后面的部分从名字的哪里开始匹配
+    let mut star = None;
+    while si < s.len() {
+        if p.get(pi) == Some(&'*') {
+            star = Some((pi, si));
+            pi += 1;
+            continue;
+        }
+        if let Some(len) = p.get(pi).and_then(|_| match_one(&p[pi..], s[si])) {
+            pi += len;
+            si += 1;
+            continue;
+        }
+        // 失配时回溯，让最近的 * 多匹配一个字符
+        match star {
+            Some((sp, ss)) => {
+                star = Some((sp, ss + 1));
+                pi = sp + 1;
+                si = ss + 1;
+            }
+            None => return false,
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+/// 用模式开头的一个元素匹配字符 c，匹配时返回这个元素在模式中占的长度
+fn match_one(p: &[char], c: char) -> Option<usize> {
+    match p[0] {
+        '?' => Some(1),
+        '\\' if p.len() > 1 => (p[1] == c).then_some(2),
+        '[' => {
+            let negate = p.get(1) == Some(&'^');
+            let start = if negate { 2 } else { 1 };
+            // 没有闭合的 [ 当作普通字符
+            let Some(end) = p[start..].iter().position(|v| *v == ']').map(|i| start + i) else {
+                return (c == '[').then_some(1);
+            };
+            let class = &p[start..end];
+            let mut matched = false;
+            let mut i = 0;
+            while i < class.len() {
+                if i + 2 < class.len() && class[i + 1] == '-' {
+                    matched |= class[i] <= c && c <= class[i + 2];
+                    i += 3;
+                } else {
+                    matched |= class[i] == c;
+                    i += 1;
+                }
+            }
+            (matched != negate).then_some(end + 1)
+        }
+        v => (v == c).then_some(1),
     }
 }
 
@@ -149,6 +859,7 @@ mod tests {
 
     use super::*;
     use std::convert::TryInto;
+    use tokio::time;
 
     #[tokio::test]
     async fn pub_sub_should_work() {
@@ -169,7 +880,7 @@ mod tests {
         let res2 = stream2.recv().await.unwrap();
 
         assert_eq!(res1, res2);
-        assert_res_ok(&res1, &[v.clone()], &[]);
+        assert_res_ok(&res1, &[v], &[]);
 
         // 如果 subscriber 取消订阅，则收不到新数据
         let _ = b.clone().unsubscribe(lobby.clone(), id1 as _);
@@ -180,6 +891,341 @@ mod tests {
 
         assert!(stream1.recv().await.is_none());
         let res2 = stream2.recv().await.unwrap();
-        assert_res_ok(&res2, &[v.clone()], &[]);
+        assert_res_ok(&res2, &[v], &[]);
+    }
+
+    #[tokio::test]
+    async fn try_publish_should_drop_message_when_subscriber_is_full() {
+        let b = Arc::new(Broadcaster::default());
+        let lobby = "lobby".to_string();
+        let mut stream = b.clone().subscribe(lobby.clone());
+        // 等待订阅 id 发送出去
+        stream.recv().await.unwrap();
+
+        for i in 0..BROADCAST_CAPACITY + 1 {
+            let v: Value = (i as i64).into();
+            b.try_publish(&lobby, Arc::new(v.into()));
+        }
+        for i in 0..BROADCAST_CAPACITY {
+            let res = stream.recv().await.unwrap();
+            assert_res_ok(&res, &[(i as i64).into()], &[]);
+        }
+        assert!(stream.try_recv().is_err());
+
+        // 订阅者断开后被移除
+        drop(stream);
+        b.try_publish(&lobby, Arc::new(CommandResponse::ok()));
+        assert!(!b.has_subscribers(&lobby));
+    }
+
+    #[tokio::test]
+    async fn psubscribe_should_receive_messages_of_matching_topics() {
+        let b = Arc::new(Broadcaster::default());
+        let mut stream = b.clone().psubscribe("orders.*".into(), None);
+        let id: i64 = stream.recv().await.unwrap().as_ref().try_into().unwrap();
+        let mut exact = b.clone().subscribe("orders.new".into());
+        exact.recv().await.unwrap();
+
+        let v: Value = "hello".into();
+        b.clone()
+            .publish("orders.new".into(), Arc::new(v.clone().into()));
+        b.clone()
+            .publish("users.new".into(), Arc::new(v.clone().into()));
+        b.clone()
+            .publish("orders.paid".into(), Arc::new(v.clone().into()));
+
+        // 模式订阅收到的消息带有 topic 的名字，精确订阅的不带
+        let res = stream.recv().await.unwrap();
+        assert_eq!(res.topic, "orders.new");
+        assert_res_ok(&res, &["hello".into()], &[]);
+        let res = stream.recv().await.unwrap();
+        assert_eq!(res.topic, "orders.paid");
+        let res = exact.recv().await.unwrap();
+        assert_eq!(res.topic, "");
+        assert!(b.has_subscribers("orders.x"));
+
+        b.clone().punsubscribe("orders.*".into(), id as _).unwrap();
+        assert!(stream.recv().await.is_none());
+        assert!(!b.has_subscribers("orders.x"));
+        assert!(b.clone().punsubscribe("orders.*".into(), id as _).is_err());
+    }
+
+    #[tokio::test]
+    async fn subscribe_from_should_replay_retained_messages() {
+        let b = Arc::new(Broadcaster::new(PubsubConfig {
+            retention: 3,
+            ..Default::default()
+        }));
+        let lobby = "lobby".to_string();
+        for i in 1..=5 {
+            let v: Value = (i as i64).into();
+            b.try_publish(&lobby, Arc::new(v.into()));
+        }
+
+        let offsets = |rx: &mut mpsc::Receiver<Arc<CommandResponse>>| {
+            let mut offsets = vec![];
+            while let Ok(res) = rx.try_recv() {
+                offsets.push(res.offset);
+            }
+            offsets
+        };
+        // 第一条消息是订阅 id，它没有 offset
+        let mut earliest = b.subscribe_from(lobby.clone(), Some(0), None);
+        assert_eq!(offsets(&mut earliest), [0, 3, 4, 5]);
+        let mut from = b.subscribe_from(lobby.clone(), Some(4), None);
+        assert_eq!(offsets(&mut from), [0, 4, 5]);
+        let mut latest = b.subscribe(lobby.clone());
+        assert_eq!(offsets(&mut latest), [0]);
+
+        b.try_publish(&lobby, Arc::new(CommandResponse::ok()));
+        for rx in [&mut earliest, &mut from, &mut latest] {
+            assert_eq!(offsets(rx), [6]);
+        }
+    }
+
+    #[tokio::test]
+    async fn publish_should_assign_offsets_in_order() {
+        let b = Arc::new(Broadcaster::default());
+        let lobby = "lobby".to_string();
+        let mut stream = b.clone().subscribe(lobby.clone());
+        stream.recv().await.unwrap();
+        for _ in 0..10 {
+            b.clone()
+                .publish(lobby.clone(), Arc::new(CommandResponse::ok()));
+        }
+        // 没有等待后台任务，offset 已经按发布的顺序分配好
+        assert_eq!(b.logs.get(&lobby).unwrap().last, 10);
+        let mut offsets = vec![];
+        for _ in 0..10 {
+            offsets.push(stream.recv().await.unwrap().offset);
+        }
+        offsets.sort();
+        assert_eq!(offsets, (1..=10).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn topics_without_retention_should_not_keep_logs() {
+        let b = Arc::new(Broadcaster::default());
+        let lobby = "lobby".to_string();
+        b.try_publish(&lobby, Arc::new(CommandResponse::ok()));
+        assert!(b.logs.is_empty());
+
+        let mut stream = b.clone().subscribe(lobby.clone());
+        let id: i64 = stream.recv().await.unwrap().as_ref().try_into().unwrap();
+        b.try_publish(&lobby, Arc::new(CommandResponse::ok()));
+        assert_eq!(stream.recv().await.unwrap().offset, 1);
+        assert!(b.logs.contains_key(&lobby));
+
+        // 最后一个订阅者离开后删除日志
+        b.clone().unsubscribe(lobby.clone(), id as _).unwrap();
+        assert!(b.logs.is_empty());
+    }
+
+    #[tokio::test]
+    async fn consumer_group_should_ack_redeliver_and_dead_letter() {
+        let b = Arc::new(Broadcaster::new(PubsubConfig {
+            ack_timeout_ms: 20,
+            max_deliveries: 2,
+            ..Default::default()
+        }));
+        let (lobby, group) = ("lobby".to_string(), "workers".to_string());
+        let mut dlq = b.subscribe(dead_letter_topic(&lobby, &group));
+        dlq.recv().await.unwrap();
+        let mut m1 = b.subscribe_group(lobby.clone(), group.clone(), None);
+        let mut m2 = b.subscribe_group(lobby.clone(), group.clone(), None);
+        let id1: i64 = m1.recv().await.unwrap().as_ref().try_into().unwrap();
+        let id2: i64 = m2.recv().await.unwrap().as_ref().try_into().unwrap();
+        let (id1, id2) = (id1 as u32, id2 as u32);
+
+        // 消息轮流投递给组中的成员
+        for i in 1..=2 {
+            let v: Value = (i as i64).into();
+            b.try_publish(&lobby, Arc::new(v.into()));
+        }
+        assert_eq!(m1.recv().await.unwrap().offset, 1);
+        assert_eq!(m2.recv().await.unwrap().offset, 2);
+        // 只有负责这条消息的成员可以确认
+        assert!(b.clone().ack(lobby.clone(), group.clone(), id2, 1).is_err());
+        b.clone().ack(lobby.clone(), group.clone(), id1, 1).unwrap();
+        assert!(b.clone().ack(lobby.clone(), group.clone(), id1, 1).is_err());
+
+        // nack 之后立即投递给下一个成员，再次失败后进入死信 topic
+        assert!(b
+            .clone()
+            .nack(lobby.clone(), group.clone(), id1, 2)
+            .is_err());
+        b.clone()
+            .nack(lobby.clone(), group.clone(), id2, 2)
+            .unwrap();
+        assert_eq!(m1.recv().await.unwrap().offset, 2);
+        let res = dlq.recv().await.unwrap();
+        assert_eq!(res.topic, lobby);
+        assert_res_ok(&res, &[2.into()], &[]);
+        assert!(b
+            .clone()
+            .nack(lobby.clone(), group.clone(), id1, 2)
+            .is_err());
+
+        drop(m1);
+        drop(m2);
+        b.try_publish(&lobby, Arc::new(CommandResponse::ok()));
+        b.try_publish(&lobby, Arc::new(CommandResponse::ok()));
+        assert_eq!(b.numsub(&lobby), 0);
+        assert!(b.has_subscribers(&lobby));
+    }
+
+    #[tokio::test]
+    async fn consumer_group_should_keep_pending_messages_until_member_rejoins() {
+        let b = Arc::new(Broadcaster::new(PubsubConfig {
+            ack_timeout_ms: 20,
+            group_idle_timeout_ms: 100,
+            ..Default::default()
+        }));
+        let (lobby, group) = ("lobby".to_string(), "workers".to_string());
+        let mut dlq = b.subscribe(dead_letter_topic(&lobby, &group));
+        dlq.recv().await.unwrap();
+        let mut m1 = b.subscribe_group(lobby.clone(), group.clone(), None);
+        let id1: i64 = m1.recv().await.unwrap().as_ref().try_into().unwrap();
+        b.try_publish(&lobby, Arc::new(CommandResponse::ok()));
+        assert_eq!(m1.recv().await.unwrap().offset, 1);
+
+        // 唯一的成员离开，没有确认的消息和之后发布的消息都留在消费组中
+        b.clone().unsubscribe(lobby.clone(), id1 as _).unwrap();
+        assert!(b.has_subscribers(&lobby));
+        b.try_publish(&lobby, Arc::new(CommandResponse::ok()));
+
+        let mut m2 = b.subscribe_group(lobby.clone(), group.clone(), None);
+        let id2: i64 = m2.recv().await.unwrap().as_ref().try_into().unwrap();
+        assert_eq!(m2.recv().await.unwrap().offset, 1);
+        assert_eq!(m2.recv().await.unwrap().offset, 2);
+        assert!(b
+            .clone()
+            .ack(lobby.clone(), group.clone(), id1 as _, 1)
+            .is_err());
+        b.clone()
+            .ack(lobby.clone(), group.clone(), id2 as _, 1)
+            .unwrap();
+        b.clone()
+            .ack(lobby.clone(), group.clone(), id2 as _, 2)
+            .unwrap();
+
+        // 成员全部离开超过 group_idle_timeout 后删除消费组，没有确认的消息放进死信 topic
+        b.clone().unsubscribe(lobby.clone(), id2 as _).unwrap();
+        b.try_publish(&lobby, Arc::new(CommandResponse::ok()));
+        assert!(b.has_subscribers(&lobby));
+        let res = time::timeout(Duration::from_secs(1), dlq.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(res.topic, lobby);
+        assert!(!b.has_subscribers(&lobby));
+    }
+
+    #[tokio::test]
+    async fn slow_subscriber_should_not_stall_others() {
+        let b = Arc::new(Broadcaster::default());
+        let lobby = "lobby".to_string();
+        // 不读取消息的订阅者
+        let _stuck = b.subscribe(lobby.clone());
+        let mut newest = b.subscribe_from(lobby.clone(), None, Some(OverflowPolicy::DropNewest));
+        let mut fast = b.subscribe(lobby.clone());
+        newest.recv().await.unwrap();
+        fast.recv().await.unwrap();
+
+        let count = BROADCAST_CAPACITY * 2;
+        for i in 0..count {
+            let v: Value = (i as i64).into();
+            b.clone().publish(lobby.clone(), Arc::new(v.into()));
+        }
+        for _ in 0..count {
+            time::timeout(Duration::from_secs(1), fast.recv())
+                .await
+                .unwrap()
+                .unwrap();
+        }
+        // DropNewest 的订阅者只收到缓冲区放得下的消息
+        let mut received = 0;
+        while newest.try_recv().is_ok() {
+            received += 1;
+        }
+        assert_eq!(received, BROADCAST_CAPACITY);
+        assert_eq!(b.dropped(), count as u64 - BROADCAST_CAPACITY as u64);
+    }
+
+    #[tokio::test]
+    async fn disconnect_policy_should_remove_full_subscriber() {
+        let b = Arc::new(Broadcaster::new(PubsubConfig {
+            overflow: OverflowPolicy::Disconnect,
+            ..Default::default()
+        }));
+        let lobby = "lobby".to_string();
+        let mut rx = b.subscribe(lobby.clone());
+        let id: i64 = rx.recv().await.unwrap().as_ref().try_into().unwrap();
+        for _ in 0..BROADCAST_CAPACITY + 1 {
+            b.try_publish(&lobby, Arc::new(CommandResponse::ok()));
+        }
+        assert!(b.subscription_dropped(id as _).is_none());
+        assert!(!b.has_subscribers(&lobby));
+        assert_eq!(b.dropped(), 1);
+        // 已经放进缓冲区的消息仍然可以读完
+        for _ in 0..BROADCAST_CAPACITY {
+            rx.recv().await.unwrap();
+        }
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn introspection_should_report_topics_and_subscriptions() {
+        let b = Arc::new(Broadcaster::default());
+        let mut s1 = b.subscribe("orders.new".into());
+        let _s2 = b.subscribe("orders.new".into());
+        let _s3 = b.subscribe_group("orders.paid".into(), "g1".into(), None);
+        let mut p = b.psubscribe("orders.*".into(), Some(OverflowPolicy::DropNewest));
+        let _users = b.subscribe("users.new".into());
+
+        assert_eq!(
+            b.topics("orders.*"),
+            [
+                ("orders.new".to_string(), 2),
+                ("orders.paid".to_string(), 1)
+            ]
+        );
+        assert_eq!(b.topics("").len(), 3);
+        assert_eq!(b.numsub("orders.new"), 2);
+        assert_eq!(b.numsub("orders.x"), 0);
+
+        let id: i64 = s1.recv().await.unwrap().as_ref().try_into().unwrap();
+        b.try_publish("orders.new", Arc::new(CommandResponse::ok()));
+        let info = b.subscription(id as _).unwrap();
+        assert_eq!(info.topic.as_deref(), Some("orders.new"));
+        assert_eq!(info.overflow, OverflowPolicy::Block);
+        assert_eq!(info.buffered, 1);
+
+        let id: i64 = p.recv().await.unwrap().as_ref().try_into().unwrap();
+        let info = b.subscription(id as _).unwrap();
+        assert_eq!(info.pattern.as_deref(), Some("orders.*"));
+        assert_eq!(info.topic, None);
+        assert_eq!(info.overflow, OverflowPolicy::DropNewest);
+        assert!(b.subscription(0).is_none());
+    }
+
+    #[test]
+    fn glob_match_should_work() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("orders.*", "orders.new"));
+        assert!(!glob_match("orders.*", "order.new"));
+        assert!(glob_match("*.new", "orders.new"));
+        assert!(glob_match("a*b*c", "aXXbYbc"));
+        assert!(!glob_match("a*b*c", "aXXbYbcd"));
+        assert!(glob_match("h?llo", "hello"));
+        assert!(!glob_match("h?llo", "hllo"));
+        assert!(glob_match("h[ae]llo", "hallo"));
+        assert!(!glob_match("h[ae]llo", "hillo"));
+        assert!(glob_match("h[^e]llo", "hallo"));
+        assert!(!glob_match("h[^e]llo", "hello"));
+        assert!(glob_match("v[0-9]", "v7"));
+        assert!(glob_match("a\\*", "a*"));
+        assert!(!glob_match("a\\*", "ab"));
+        assert!(glob_match("a[b", "a[b"));
     }
 }
@@ -2,7 +2,11 @@ use futures::{stream, Stream};
 use std::{pin::Pin, sync::Arc};
 use tokio_stream::wrappers::ReceiverStream;
 
-use crate::{CommandResponse, Publish, Subscribe, Topic, Unsubscribe};
+use crate::{
+    subscribe::Start, Ack, CommandResponse, KvError, Kvpair, Nack, OverflowPolicy, Psubscribe,
+    Publish, PubsubNumsub, PubsubSubscription, PubsubTopics, Punsubscribe, Subscribe,
+    SubscriptionInfo, Topic, Unsubscribe, Value, DEAD_LETTER_TOPIC_PREFIX, KEYSPACE_TOPIC_PREFIX,
+};
 
 pub type StreamingResponse = Pin<Box<dyn Stream<Item = Arc<CommandResponse>> + Send>>;
 
@@ -26,7 +30,21 @@ impl TopicService for Subscribe {
     fn execute(self, topic: impl Topic) -> StreamingResponse {
         // let rx = topic.subscribe(self.topic);
         // Box::pin(ReceiverStream::new(rx))
-        let rx = topic.subscribe(self.topic);
+        let overflow = match parse_overflow(&self.overflow) {
+            Ok(v) => v,
+            Err(e) => return error_stream(e),
+        };
+        // 消费组只接收订阅之后发布的消息，忽略 start
+        if !self.group.is_empty() {
+            let rx = topic.subscribe_group(self.topic, self.group, overflow);
+            return Box::pin(ReceiverStream::new(rx));
+        }
+        let from = match self.start {
+            Some(Start::Earliest(_)) => Some(0),
+            Some(Start::Offset(offset)) => Some(offset),
+            None => None,
+        };
+        let rx = topic.subscribe_from(self.topic, from, overflow);
         Box::pin(ReceiverStream::new(rx))
     }
 }
@@ -43,23 +61,145 @@ impl TopicService for Unsubscribe {
     }
 }
 
+impl TopicService for Psubscribe {
+    fn execute(self, topic: impl Topic) -> StreamingResponse {
+        let overflow = match parse_overflow(&self.overflow) {
+            Ok(v) => v,
+            Err(e) => return error_stream(e),
+        };
+        let rx = topic.psubscribe(self.pattern, overflow);
+        Box::pin(ReceiverStream::new(rx))
+    }
+}
+
+impl TopicService for Punsubscribe {
+    fn execute(self, topic: impl Topic) -> StreamingResponse {
+        let res = match topic.punsubscribe(self.pattern, self.id) {
+            Ok(_) => CommandResponse::ok(),
+            Err(e) => e.into(),
+        };
+        Box::pin(stream::once(async { Arc::new(res) }))
+    }
+}
+
+impl TopicService for Ack {
+    fn execute(self, topic: impl Topic) -> StreamingResponse {
+        let res = match topic.ack(self.topic, self.group, self.id, self.offset) {
+            Ok(_) => CommandResponse::ok(),
+            Err(e) => e.into(),
+        };
+        Box::pin(stream::once(async { Arc::new(res) }))
+    }
+}
+
+impl TopicService for Nack {
+    fn execute(self, topic: impl Topic) -> StreamingResponse {
+        let res = match topic.nack(self.topic, self.group, self.id, self.offset) {
+            Ok(_) => CommandResponse::ok(),
+            Err(e) => e.into(),
+        };
+        Box::pin(stream::once(async { Arc::new(res) }))
+    }
+}
+
 impl TopicService for Publish {
     fn execute(self, topic: impl Topic) -> StreamingResponse {
         // topic.publish(self.topic, Arc::new(self.dat.into()));
         // Box::pin(stream::once(async { Arc::new(CommandResponse::ok()) }))
 
+        // keyspace 通知和死信的 topic 只能由 Service 发布
+        if self.topic.starts_with(KEYSPACE_TOPIC_PREFIX)
+            || self.topic.starts_with(DEAD_LETTER_TOPIC_PREFIX)
+        {
+            let res: CommandResponse =
+                KvError::InvalidCommand(format!("topic {} is reserved", self.topic)).into();
+            return Box::pin(stream::once(async { Arc::new(res) }));
+        }
         topic.publish(self.topic, Arc::new(self.dat.into()));
         Box::pin(stream::once(async { Arc::new(CommandResponse::ok()) }))
     }
 }
 
+impl TopicService for PubsubTopics {
+    fn execute(self, topic: impl Topic) -> StreamingResponse {
+        let pairs: Vec<Kvpair> = topic
+            .topics(&self.pattern)
+            .into_iter()
+            .map(|(name, count)| Kvpair::new(name, (count as i64).into()))
+            .collect();
+        let res: CommandResponse = pairs.into();
+        Box::pin(stream::once(async { Arc::new(res) }))
+    }
+}
+
+impl TopicService for PubsubNumsub {
+    fn execute(self, topic: impl Topic) -> StreamingResponse {
+        let pairs: Vec<Kvpair> = self
+            .topics
+            .into_iter()
+            .map(|name| {
+                let count = topic.numsub(&name) as i64;
+                Kvpair::new(name, count.into())
+            })
+            .collect();
+        let res: CommandResponse = pairs.into();
+        Box::pin(stream::once(async { Arc::new(res) }))
+    }
+}
+
+impl TopicService for PubsubSubscription {
+    fn execute(self, topic: impl Topic) -> StreamingResponse {
+        let res: CommandResponse = match topic.subscription(self.id) {
+            Some(info) => subscription_pairs(info).into(),
+            None => KvError::NotFound(format!("subscription {}", self.id)).into(),
+        };
+        Box::pin(stream::once(async { Arc::new(res) }))
+    }
+}
+
+/// 把 subscription 的状态转换成 Kvpair，没有的字段不返回
+fn subscription_pairs(info: SubscriptionInfo) -> Vec<Kvpair> {
+    let mut pairs = vec![Kvpair::new("id", (info.id as i64).into())];
+    let names = [
+        ("topic", info.topic),
+        ("pattern", info.pattern),
+        ("group", info.group),
+    ];
+    for (key, name) in names {
+        if let Some(name) = name {
+            pairs.push(Kvpair::new(key, name.into()));
+        }
+    }
+    let overflow: Value = info.overflow.as_str().into();
+    pairs.push(Kvpair::new("overflow", overflow));
+    pairs.push(Kvpair::new("buffered", (info.buffered as i64).into()));
+    pairs.push(Kvpair::new("dropped", (info.dropped as i64).into()));
+    pairs
+}
+
+/// 解析订阅命令中的溢出策略，为空时使用 Broadcaster 默认的策略
+fn parse_overflow(overflow: &str) -> Result<Option<OverflowPolicy>, KvError> {
+    match overflow {
+        "" => Ok(None),
+        v => v.parse().map(Some),
+    }
+}
+
+fn error_stream(e: KvError) -> StreamingResponse {
+    let res: CommandResponse = e.into();
+    Box::pin(stream::once(async { Arc::new(res) }))
+}
+
 #[cfg(test)]
 mod tests {
     use std::time::Duration;
 
     use tokio::time;
 
-    use crate::{assert_res_ok, dispatch_stream, service::topic::Broadcaster, CommandRequest};
+    use crate::{
+        assert_res_error, assert_res_ok, command_request::RequestData, dispatch_stream,
+        service::topic::Broadcaster, CommandRequest,
+    };
     use futures::StreamExt;
 
     use super::*;
@@ -73,6 +213,62 @@ mod tests {
         assert_res_ok(&data, &[], &[]);
     }
 
+    #[tokio::test]
+    async fn dispatch_publish_to_keyspace_topic_should_fail() {
+        let topic = Arc::new(Broadcaster::default());
+        let cmd = CommandRequest::new_publish("__keyspace:t1", vec!["hello".into()]);
+        let mut res = dispatch_stream(cmd, topic);
+        let data = res.next().await.unwrap();
+        assert_res_error(&data, 400, "reserved");
+    }
+
+    #[tokio::test]
+    async fn dispatch_subscribe_with_unknown_overflow_should_fail() {
+        let topic = Arc::new(Broadcaster::default());
+        let mut cmd = CommandRequest::new_subscribe("t1");
+        if let Some(RequestData::Subscribe(v)) = &mut cmd.request_data {
+            v.overflow = "DropAll".into();
+        }
+        let mut res = dispatch_stream(cmd, topic.clone());
+        assert_res_error(&res.next().await.unwrap(), 400, "overflow");
+        assert!(res.next().await.is_none());
+
+        let cmd = CommandRequest::new_psubscribe("t*").with_overflow(OverflowPolicy::DropNewest);
+        let mut res = dispatch_stream(cmd, topic);
+        assert!(get_id(&mut res).await > 0);
+    }
+
+    #[tokio::test]
+    async fn dispatch_pubsub_introspection_should_work() {
+        let topic = Arc::new(Broadcaster::default());
+        let mut res = dispatch_stream(CommandRequest::new_subscribe("t1"), topic.clone());
+        let id = get_id(&mut res).await;
+
+        let cmd = CommandRequest::new_pubsub_topics("t*");
+        let data = dispatch_stream(cmd, topic.clone()).next().await.unwrap();
+        assert_res_ok(&data, &[], &[Kvpair::new("t1", 1.into())]);
+
+        let cmd = CommandRequest::new_pubsub_numsub(vec!["t1".into(), "t2".into()]);
+        let data = dispatch_stream(cmd, topic.clone()).next().await.unwrap();
+        let pairs = [Kvpair::new("t1", 1.into()), Kvpair::new("t2", 0.into())];
+        assert_res_ok(&data, &[], &pairs);
+
+        let cmd = CommandRequest::new_pubsub_subscription(id);
+        let data = dispatch_stream(cmd, topic.clone()).next().await.unwrap();
+        let pairs = [
+            Kvpair::new("id", (id as i64).into()),
+            Kvpair::new("topic", "t1".into()),
+            Kvpair::new("overflow", "Block".into()),
+            Kvpair::new("buffered", 0.into()),
+            Kvpair::new("dropped", 0.into()),
+        ];
+        assert_res_ok(&data, &[], &pairs);
+
+        let cmd = CommandRequest::new_pubsub_subscription(0);
+        let data = dispatch_stream(cmd, topic).next().await.unwrap();
+        assert_res_error(&data, 404, "subscription");
+    }
+
     #[tokio::test]
     async fn dispatch_subscribe_should_work() {
         let topic = Arc::new(Broadcaster::default());
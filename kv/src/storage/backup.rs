@@ -0,0 +1,169 @@
+use super::{with_suffix, Storage};
+use crate::{
+    backup_record::Record, network::read_frame_blocking, BackupFooter, BackupHeader, BackupRecord,
+    FrameCoder, KvError, WriteBatch, WriteOp,
+};
+use bytes::BytesMut;
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, ErrorKind, Write},
+    path::Path,
+    time::Duration,
+};
+
+/// 备份文件格式的版本
+pub const BACKUP_VERSION: u32 = 1;
+/// 恢复时每次提交的 key 数量
+const RESTORE_BATCH_SIZE: usize = 1024;
+
+/// 把 store 中所有 table 的数据备份到 path，返回备份的 key 数量。
+/// 先写入临时文件，完成后再替换 path，不会留下不完整的备份
+pub fn backup(store: &impl Storage, path: impl AsRef<Path>) -> Result<usize, KvError> {
+    let path = path.as_ref();
+    let tmp_path = with_suffix(path, ".tmp");
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+    let mut buf = BytesMut::new();
+    let mut write = |record: Record, ttl: Option<Duration>| -> Result<(), KvError> {
+        let record = BackupRecord {
+            record: Some(record),
+            // 剩余时间不足 1ms 的 key 也要保留过期时间，0 表示不过期
+            ttl: ttl.map_or(0, |ttl| (ttl.as_millis() as u64).max(1)),
+        };
+        buf.clear();
+        record.encode_frame(&mut buf)?;
+        writer.write_all(&buf)?;
+        Ok(())
+    };
+
+    let header = BackupHeader {
+        version: BACKUP_VERSION,
+    };
+    write(Record::Header(header), None)?;
+    let mut current: Option<String> = None;
+    let mut count = 0;
+    store.export(|table, pair, ttl| {
+        if current.as_deref() != Some(table) {
+            write(Record::Table(table.into()), None)?;
+            current = Some(table.into());
+        }
+        count += 1;
+        write(Record::Pair(pair), ttl)
+    })?;
+    let footer = BackupFooter {
+        count: count as u64,
+    };
+    write(Record::Footer(footer), None)?;
+
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    Ok(count)
+}
+
+/// 把 path 中的备份写入 store，已有的同名 key 会被覆盖，返回恢复的 key 数量。
+/// 数据按批提交，备份文件损坏时已经提交的数据不会回滚
+pub fn restore(store: &impl Storage, path: impl AsRef<Path>) -> Result<usize, KvError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut buf = BytesMut::new();
+    let mut next = || -> Result<BackupRecord, KvError> {
+        buf.clear();
+        match read_frame_blocking(&mut reader, &mut buf) {
+            Ok(()) => BackupRecord::decode_frame(&mut buf),
+            Err(KvError::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => {
+                Err(invalid_backup("file is truncated"))
+            }
+            Err(e) => Err(e),
+        }
+    };
+
+    match next()?.record {
+        Some(Record::Header(header)) if header.version == BACKUP_VERSION => {}
+        Some(Record::Header(header)) => {
+            return Err(invalid_backup(format!(
+                "unsupported version {}",
+                header.version
+            )))
+        }
+        _ => return Err(invalid_backup("missing header")),
+    }
+
+    let mut table = None;
+    let mut batch = WriteBatch::default();
+    let mut count = 0;
+    loop {
+        let record = next()?;
+        match record.record {
+            Some(Record::Table(name)) => table = Some(name),
+            Some(Record::Pair(pair)) => {
+                let table = table
+                    .clone()
+                    .ok_or_else(|| invalid_backup("pair without table"))?;
+                batch.writes.push(WriteOp::Set {
+                    table,
+                    key: pair.key,
+                    value: pair.value.unwrap_or_default(),
+                    ttl: (record.ttl > 0).then(|| Duration::from_millis(record.ttl)),
+                });
+                count += 1;
+                if batch.writes.len() == RESTORE_BATCH_SIZE {
+                    store.commit(std::mem::take(&mut batch))?;
+                }
+            }
+            Some(Record::Footer(footer)) if footer.count == count as u64 => break,
+            Some(Record::Footer(_)) => return Err(invalid_backup("key count mismatch")),
+            _ => return Err(invalid_backup("unexpected record")),
+        }
+    }
+    if !batch.writes.is_empty() {
+        store.commit(batch)?;
+    }
+    Ok(count)
+}
+
+fn invalid_backup(reason: impl AsRef<str>) -> KvError {
+    KvError::InvalidCommand(format!("Invalid backup file: {}", reason.as_ref()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemTable, SledDb, Value};
+    use tempfile::tempdir;
+
+    #[test]
+    fn backup_and_restore_should_move_data_between_stores() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("kv.backup");
+        let store = MemTable::new();
+        store.set("t1", "k1", "v1").unwrap();
+        store.set("t1", "k2", 2).unwrap();
+        store
+            .set_with_ttl("t2", "k1", "v3", Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(backup(&store, &path).unwrap(), 3);
+
+        let target = SledDb::new(dir.path().join("sled"));
+        assert_eq!(restore(&target, &path).unwrap(), 3);
+        assert_eq!(target.list_tables().unwrap(), ["t1", "t2"]);
+        assert_eq!(target.get("t1", "k1").unwrap(), Some("v1".into()));
+        assert_eq!(target.get("t1", "k2").unwrap(), Some(Value::from(2)));
+        assert!(target.ttl("t2", "k1").unwrap().unwrap() > Duration::from_secs(50));
+        assert!(target.ttl("t1", "k1").unwrap().is_none());
+    }
+
+    #[test]
+    fn restore_should_reject_truncated_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("kv.backup");
+        let store = MemTable::new();
+        store.set("t1", "k1", "v1").unwrap();
+        backup(&store, &path).unwrap();
+        let data = fs::read(&path).unwrap();
+        fs::write(&path, &data[..data.len() - 1]).unwrap();
+
+        let err = restore(&MemTable::new(), &path).unwrap_err();
+        assert!(matches!(err, KvError::InvalidCommand(_)));
+    }
+}
@@ -0,0 +1,569 @@
+use crate::{value, KvError, ScoredMember, Value, ValueList, ValueSet, ValueZset};
+use prost::{encoding::encoded_len_varint, Message};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
+    ops::Bound::{Excluded, Unbounded},
+};
+
+/// 读取列表，key 不存在时是空列表，值不是列表时返回 ConvertError
+fn to_list(v: Option<&Value>) -> Result<VecDeque<Value>, KvError> {
+    match v {
+        Some(v) => Ok(ValueList::try_from(v)?.values.into()),
+        None => Ok(VecDeque::new()),
+    }
+}
+
+/// 读取集合，key 不存在时是空集合，值不是集合时返回 ConvertError
+fn to_set(v: Option<&Value>) -> Result<Vec<Value>, KvError> {
+    match v {
+        Some(v) => Ok(ValueSet::try_from(v)?.values),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// 读取有序集合的 member，key 不存在时是空集合，值不是有序集合时返回 ConvertError
+fn zset_members(v: Option<&Value>) -> Result<&[ScoredMember], KvError> {
+    match v {
+        Some(Value {
+            value: Some(value::Value::Zset(zset)),
+        }) => Ok(&zset.members),
+        Some(v) => Err(KvError::ConvertError(v.format(), "Zset")),
+        None => Ok(&[]),
+    }
+}
+
+/// 空的列表不保存，key 会被删除
+fn from_list(values: VecDeque<Value>) -> Option<Value> {
+    (!values.is_empty()).then(|| {
+        ValueList {
+            values: values.into(),
+        }
+        .into()
+    })
+}
+
+/// 空的集合不保存，key 会被删除
+fn from_set(values: Vec<Value>) -> Option<Value> {
+    (!values.is_empty()).then(|| ValueSet { values }.into())
+}
+
+/// 空的有序集合不保存，key 会被删除
+fn from_zset(members: Vec<ScoredMember>) -> Option<Value> {
+    (!members.is_empty()).then(|| ValueZset { members }.into())
+}
+
+/// 有序集合中 member 的顺序：先按 score，score 相同时按 member
+pub(super) fn cmp_member(a: &ScoredMember, b: &ScoredMember) -> Ordering {
+    a.score
+        .total_cmp(&b.score)
+        .then_with(|| a.member.cmp(&b.member))
+}
+
+/// 按 f64::total_cmp 比较的 score，可以作为 BTreeSet 的 key
+#[derive(Debug, Clone, Copy)]
+struct Score(f64);
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// 有序集合的结构：ordered 按 (score, member) 排序，scores 用来按 member 查找 score。
+/// MemTable 直接保存这个结构，修改时不需要解码和编码整个有序集合
+#[derive(Debug, Default, Clone)]
+pub(super) struct Zset {
+    ordered: BTreeSet<(Score, String)>,
+    scores: HashMap<String, f64>,
+    /// 所有 member 编码后的长度之和，用来计算编码成 Value 后的大小
+    members_len: usize,
+}
+
+impl From<ValueZset> for Zset {
+    fn from(zset: ValueZset) -> Self {
+        let mut result = Self::default();
+        for m in zset.members {
+            result.insert(&m.member, m.score);
+        }
+        result
+    }
+}
+
+impl Zset {
+    fn load(v: Option<&Value>) -> Result<Self, KvError> {
+        let members = zset_members(v)?;
+        let mut zset = Self::default();
+        for m in members {
+            zset.insert(&m.member, m.score);
+        }
+        Ok(zset)
+    }
+
+    /// 加入 member 或者更新它的 score，返回 member 是否是新加入的
+    fn insert(&mut self, member: &str, score: f64) -> bool {
+        let old = self.scores.insert(member.to_owned(), score);
+        if let Some(old) = old {
+            self.ordered.remove(&(Score(old), member.to_owned()));
+            self.members_len -= member_len(member, old);
+        }
+        self.ordered.insert((Score(score), member.to_owned()));
+        self.members_len += member_len(member, score);
+        old.is_none()
+    }
+
+    /// 删除 member，返回 member 是否存在
+    fn remove(&mut self, member: &str) -> bool {
+        match self.scores.remove(member) {
+            Some(score) => {
+                self.members_len -= member_len(member, score);
+                self.ordered.remove(&(Score(score), member.to_owned()))
+            }
+            None => false,
+        }
+    }
+
+    /// 把 members 加入有序集合，已有的 member 更新 score，返回新加入的 member 数量。
+    /// 调用前需要用 check_scores 检查 score
+    pub(super) fn add(&mut self, members: &[ScoredMember]) -> usize {
+        // 加上 0.0 把 -0.0 变成 0.0，保证它们排在同一个位置
+        members
+            .iter()
+            .filter(|m| self.insert(&m.member, m.score + 0.0))
+            .count()
+    }
+
+    /// 删除 members，返回删除的 member 数量
+    pub(super) fn remove_all(&mut self, members: &[String]) -> usize {
+        members.iter().filter(|m| self.remove(m)).count()
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub(super) fn score(&self, member: &str) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// 返回 member 按 score 从小到大的排名，rev 为 true 时从大到小。
+    /// BTreeSet 不记录排名，需要数出排在 member 前面的元素
+    pub(super) fn rank(&self, member: &str, rev: bool) -> Option<usize> {
+        let target = (Score(self.score(member)?), member.to_owned());
+        let rank = match rev {
+            true => self.ordered.range((Excluded(target), Unbounded)).count(),
+            false => self.ordered.range(..target).count(),
+        };
+        Some(rank)
+    }
+
+    /// 按排名返回 [start, stop] 之间的 member，rev 为 true 时按 score 从大到小排名
+    pub(super) fn range(&self, start: i64, stop: i64, rev: bool) -> Vec<ScoredMember> {
+        let Some((start, stop)) = normalize_range(self.scores.len(), start, stop) else {
+            return Vec::new();
+        };
+        let iter: Box<dyn Iterator<Item = _>> = match rev {
+            true => Box::new(self.ordered.iter().rev()),
+            false => Box::new(self.ordered.iter()),
+        };
+        iter.skip(start)
+            .take(stop - start + 1)
+            .map(|(score, member)| ScoredMember::new(member, score.0))
+            .collect()
+    }
+
+    /// 按 score 从小到大返回 score 在 [min, max] 之间的 member
+    pub(super) fn range_by_score(&self, min: f64, max: f64) -> Vec<ScoredMember> {
+        // 包括 min 或 max 是 NaN 的情况
+        if min.partial_cmp(&max).is_none_or(|v| v.is_gt()) {
+            return Vec::new();
+        }
+        self.ordered
+            .range((Score(min + 0.0), String::new())..)
+            .take_while(|(score, _)| score.0 <= max)
+            .map(|(score, member)| ScoredMember::new(member, score.0))
+            .collect()
+    }
+
+    /// 编码成 Value 后的大小
+    pub(super) fn encoded_len(&self) -> usize {
+        1 + encoded_len_varint(self.members_len as u64) + self.members_len
+    }
+
+    /// 编码成 Value，member 按 (score, member) 排序
+    pub(super) fn to_value(&self) -> Value {
+        let members = self
+            .ordered
+            .iter()
+            .map(|(score, member)| ScoredMember::new(member, score.0))
+            .collect();
+        ValueZset { members }.into()
+    }
+
+    /// 空的有序集合不保存，key 会被删除
+    fn into_value(self) -> Option<Value> {
+        let members = self
+            .ordered
+            .into_iter()
+            .map(|(score, member)| ScoredMember {
+                member,
+                score: score.0,
+            })
+            .collect();
+        from_zset(members)
+    }
+}
+
+/// 一个 member 在 ValueZset 中编码后的长度
+fn member_len(member: &str, score: f64) -> usize {
+    let len = ScoredMember::new(member, score).encoded_len();
+    1 + encoded_len_varint(len as u64) + len
+}
+
+/// score 不能是 NaN
+pub(super) fn check_scores(members: &[ScoredMember]) -> Result<(), KvError> {
+    match members.iter().find(|m| m.score.is_nan()) {
+        Some(m) => Err(KvError::InvalidCommand(format!(
+            "score of member {} is NaN",
+            m.member
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// 修改集合时使用的结构：values 保持加入的顺序，keys 是编码后的元素，用来判断元素是否存在
+#[derive(Debug, Default)]
+struct Set {
+    values: Vec<Value>,
+    keys: HashSet<Vec<u8>>,
+}
+
+impl Set {
+    fn load(v: Option<&Value>) -> Result<Self, KvError> {
+        let values = to_set(v)?;
+        let keys = values.iter().map(Message::encode_to_vec).collect();
+        Ok(Self { values, keys })
+    }
+
+    /// 加入集合中还没有的 value，返回 value 是否是新加入的
+    fn insert(&mut self, value: &Value) -> bool {
+        let added = self.keys.insert(value.encode_to_vec());
+        if added {
+            self.values.push(value.clone());
+        }
+        added
+    }
+
+    fn contains(&self, value: &Value) -> bool {
+        self.keys.contains(&value.encode_to_vec())
+    }
+
+    /// 删除 values 中在集合里的元素，返回删除的元素数量
+    fn remove(&mut self, values: &[Value]) -> usize {
+        let removed: HashSet<_> = values
+            .iter()
+            .map(Message::encode_to_vec)
+            .filter(|key| self.keys.remove(key))
+            .collect();
+        if !removed.is_empty() {
+            self.values
+                .retain(|v| !removed.contains(&v.encode_to_vec()));
+        }
+        removed.len()
+    }
+
+    /// 空的集合不保存，key 会被删除
+    fn into_value(self) -> Option<Value> {
+        from_set(self.values)
+    }
+}
+
+/// 把 [start, stop] 换算成长度为 len 的序列中的下标范围，负数表示从尾部开始计数，范围为空时返回 None
+pub(super) fn normalize_range(len: usize, start: i64, stop: i64) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 { len + start } else { start }.max(0);
+    let stop = if stop < 0 { len + stop } else { stop }.min(len - 1);
+    (start <= stop).then_some((start as usize, stop as usize))
+}
+
+/// 把 values 依次插入列表的头部或尾部，返回新的列表和它的长度
+pub(super) fn push(
+    v: Option<&Value>,
+    values: &[Value],
+    front: bool,
+) -> Result<(Option<Value>, usize), KvError> {
+    let mut list = to_list(v)?;
+    for value in values {
+        match front {
+            true => list.push_front(value.clone()),
+            false => list.push_back(value.clone()),
+        }
+    }
+    let len = list.len();
+    Ok((from_list(list), len))
+}
+
+/// 从列表的头部或尾部弹出最多 count 个元素，返回新的列表和弹出的元素
+pub(super) fn pop(
+    v: Option<&Value>,
+    count: usize,
+    front: bool,
+) -> Result<(Option<Value>, Vec<Value>), KvError> {
+    let mut list = to_list(v)?;
+    let count = count.min(list.len());
+    let popped = match front {
+        true => list.drain(..count).collect(),
+        false => list.drain(list.len() - count..).rev().collect(),
+    };
+    Ok((from_list(list), popped))
+}
+
+/// 返回列表中下标在 [start, stop] 之间的元素，负数表示从尾部开始计数
+pub(super) fn range(v: Option<&Value>, start: i64, stop: i64) -> Result<Vec<Value>, KvError> {
+    let list = to_list(v)?;
+    let Some((start, stop)) = normalize_range(list.len(), start, stop) else {
+        return Ok(Vec::new());
+    };
+    Ok(list
+        .into_iter()
+        .skip(start)
+        .take(stop - start + 1)
+        .collect())
+}
+
+/// 判断 value 是否在集合中，和 add、remove 一样按编码后的元素判断是否相同
+pub(super) fn is_member(v: Option<&Value>, value: &Value) -> Result<bool, KvError> {
+    Ok(Set::load(v)?.contains(value))
+}
+
+/// 把集合中还没有的 values 加入集合，返回新的集合和新加入的元素数量
+pub(super) fn add(v: Option<&Value>, values: &[Value]) -> Result<(Option<Value>, usize), KvError> {
+    let mut set = Set::load(v)?;
+    let added = values.iter().filter(|v| set.insert(v)).count();
+    Ok((set.into_value(), added))
+}
+
+/// 从集合中删除 values，返回新的集合和删除的元素数量
+pub(super) fn remove(
+    v: Option<&Value>,
+    values: &[Value],
+) -> Result<(Option<Value>, usize), KvError> {
+    let mut set = Set::load(v)?;
+    let removed = set.remove(values);
+    Ok((set.into_value(), removed))
+}
+
+/// 返回集合中的所有元素
+pub(super) fn members(v: Option<&Value>) -> Result<Vec<Value>, KvError> {
+    to_set(v)
+}
+
+/// 把 members 加入有序集合，已有的 member 更新 score，返回新的有序集合和新加入的 member 数量
+pub(super) fn zadd(
+    v: Option<&Value>,
+    members: &[ScoredMember],
+) -> Result<(Option<Value>, usize), KvError> {
+    check_scores(members)?;
+    let mut zset = Zset::load(v)?;
+    let added = zset.add(members);
+    Ok((zset.into_value(), added))
+}
+
+/// 从有序集合中删除 members，返回新的有序集合和删除的 member 数量
+pub(super) fn zrem(
+    v: Option<&Value>,
+    members: &[String],
+) -> Result<(Option<Value>, usize), KvError> {
+    let mut zset = Zset::load(v)?;
+    let removed = zset.remove_all(members);
+    Ok((zset.into_value(), removed))
+}
+
+pub(super) fn zscore(v: Option<&Value>, member: &str) -> Result<Option<f64>, KvError> {
+    let zset = zset_members(v)?;
+    Ok(zset.iter().find(|v| v.member == member).map(|v| v.score))
+}
+
+/// 返回 member 按 score 从小到大的排名，rev 为 true 时从大到小
+pub(super) fn zrank(v: Option<&Value>, member: &str, rev: bool) -> Result<Option<usize>, KvError> {
+    let zset = zset_members(v)?;
+    let Some(score) = zset.iter().find(|v| v.member == member).map(|v| v.score) else {
+        return Ok(None);
+    };
+    // 保存的 member 已经排好序，知道 score 后可以二分查找
+    let target = ScoredMember::new(member, score);
+    let i = zset.partition_point(|v| cmp_member(v, &target) == Ordering::Less);
+    Ok(Some(if rev { zset.len() - 1 - i } else { i }))
+}
+
+/// 按排名返回 [start, stop] 之间的 member，rev 为 true 时按 score 从大到小排名
+pub(super) fn zrange(
+    v: Option<&Value>,
+    start: i64,
+    stop: i64,
+    rev: bool,
+) -> Result<Vec<ScoredMember>, KvError> {
+    let zset = zset_members(v)?;
+    let Some((start, stop)) = normalize_range(zset.len(), start, stop) else {
+        return Ok(Vec::new());
+    };
+    let result = match rev {
+        true => zset
+            .iter()
+            .rev()
+            .skip(start)
+            .take(stop - start + 1)
+            .cloned()
+            .collect(),
+        false => zset[start..=stop].to_vec(),
+    };
+    Ok(result)
+}
+
+/// 按 score 从小到大返回 score 在 [min, max] 之间的 member
+pub(super) fn zrange_by_score(
+    v: Option<&Value>,
+    min: f64,
+    max: f64,
+) -> Result<Vec<ScoredMember>, KvError> {
+    let zset = zset_members(v)?;
+    let start = zset.partition_point(|v| v.score < min);
+    let end = zset.partition_point(|v| v.score <= max);
+    Ok(zset[start..end.max(start)].to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_should_handle_negative_and_out_of_bound_index() {
+        let list: Value = ValueList {
+            values: (0..5).map(Value::from).collect(),
+        }
+        .into();
+        let list = Some(&list);
+        let values = |v: &[i64]| v.iter().map(|i| Value::from(*i)).collect::<Vec<_>>();
+        assert_eq!(range(list, 0, -1).unwrap(), values(&[0, 1, 2, 3, 4]));
+        assert_eq!(range(list, -2, 10).unwrap(), values(&[3, 4]));
+        assert_eq!(range(list, -10, 1).unwrap(), values(&[0, 1]));
+        assert!(range(list, 3, 1).unwrap().is_empty());
+        assert!(range(list, 5, 10).unwrap().is_empty());
+        assert!(range(None, 0, -1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn zadd_should_keep_members_ordered_by_score() {
+        let members = [
+            ScoredMember::new("b", 2.0),
+            ScoredMember::new("a", 2.0),
+            ScoredMember::new("c", -0.0),
+            ScoredMember::new("d", 0.0),
+        ];
+        let (zset, added) = zadd(None, &members).unwrap();
+        assert_eq!(added, 4);
+        let (zset, added) = zadd(zset.as_ref(), &[ScoredMember::new("d", 3.0)]).unwrap();
+        assert_eq!(added, 0);
+        let names: Vec<_> = zrange(zset.as_ref(), 0, -1, false)
+            .unwrap()
+            .into_iter()
+            .map(|v| v.member)
+            .collect();
+        assert_eq!(names, ["c", "a", "b", "d"]);
+        assert_eq!(zrank(zset.as_ref(), "d", true).unwrap(), Some(0));
+        assert_eq!(zrank(zset.as_ref(), "a", false).unwrap(), Some(1));
+        assert_eq!(zrank(zset.as_ref(), "e", false).unwrap(), None);
+        assert!(zadd(None, &[ScoredMember::new("e", f64::NAN)]).is_err());
+
+        let members = ["a".to_owned(), "e".to_owned(), "a".to_owned()];
+        let (zset, removed) = zrem(zset.as_ref(), &members).unwrap();
+        assert_eq!(removed, 1);
+        assert_eq!(zscore(zset.as_ref(), "a").unwrap(), None);
+        assert_eq!(zscore(zset.as_ref(), "d").unwrap(), Some(3.0));
+        let members = ["b".to_owned(), "c".to_owned(), "d".to_owned()];
+        assert_eq!(zrem(zset.as_ref(), &members).unwrap(), (None, 3));
+    }
+
+    #[test]
+    fn zset_should_track_rank_and_encoded_len() {
+        let mut zset = Zset::default();
+        let members = [
+            ScoredMember::new("a", 1.0),
+            ScoredMember::new("b", 0.0),
+            ScoredMember::new("c", 1.0),
+            ScoredMember::new("d", 2.5),
+        ];
+        assert_eq!(zset.add(&members), 4);
+        assert_eq!(zset.encoded_len(), zset.to_value().encoded_len());
+        assert_eq!(zset.add(&[ScoredMember::new("b", 5.0)]), 0);
+        assert_eq!(zset.encoded_len(), zset.to_value().encoded_len());
+        assert_eq!(zset.score("b"), Some(5.0));
+        assert_eq!(zset.rank("a", false), Some(0));
+        assert_eq!(zset.rank("b", false), Some(3));
+        assert_eq!(zset.rank("c", true), Some(2));
+        assert_eq!(zset.rank("e", false), None);
+        assert_eq!(
+            zset.range(0, -3, true),
+            [ScoredMember::new("b", 5.0), ScoredMember::new("d", 2.5)]
+        );
+        assert_eq!(
+            zset.range_by_score(1.0, 2.5),
+            [
+                ScoredMember::new("a", 1.0),
+                ScoredMember::new("c", 1.0),
+                ScoredMember::new("d", 2.5)
+            ]
+        );
+        assert!(zset.range_by_score(3.0, 1.0).is_empty());
+        assert!(zset.range_by_score(f64::NAN, 1.0).is_empty());
+
+        assert_eq!(zset.remove_all(&["a".into(), "e".into()]), 1);
+        assert_eq!(zset.encoded_len(), zset.to_value().encoded_len());
+        assert_eq!(zset.remove_all(&["b".into(), "c".into(), "d".into()]), 3);
+        assert!(zset.is_empty());
+        assert_eq!(zset.encoded_len(), zset.to_value().encoded_len());
+    }
+
+    #[test]
+    fn add_should_keep_values_unique_in_insertion_order() {
+        let values = |v: &[&str]| v.iter().map(|s| Value::from(*s)).collect::<Vec<_>>();
+        let (set, added) = add(None, &values(&["b", "a", "b", "c"])).unwrap();
+        assert_eq!(added, 3);
+        let (set, added) = add(set.as_ref(), &values(&["c", "d", "a"])).unwrap();
+        assert_eq!(added, 1);
+        assert_eq!(
+            members(set.as_ref()).unwrap(),
+            values(&["b", "a", "c", "d"])
+        );
+
+        let (set, removed) = remove(set.as_ref(), &values(&["a", "e", "a"])).unwrap();
+        assert_eq!(removed, 1);
+        assert_eq!(members(set.as_ref()).unwrap(), values(&["b", "c", "d"]));
+        assert!(is_member(set.as_ref(), &"c".into()).unwrap());
+        assert!(!is_member(set.as_ref(), &"a".into()).unwrap());
+
+        // 按编码判断：NaN 和自身相同，0.0 和 -0.0 不同
+        let floats = [f64::NAN.into(), 0.0.into()];
+        let (floats, _) = add(None, &floats).unwrap();
+        assert!(is_member(floats.as_ref(), &f64::NAN.into()).unwrap());
+        assert!(!is_member(floats.as_ref(), &(-0.0).into()).unwrap());
+        assert_eq!(
+            remove(set.as_ref(), &values(&["b", "c", "d"])).unwrap(),
+            (None, 3)
+        );
+    }
+}
@@ -0,0 +1,236 @@
+use crate::{is_reserved_table, EvictionConfig, EvictionPolicy};
+use std::{
+    collections::{hash_map::RandomState, BTreeSet, HashMap},
+    hash::BuildHasher,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, OnceLock,
+    },
+};
+
+/// 估算内存时每个 key 额外的开销（BTreeMap 节点、过期时间等）
+const ENTRY_OVERHEAD: usize = 64;
+
+/// 排序的依据，最小的最先被淘汰
+type Rank = (u64, u64);
+
+/// 记录 MemTable 中所有 key 的大小和访问情况，超出限制时选出要淘汰的 key。
+/// 没有调用 enable 之前所有操作都是空操作
+#[derive(Debug, Default)]
+pub(crate) struct Evictor {
+    config: OnceLock<EvictionConfig>,
+    state: Mutex<State>,
+    evicted: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    tick: u64,
+    memory: usize,
+    len: usize,
+    tables: HashMap<String, HashMap<String, Meta>>,
+    order: BTreeSet<(Rank, String, String)>,
+    random: RandomState,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Meta {
+    size: usize,
+    rank: Rank,
+}
+
+impl Evictor {
+    /// 开启限制，只有第一次调用生效
+    pub fn enable(&self, config: EvictionConfig) {
+        let _ = self.config.set(config);
+    }
+
+    /// 记录 key 被写入，len 是值编码后的大小
+    pub fn on_write(&self, table: &str, key: &str, len: usize) {
+        let Some(config) = self.config.get() else {
+            return;
+        };
+        // 内部 table 的数据由 Service 自己管理，不参与淘汰
+        if is_reserved_table(table) {
+            return;
+        }
+        let size = table.len() + key.len() + len + ENTRY_OVERHEAD;
+        let mut state = self.state.lock().unwrap();
+        let old = state.remove(table, key);
+        let rank = state.next_rank(config.policy, old.map(|v| v.rank));
+        state.insert(table, key, Meta { size, rank });
+    }
+
+    /// 记录 key 被读取
+    pub fn on_read(&self, table: &str, key: &str) {
+        let Some(config) = self.config.get() else {
+            return;
+        };
+        // 随机淘汰不关心访问情况
+        if config.policy == EvictionPolicy::Random {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        if let Some(old) = state.remove(table, key) {
+            let rank = state.next_rank(config.policy, Some(old.rank));
+            state.insert(table, key, Meta { rank, ..old });
+        }
+    }
+
+    /// 记录 key 被删除或者过期
+    pub fn on_remove(&self, table: &str, key: &str) {
+        if self.config.get().is_some() {
+            self.state.lock().unwrap().remove(table, key);
+        }
+    }
+
+    pub fn on_drop_table(&self, table: &str) {
+        if self.config.get().is_some() {
+            self.state.lock().unwrap().remove_table(table);
+        }
+    }
+
+    pub fn on_rename_table(&self, from: &str, to: &str) {
+        if self.config.get().is_none() {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.remove_table(to);
+        for (key, meta) in state.remove_table(from) {
+            state.insert(to, &key, meta);
+        }
+    }
+
+    /// 超出限制时按策略选出要淘汰的 key，返回的 key 已经不再被记录
+    pub fn victims(&self) -> Vec<(String, String)> {
+        let Some(config) = self.config.get() else {
+            return Vec::new();
+        };
+        let mut state = self.state.lock().unwrap();
+        let mut victims = Vec::new();
+        while config.max_entries.is_some_and(|max| state.len > max)
+            || config.max_memory.is_some_and(|max| state.memory > max)
+        {
+            let Some((_, table, key)) = state.order.first().cloned() else {
+                break;
+            };
+            state.remove(&table, &key);
+            victims.push((table, key));
+        }
+        self.evicted
+            .fetch_add(victims.len() as u64, Ordering::Relaxed);
+        victims
+    }
+
+    /// 被淘汰的 key 的总数
+    pub fn evicted(&self) -> u64 {
+        self.evicted.load(Ordering::Relaxed)
+    }
+}
+
+impl State {
+    /// LRU 按最近访问的时间排序，LFU 按访问次数排序（次数相同时按时间），
+    /// Random 在写入时分配一个随机的位置
+    fn next_rank(&mut self, policy: EvictionPolicy, old: Option<Rank>) -> Rank {
+        self.tick += 1;
+        match (policy, old) {
+            (EvictionPolicy::Lru, _) => (self.tick, 0),
+            (EvictionPolicy::Lfu, Some((hits, _))) => (hits.saturating_add(1), self.tick),
+            (EvictionPolicy::Lfu, None) => (1, self.tick),
+            (EvictionPolicy::Random, Some(rank)) => rank,
+            (EvictionPolicy::Random, None) => (self.random.hash_one(self.tick), self.tick),
+        }
+    }
+
+    fn insert(&mut self, table: &str, key: &str, meta: Meta) {
+        self.memory += meta.size;
+        self.len += 1;
+        self.order.insert((meta.rank, table.into(), key.into()));
+        self.tables
+            .entry(table.into())
+            .or_default()
+            .insert(key.into(), meta);
+    }
+
+    fn remove(&mut self, table: &str, key: &str) -> Option<Meta> {
+        let keys = self.tables.get_mut(table)?;
+        let meta = keys.remove(key)?;
+        if keys.is_empty() {
+            self.tables.remove(table);
+        }
+        self.forget(table, key, meta);
+        Some(meta)
+    }
+
+    fn remove_table(&mut self, table: &str) -> HashMap<String, Meta> {
+        let keys = self.tables.remove(table).unwrap_or_default();
+        for (key, meta) in keys.iter() {
+            self.forget(table, key, *meta);
+        }
+        keys
+    }
+
+    fn forget(&mut self, table: &str, key: &str, meta: Meta) {
+        self.memory -= meta.size;
+        self.len -= 1;
+        self.order.remove(&(meta.rank, table.into(), key.into()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evictor(policy: EvictionPolicy, max_entries: usize) -> Evictor {
+        let evictor = Evictor::default();
+        evictor.enable(EvictionConfig {
+            policy,
+            max_entries: Some(max_entries),
+            max_memory: None,
+        });
+        evictor
+    }
+
+    #[test]
+    fn lru_should_evict_least_recently_used() {
+        let evictor = evictor(EvictionPolicy::Lru, 2);
+        evictor.on_write("t1", "k1", 4);
+        evictor.on_write("t2", "k2", 4);
+        evictor.on_read("t1", "k1");
+        evictor.on_write("t1", "k3", 4);
+        assert_eq!(evictor.victims(), [("t2".into(), "k2".into())]);
+        assert_eq!(evictor.evicted(), 1);
+    }
+
+    #[test]
+    fn lfu_should_evict_least_frequently_used() {
+        let evictor = evictor(EvictionPolicy::Lfu, 2);
+        evictor.on_write("t1", "k1", 4);
+        evictor.on_write("t1", "k2", 4);
+        evictor.on_read("t1", "k1");
+        evictor.on_read("t1", "k1");
+        evictor.on_read("t1", "k2");
+        evictor.on_write("t1", "k3", 4);
+        evictor.on_read("t1", "k3");
+        evictor.on_read("t1", "k3");
+        evictor.on_read("t1", "k3");
+        assert_eq!(evictor.victims(), [("t1".into(), "k2".into())]);
+    }
+
+    #[test]
+    fn max_memory_should_be_respected() {
+        let evictor = Evictor::default();
+        evictor.enable(EvictionConfig {
+            policy: EvictionPolicy::Random,
+            max_entries: None,
+            max_memory: Some(ENTRY_OVERHEAD * 3),
+        });
+        for i in 0..10 {
+            evictor.on_write("t1", &format!("k{}", i), 3);
+        }
+        assert_eq!(evictor.victims().len(), 8);
+        evictor.on_drop_table("t1");
+        assert!(evictor.victims().is_empty());
+        assert_eq!(evictor.state.lock().unwrap().memory, 0);
+    }
+}
@@ -1,31 +1,599 @@
-use crate::{Kvpair, Storage, StorageIter, Value};
-use dashmap::{mapref::one::Ref, DashMap};
+use super::{
+    add_float, add_integer,
+    collection::{self, Zset},
+    eviction::Evictor,
+    is_empty_range,
+    wal::{self, Wal},
+    ExpiredHook, ExpiryListener,
+};
+use crate::{
+    value, wal_op, EvictionConfig, FsyncPolicy, KvError, Kvpair, ScoredMember, Storage, Value,
+    WalOp, WriteBatch, WriteOp,
+};
+use dashmap::DashMap;
+use prost::Message;
+use std::{
+    borrow::Cow,
+    collections::{btree_map::Entry as MapEntry, BTreeMap, VecDeque},
+    ops::{Bound, Deref},
+    path::Path,
+    sync::{Arc, RwLock, RwLockReadGuard, Weak},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// 后台清理过期 key 的间隔
+const EXPIRE_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+/// 范围迭代时每次持锁读取的条数
+const RANGE_BATCH_SIZE: usize = 128;
+
+/// 每个 table 是按 key 排序的 BTreeMap，用来支持范围扫描
+type Table = RwLock<BTreeMap<String, Entry>>;
+type Tables = DashMap<String, Arc<Table>>;
 
 #[derive(Clone, Debug, Default)]
 pub struct MemTable {
-    tables: DashMap<String, DashMap<String, Value>>,
+    tables: Arc<Tables>,
+    /// 普通操作持有读锁，事务提交时持有写锁，保证事务的写入对其他操作原子可见
+    txn_lock: Arc<RwLock<()>>,
+    /// 开启持久化时，所有修改在生效前先写入 WAL
+    wal: Option<Arc<Wal>>,
+    /// 开启容量限制时记录所有 key 的大小和访问情况
+    evictor: Arc<Evictor>,
+    /// key 过期被清理时通知
+    expired: ExpiryListener,
+    /// key 因为容量限制被淘汰时通知
+    evicted: ExpiryListener,
+}
+
+/// 持有事务读锁的 table 引用
+struct TableRef<'a> {
+    table: Arc<Table>,
+    _guard: RwLockReadGuard<'a, ()>,
+}
+
+impl Deref for TableRef<'_> {
+    type Target = Table;
+
+    fn deref(&self) -> &Self::Target {
+        &self.table
+    }
+}
+
+/// key 对应的值，有序集合保存成可以直接修改的 Zset，修改时不需要解码和编码整个有序集合
+#[derive(Clone, Debug)]
+enum Data {
+    Value(Value),
+    Zset(Zset),
+}
+
+impl From<Value> for Data {
+    fn from(value: Value) -> Self {
+        match value {
+            Value {
+                value: Some(value::Value::Zset(zset)),
+            } => Self::Zset(zset.into()),
+            value => Self::Value(value),
+        }
+    }
+}
+
+impl Data {
+    /// 有序集合需要编码成 Value
+    fn to_value(&self) -> Cow<'_, Value> {
+        match self {
+            Self::Value(v) => Cow::Borrowed(v),
+            Self::Zset(zset) => Cow::Owned(zset.to_value()),
+        }
+    }
+
+    fn into_value(self) -> Value {
+        match self {
+            Self::Value(v) => v,
+            Self::Zset(zset) => zset.to_value(),
+        }
+    }
+
+    fn encoded_len(&self) -> usize {
+        match self {
+            Self::Value(v) => v.encoded_len(),
+            Self::Zset(zset) => zset.encoded_len(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    data: Data,
+    expire_at: Option<Instant>,
+}
+
+impl Entry {
+    fn new(data: impl Into<Data>, ttl: Option<Duration>) -> Self {
+        Self {
+            data: data.into(),
+            expire_at: ttl.map(|ttl| Instant::now() + ttl),
+        }
+    }
+
+    fn is_expired(&self, now: Instant) -> bool {
+        matches!(self.expire_at, Some(at) if at <= now)
+    }
+
+    /// 没有过期时返回 value
+    fn into_live_value(self) -> Option<Value> {
+        match self.is_expired(Instant::now()) {
+            true => None,
+            false => Some(self.data.into_value()),
+        }
+    }
+}
+
+/// 按 key 顺序遍历 table 的迭代器，每次只持锁读取一批数据，不会复制整个 table
+struct RangeIter {
+    table: Arc<Table>,
+    start: Bound<String>,
+    end: Bound<String>,
+    buf: VecDeque<Kvpair>,
+    done: bool,
+}
+
+impl RangeIter {
+    fn new(table: Arc<Table>, start: Bound<String>, end: Bound<String>) -> Self {
+        let done = is_empty_range(&start, &end);
+        Self {
+            table,
+            start,
+            end,
+            buf: VecDeque::new(),
+            done,
+        }
+    }
+
+    fn fill(&mut self) {
+        let table = self.table.read().unwrap();
+        let now = Instant::now();
+        let mut last = None;
+        let range = (self.start.as_ref(), self.end.as_ref());
+        for (k, v) in table.range::<String, _>(range).take(RANGE_BATCH_SIZE) {
+            if !v.is_expired(now) {
+                self.buf
+                    .push_back(Kvpair::new(k, v.data.to_value().into_owned()));
+            }
+            last = Some(k.clone());
+        }
+        match last {
+            Some(k) => self.start = Bound::Excluded(k),
+            None => self.done = true,
+        }
+    }
+}
+
+impl Iterator for RangeIter {
+    type Item = Kvpair;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.buf.is_empty() && !self.done {
+            self.fill();
+        }
+        self.buf.pop_front()
+    }
 }
 
 impl MemTable {
+    /// 创建 MemTable，并启动后台线程定期清理过期的 key
     pub fn new() -> Self {
-        Self::default()
+        let table = Self::default();
+        start_sweeper(&table);
+        table
+    }
+
+    /// 创建带 WAL 的 MemTable：先加载快照并重放 WAL 恢复数据，之后的修改都会先写入 WAL。
+    /// snapshot_interval 不为空时，后台线程会定期生成快照并清空 WAL
+    pub fn with_wal(
+        path: impl AsRef<Path>,
+        fsync_policy: FsyncPolicy,
+        snapshot_interval: Option<Duration>,
+    ) -> Result<Self, KvError> {
+        let mut table = Self::default();
+        let wal = Wal::open(path, fsync_policy, |record| {
+            record.ops.into_iter().for_each(|op| table.apply(op))
+        })?;
+        table.wal = Some(wal);
+        start_sweeper(&table);
+        if let Some(interval) = snapshot_interval {
+            start_snapshotter(&table, interval);
+        }
+        Ok(table)
+    }
+
+    /// 限制所有 table 中 key 的数量和占用的内存，超出时按 config.policy 淘汰 key，
+    /// 已有的数据超出限制时会马上被淘汰，淘汰的 key 写入 WAL 失败时返回错误
+    pub fn with_eviction(self, config: EvictionConfig) -> Result<Self, KvError> {
+        {
+            let _guard = self.txn_lock.write().unwrap();
+            self.evictor.enable(config);
+            for table in self.tables.iter() {
+                for (k, v) in table.value().read().unwrap().iter() {
+                    self.evictor.on_write(table.key(), k, v.data.encoded_len());
+                }
+            }
+        }
+        self.evict()?;
+        Ok(self)
+    }
+
+    /// 因为容量限制被淘汰的 key 的总数
+    pub fn evicted_count(&self) -> u64 {
+        self.evictor.evicted()
+    }
+
+    /// 把当前的数据写入快照并清空 WAL，没有开启 WAL 时什么都不做
+    pub fn snapshot(&self) -> Result<(), KvError> {
+        let Some(wal) = &self.wal else {
+            return Ok(());
+        };
+        // 写快照期间阻塞所有修改，保证快照和 WAL 的 seq 对应
+        let _guard = self.txn_lock.write().unwrap();
+        let now = Instant::now();
+        let ops = self.tables.iter().flat_map(|table| {
+            let name = table.key();
+            let table = table.value().read().unwrap();
+            table
+                .iter()
+                .filter(|(_, v)| !v.is_expired(now))
+                .map(|(k, v)| WalOp::new_set(name, k, v.data.to_value().into_owned(), v.expire_at))
+                .collect::<Vec<_>>()
+        });
+        wal.snapshot(ops)
     }
 
-    fn get_or_create_table(&self, name: &str) -> Ref<String, DashMap<String, Value>> {
-        match self.tables.get(name) {
-            Some(table) => table,
-            None => {
-                let entry = self.tables.entry(name.into()).or_default();
-                entry.downgrade()
+    /// 清理所有 table 中已经过期的 key
+    pub fn purge_expired(&self) {
+        purge_expired(&self.tables, &self.evictor, &self.expired);
+    }
+
+    fn get_or_create_table(&self, name: &str) -> TableRef<'_> {
+        let guard = self.txn_lock.read().unwrap();
+        TableRef {
+            table: get_or_create_table(&self.tables, name),
+            _guard: guard,
+        }
+    }
+
+    /// 开启 WAL 时把修改写入 WAL。需要在持有相关 table 的写锁时调用，保证 WAL 中的顺序和实际修改的顺序一致
+    fn log(&self, ops: impl FnOnce() -> Vec<WalOp>) -> Result<(), KvError> {
+        match &self.wal {
+            Some(wal) => wal.append(ops()),
+            None => Ok(()),
+        }
+    }
+
+    /// 淘汰超出容量限制的 key，不能在持有 table 的锁时调用
+    fn evict(&self) -> Result<(), KvError> {
+        for (name, key) in self.evictor.victims() {
+            let _guard = self.txn_lock.read().unwrap();
+            let Some(table) = self.tables.get(&name).map(|v| v.value().clone()) else {
+                continue;
+            };
+            let mut table = table.write().unwrap();
+            if table.contains_key(&key) {
+                self.log(|| vec![WalOp::new_del(&name, &key)])?;
+                if let Some(entry) = table.remove(&key) {
+                    drop(table);
+                    self.evicted.notify(&name, &key, &entry.data.to_value());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 重放 WAL 中的一条修改，只在启动时调用
+    fn apply(&self, op: WalOp) {
+        let Some(op) = op.op else {
+            return;
+        };
+        match op {
+            wal_op::Op::Set(v) => {
+                let table = get_or_create_table(&self.tables, &v.table);
+                let mut table = table.write().unwrap();
+                let entry = Entry {
+                    data: v.value.unwrap_or_default().into(),
+                    expire_at: wal::from_unix_ms(v.expire_at),
+                };
+                match entry.is_expired(Instant::now()) {
+                    true => table.remove(&v.key),
+                    false => table.insert(v.key, entry),
+                };
+            }
+            wal_op::Op::Del(v) => {
+                let table = get_or_create_table(&self.tables, &v.table);
+                table.write().unwrap().remove(&v.key);
+            }
+            wal_op::Op::Expire(v) => {
+                let table = get_or_create_table(&self.tables, &v.table);
+                let mut table = table.write().unwrap();
+                if let Some(entry) = table.get_mut(&v.key) {
+                    entry.expire_at = wal::from_unix_ms(v.expire_at);
+                }
+            }
+            wal_op::Op::DropTable(v) => {
+                self.tables.remove(&v.table);
+            }
+            wal_op::Op::RenameTable(v) => {
+                if let Some((_, table)) = self.tables.remove(&v.from) {
+                    self.tables.insert(v.to, table);
+                }
+            }
+            wal_op::Op::Zadd(v) => {
+                let table = get_or_create_table(&self.tables, &v.table);
+                let mut table = table.write().unwrap();
+                let entry = table
+                    .entry(v.key)
+                    .or_insert_with(|| Entry::new(Data::Zset(Zset::default()), None));
+                if let Data::Zset(zset) = &mut entry.data {
+                    zset.add(&v.members);
+                }
+            }
+            wal_op::Op::Zrem(v) => {
+                let table = get_or_create_table(&self.tables, &v.table);
+                let mut table = table.write().unwrap();
+                if let Some(Entry {
+                    data: Data::Zset(zset),
+                    ..
+                }) = table.get_mut(&v.key)
+                {
+                    zset.remove_all(&v.members);
+                    if zset.is_empty() {
+                        table.remove(&v.key);
+                    }
+                }
+            }
+        }
+    }
+
+    fn get_live(&self, table: &str, key: &str) -> Option<Entry> {
+        let name = table;
+        let table = self.get_or_create_table(name);
+        let entry = table.read().unwrap().get(key).cloned()?;
+        if entry.is_expired(Instant::now()) {
+            // 惰性删除：读到过期的 key 时顺手删掉
+            let mut table = table.write().unwrap();
+            if matches!(table.get(key), Some(v) if v.is_expired(Instant::now())) {
+                table.remove(key);
+                self.evictor.on_remove(name, key);
+                drop(table);
+                self.expired.notify(name, key, &entry.data.to_value());
+            }
+            return None;
+        }
+        self.evictor.on_read(name, key);
+        Some(entry)
+    }
+
+    /// 持有 table 的读锁把没有过期的有序集合交给 f，key 不存在时是空的有序集合
+    fn with_zset<T>(
+        &self,
+        table: &str,
+        key: &str,
+        f: impl FnOnce(&Zset) -> T,
+    ) -> Result<T, KvError> {
+        let name = table;
+        let table = self.get_or_create_table(name);
+        let table = table.read().unwrap();
+        match table.get(key) {
+            Some(entry) if !entry.is_expired(Instant::now()) => {
+                self.evictor.on_read(name, key);
+                match &entry.data {
+                    Data::Zset(zset) => Ok(f(zset)),
+                    Data::Value(v) => Err(KvError::ConvertError(v.format(), "Zset")),
+                }
+            }
+            _ => Ok(f(&Zset::default())),
+        }
+    }
+
+    /// 持有 table 的写锁直接修改 key 对应的有序集合，op 是写入 WAL 的修改。过期的 key 视为不存在，
+    /// create 为 false 时不会创建不存在的 key，修改后有序集合为空时删除 key
+    fn modify_zset<T>(
+        &self,
+        table: &str,
+        key: &str,
+        create: bool,
+        op: impl FnOnce() -> WalOp,
+        f: impl FnOnce(&mut Zset) -> T,
+    ) -> Result<T, KvError> {
+        let name = table;
+        let result = {
+            let table = self.get_or_create_table(name);
+            let mut table = table.write().unwrap();
+            let live = match table.get(key) {
+                Some(entry) if entry.is_expired(Instant::now()) => false,
+                Some(Entry {
+                    data: Data::Value(v),
+                    ..
+                }) => return Err(KvError::ConvertError(v.format(), "Zset")),
+                Some(_) => true,
+                None => false,
+            };
+            if !live && !create {
+                return Ok(f(&mut Zset::default()));
+            }
+            let expired = !live && table.contains_key(key);
+            // 过期的 key 先删除，重放 WAL 时从空的有序集合开始修改
+            self.log(|| match expired {
+                true => vec![WalOp::new_del(name, key), op()],
+                false => vec![op()],
+            })?;
+            if expired {
+                table.remove(key);
+            }
+            let entry = table
+                .entry(key.into())
+                .or_insert_with(|| Entry::new(Data::Zset(Zset::default()), None));
+            let Data::Zset(zset) = &mut entry.data else {
+                unreachable!("key {} is checked to be a zset", key);
+            };
+            let result = f(zset);
+            match zset.is_empty() {
+                true => {
+                    table.remove(key);
+                    self.evictor.on_remove(name, key);
+                }
+                false => self.evictor.on_write(name, key, zset.encoded_len()),
+            }
+            result
+        };
+        self.evict()?;
+        Ok(result)
+    }
+
+    fn insert(
+        &self,
+        table: &str,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+        ttl: Option<Duration>,
+    ) -> Result<Option<Value>, KvError> {
+        let name = table;
+        let key = key.into();
+        let value = value.into();
+        let expire_at = ttl.map(|ttl| Instant::now() + ttl);
+        let old = {
+            let table = self.get_or_create_table(name);
+            let mut table = table.write().unwrap();
+            self.log(|| vec![WalOp::new_set(name, &key, value.clone(), expire_at)])?;
+            self.evictor.on_write(name, &key, value.encoded_len());
+            let entry = Entry {
+                data: value.into(),
+                expire_at,
+            };
+            table.insert(key, entry).and_then(Entry::into_live_value)
+        };
+        self.evict()?;
+        Ok(old)
+    }
+
+    /// 持有 table 的写锁，用 f 计算出新值后写回或删除 key，过期的 key 视为不存在
+    fn modify_locked<T>(
+        &self,
+        table: &str,
+        key: &str,
+        f: impl FnOnce(Option<&Value>) -> Result<(Option<Value>, T), KvError>,
+    ) -> Result<T, KvError> {
+        let name = table;
+        let table = self.get_or_create_table(name);
+        let mut table = table.write().unwrap();
+        let (old, expire_at) = match table.get(key) {
+            Some(entry) if !entry.is_expired(Instant::now()) => {
+                (Some(entry.data.to_value()), entry.expire_at)
             }
+            _ => (None, None),
+        };
+        let existed = old.is_some();
+        let (value, result) = f(old.as_deref())?;
+        drop(old);
+        match value {
+            Some(value) => {
+                self.log(|| vec![WalOp::new_set(name, key, value.clone(), expire_at)])?;
+                self.evictor.on_write(name, key, value.encoded_len());
+                let entry = Entry {
+                    data: value.into(),
+                    expire_at,
+                };
+                table.insert(key.into(), entry);
+            }
+            None if existed => {
+                self.log(|| vec![WalOp::new_del(name, key)])?;
+                self.evictor.on_remove(name, key);
+                table.remove(key);
+            }
+            None => {}
         }
+        Ok(result)
+    }
+}
+
+fn get_or_create_table(tables: &Tables, name: &str) -> Arc<Table> {
+    match tables.get(name) {
+        Some(table) => table.value().clone(),
+        None => tables.entry(name.into()).or_default().value().clone(),
+    }
+}
+
+fn start_sweeper(table: &MemTable) {
+    let tables = Arc::downgrade(&table.tables);
+    let evictor = Arc::downgrade(&table.evictor);
+    let expired = table.expired.clone();
+    thread::spawn(move || loop {
+        thread::sleep(EXPIRE_SWEEP_INTERVAL);
+        // MemTable 被释放后退出
+        match (tables.upgrade(), evictor.upgrade()) {
+            (Some(tables), Some(evictor)) => purge_expired(&tables, &evictor, &expired),
+            _ => break,
+        }
+    });
+}
+
+fn start_snapshotter(table: &MemTable, interval: Duration) {
+    let tables = Arc::downgrade(&table.tables);
+    let txn_lock = Arc::downgrade(&table.txn_lock);
+    let wal = table.wal.as_ref().map(Arc::downgrade);
+    let evictor = Arc::downgrade(&table.evictor);
+    let expired = table.expired.clone();
+    let evicted = table.evicted.clone();
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        // MemTable 被释放后退出
+        let (Some(tables), Some(txn_lock), Some(wal), Some(evictor)) = (
+            tables.upgrade(),
+            txn_lock.upgrade(),
+            wal.as_ref().and_then(Weak::upgrade),
+            evictor.upgrade(),
+        ) else {
+            break;
+        };
+        let table = MemTable {
+            tables,
+            txn_lock,
+            wal: Some(wal),
+            evictor,
+            expired: expired.clone(),
+            evicted: evicted.clone(),
+        };
+        // 失败时数据仍然保存在 WAL 中，等下次重试
+        let _ = table.snapshot();
+    });
+}
+
+fn purge_expired(tables: &Tables, evictor: &Evictor, listener: &ExpiryListener) {
+    let now = Instant::now();
+    let mut purged = vec![];
+    for table in tables.iter() {
+        table.write().unwrap().retain(|k, v| {
+            let expired = v.is_expired(now);
+            if expired {
+                evictor.on_remove(table.key(), k);
+                if listener.is_set() {
+                    purged.push((
+                        table.key().clone(),
+                        k.clone(),
+                        v.data.to_value().into_owned(),
+                    ));
+                }
+            }
+            !expired
+        });
+    }
+    // 释放所有锁之后再通知
+    for (table, key, value) in purged {
+        listener.notify(&table, &key, &value);
     }
 }
 
 impl Storage for MemTable {
     fn get(&self, table: &str, key: &str) -> Result<Option<Value>, crate::KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.get(key).map(|v| v.value().clone()))
+        Ok(self.get_live(table, key).map(|v| v.data.into_value()))
     }
 
     fn set(
@@ -34,39 +602,594 @@ impl Storage for MemTable {
         key: impl Into<String>,
         value: impl Into<Value>,
     ) -> Result<Option<Value>, crate::KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.insert(key.into(), value.into()))
+        self.insert(table, key, value, None)
     }
 
     fn contains(&self, table: &str, key: &str) -> Result<bool, crate::KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.contains_key(key))
+        Ok(self.get_live(table, key).is_some())
     }
 
     fn del(&self, table: &str, key: &str) -> Result<Option<Value>, crate::KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.remove(key).map(|(_k, v)| v))
+        let name = table;
+        let table = self.get_or_create_table(name);
+        let mut table = table.write().unwrap();
+        if table.contains_key(key) {
+            self.log(|| vec![WalOp::new_del(name, key)])?;
+            self.evictor.on_remove(name, key);
+        }
+        Ok(table.remove(key).and_then(Entry::into_live_value))
     }
 
     fn get_all(&self, table: &str) -> Result<Vec<crate::Kvpair>, crate::KvError> {
         let table = self.get_or_create_table(table);
+        let table = table.read().unwrap();
+        let now = Instant::now();
         Ok(table
             .iter()
-            .map(|v| Kvpair::new(v.key(), v.value().clone()))
+            .filter(|(_, v)| !v.is_expired(now))
+            .map(|(k, v)| Kvpair::new(k, v.data.to_value().into_owned()))
             .collect())
     }
 
     fn get_iter(
         &self,
         table: &str,
-    ) -> Result<Box<dyn Iterator<Item = crate::Kvpair>>, crate::KvError> {
-        let table: Ref<String, DashMap<String, Value>> = self.get_or_create_table(table);
-        // let data: Vec<Kvpair> = table
-        //     .iter()
-        //     .map(|v| Kvpair::new(v.key().clone(), v.value().clone()))
-        //     .collect();
-        // Ok(Box::new(data.into_iter()))
-        let iter = StorageIter::new(table.clone().into_iter());
+    ) -> Result<Box<dyn Iterator<Item = crate::Kvpair> + Send>, crate::KvError> {
+        self.get_range(table, Bound::Unbounded, Bound::Unbounded)
+    }
+
+    fn get_range(
+        &self,
+        table: &str,
+        start: Bound<String>,
+        end: Bound<String>,
+    ) -> Result<Box<dyn Iterator<Item = Kvpair> + Send>, KvError> {
+        let table = self.get_or_create_table(table);
+        let iter = RangeIter::new(table.table.clone(), start, end);
         Ok(Box::new(iter))
     }
+
+    fn set_with_ttl(
+        &self,
+        table: &str,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+        ttl: Duration,
+    ) -> Result<Option<Value>, crate::KvError> {
+        self.insert(table, key, value, Some(ttl))
+    }
+
+    fn expire(&self, table: &str, key: &str, ttl: Duration) -> Result<bool, crate::KvError> {
+        let name = table;
+        let table = self.get_or_create_table(name);
+        let mut table = table.write().unwrap();
+        let result = match table.get_mut(key) {
+            Some(v) if !v.is_expired(Instant::now()) => {
+                let expire_at = Some(Instant::now() + ttl);
+                self.log(|| vec![WalOp::new_expire(name, key, expire_at)])?;
+                v.expire_at = expire_at;
+                true
+            }
+            _ => false,
+        };
+        Ok(result)
+    }
+
+    fn ttl(&self, table: &str, key: &str) -> Result<Option<Duration>, crate::KvError> {
+        Ok(self
+            .get_live(table, key)
+            .and_then(|v| v.expire_at)
+            .map(|at| at.saturating_duration_since(Instant::now())))
+    }
+
+    fn persist(&self, table: &str, key: &str) -> Result<bool, crate::KvError> {
+        let name = table;
+        let table = self.get_or_create_table(name);
+        let mut table = table.write().unwrap();
+        let result = match table.get_mut(key) {
+            Some(v) if !v.is_expired(Instant::now()) && v.expire_at.is_some() => {
+                self.log(|| vec![WalOp::new_expire(name, key, None)])?;
+                v.expire_at = None;
+                true
+            }
+            _ => false,
+        };
+        Ok(result)
+    }
+
+    fn incr(&self, table: &str, key: &str, delta: i64) -> Result<i64, KvError> {
+        self.modify(table, key, |v| {
+            let i = add_integer(v, delta)?;
+            Ok((Some(i.into()), i))
+        })
+    }
+
+    fn incr_float(&self, table: &str, key: &str, delta: f64) -> Result<f64, KvError> {
+        self.modify(table, key, |v| {
+            let f = add_float(v, delta)?;
+            Ok((Some(f.into()), f))
+        })
+    }
+
+    fn modify<T>(
+        &self,
+        table: &str,
+        key: &str,
+        f: impl FnMut(Option<&Value>) -> Result<(Option<Value>, T), KvError>,
+    ) -> Result<T, KvError> {
+        let result = self.modify_locked(table, key, f)?;
+        self.evict()?;
+        Ok(result)
+    }
+
+    fn zadd(&self, table: &str, key: &str, members: Vec<ScoredMember>) -> Result<usize, KvError> {
+        collection::check_scores(&members)?;
+        self.modify_zset(
+            table,
+            key,
+            true,
+            || WalOp::new_zadd(table, key, members.clone()),
+            |zset| zset.add(&members),
+        )
+    }
+
+    fn zrem(&self, table: &str, key: &str, members: Vec<String>) -> Result<usize, KvError> {
+        self.modify_zset(
+            table,
+            key,
+            false,
+            || WalOp::new_zrem(table, key, members.clone()),
+            |zset| zset.remove_all(&members),
+        )
+    }
+
+    fn zscore(&self, table: &str, key: &str, member: &str) -> Result<Option<f64>, KvError> {
+        self.with_zset(table, key, |zset| zset.score(member))
+    }
+
+    fn zrank(
+        &self,
+        table: &str,
+        key: &str,
+        member: &str,
+        rev: bool,
+    ) -> Result<Option<usize>, KvError> {
+        self.with_zset(table, key, |zset| zset.rank(member, rev))
+    }
+
+    fn zrange(
+        &self,
+        table: &str,
+        key: &str,
+        start: i64,
+        stop: i64,
+        rev: bool,
+    ) -> Result<Vec<ScoredMember>, KvError> {
+        self.with_zset(table, key, |zset| zset.range(start, stop, rev))
+    }
+
+    fn zrange_by_score(
+        &self,
+        table: &str,
+        key: &str,
+        min: f64,
+        max: f64,
+    ) -> Result<Vec<ScoredMember>, KvError> {
+        self.with_zset(table, key, |zset| zset.range_by_score(min, max))
+    }
+
+    fn compare_and_swap(
+        &self,
+        table: &str,
+        key: &str,
+        expected: Option<Value>,
+        value: Value,
+    ) -> Result<bool, KvError> {
+        let name = table;
+        let log = |value: &Value, expire_at| {
+            self.log(|| vec![WalOp::new_set(name, key, value.clone(), expire_at)])?;
+            self.evictor.on_write(name, key, value.encoded_len());
+            Ok::<_, KvError>(())
+        };
+        let result = {
+            let table = self.get_or_create_table(name);
+            let mut table = table.write().unwrap();
+            match table.entry(key.into()) {
+                MapEntry::Occupied(mut entry) if !entry.get().is_expired(Instant::now()) => {
+                    match expected.as_ref() == Some(entry.get().data.to_value().as_ref()) {
+                        true => {
+                            log(&value, entry.get().expire_at)?;
+                            entry.get_mut().data = value.into();
+                            true
+                        }
+                        false => false,
+                    }
+                }
+                MapEntry::Occupied(mut entry) if expected.is_none() => {
+                    log(&value, None)?;
+                    entry.insert(Entry::new(value, None));
+                    true
+                }
+                MapEntry::Vacant(entry) if expected.is_none() => {
+                    log(&value, None)?;
+                    entry.insert(Entry::new(value, None));
+                    true
+                }
+                _ => false,
+            }
+        };
+        self.evict()?;
+        Ok(result)
+    }
+
+    fn commit(&self, batch: WriteBatch) -> Result<bool, KvError> {
+        {
+            let _guard = self.txn_lock.write().unwrap();
+            let now = Instant::now();
+            let unchanged = batch.reads.iter().all(|(table, key, origin)| {
+                let table = get_or_create_table(&self.tables, table);
+                let table = table.read().unwrap();
+                let current = table
+                    .get(key.as_str())
+                    .filter(|v| !v.is_expired(now))
+                    .map(|v| v.data.to_value());
+                current.as_deref() == origin.as_ref()
+            });
+            if !unchanged {
+                return Ok(false);
+            }
+
+            // 整个事务作为一条记录写入 WAL，重放时要么全部生效要么全部丢弃
+            self.log(|| {
+                batch
+                    .writes
+                    .iter()
+                    .map(|op| match op {
+                        WriteOp::Set {
+                            table,
+                            key,
+                            value,
+                            ttl,
+                        } => WalOp::new_set(table, key, value.clone(), ttl.map(|ttl| now + ttl)),
+                        WriteOp::Del { table, key } => WalOp::new_del(table, key),
+                    })
+                    .collect()
+            })?;
+            for op in batch.writes {
+                match op {
+                    WriteOp::Set {
+                        table,
+                        key,
+                        value,
+                        ttl,
+                    } => {
+                        let entries = get_or_create_table(&self.tables, &table);
+                        self.evictor.on_write(&table, &key, value.encoded_len());
+                        let entry = Entry {
+                            data: value.into(),
+                            expire_at: ttl.map(|ttl| now + ttl),
+                        };
+                        entries.write().unwrap().insert(key, entry);
+                    }
+                    WriteOp::Del { table, key } => {
+                        let entries = get_or_create_table(&self.tables, &table);
+                        self.evictor.on_remove(&table, &key);
+                        entries.write().unwrap().remove(&key);
+                    }
+                }
+            }
+        }
+        self.evict()?;
+        Ok(true)
+    }
+
+    fn list_tables(&self) -> Result<Vec<String>, KvError> {
+        let mut tables: Vec<_> = self
+            .tables
+            .iter()
+            .filter(|v| !v.value().read().unwrap().is_empty())
+            .map(|v| v.key().clone())
+            .collect();
+        tables.sort();
+        Ok(tables)
+    }
+
+    fn drop_table(&self, table: &str) -> Result<bool, KvError> {
+        // 等待正在执行的操作结束，避免它们写入被删除的 table
+        let _guard = self.txn_lock.write().unwrap();
+        if self.tables.contains_key(table) {
+            self.log(|| vec![WalOp::new_drop_table(table)])?;
+            self.evictor.on_drop_table(table);
+        }
+        Ok(matches!(self.tables.remove(table), Some((_, v)) if !v.read().unwrap().is_empty()))
+    }
+
+    fn table_len(&self, table: &str) -> Result<usize, KvError> {
+        let table = self.get_or_create_table(table);
+        let now = Instant::now();
+        let table = table.read().unwrap();
+        Ok(table.values().filter(|v| !v.is_expired(now)).count())
+    }
+
+    fn rename_table(&self, from: &str, to: &str) -> Result<bool, KvError> {
+        let _guard = self.txn_lock.write().unwrap();
+        let is_empty = |name| match self.tables.get(name) {
+            Some(table) => table.read().unwrap().is_empty(),
+            None => true,
+        };
+        if is_empty(from) {
+            return Ok(false);
+        }
+        if !is_empty(to) {
+            return Err(KvError::PreconditionFailed(format!(
+                "table {} already exists",
+                to
+            )));
+        }
+        self.log(|| vec![WalOp::new_rename_table(from, to)])?;
+        self.evictor.on_rename_table(from, to);
+        if let Some((_, table)) = self.tables.remove(from) {
+            self.tables.insert(to.into(), table);
+        }
+        Ok(true)
+    }
+
+    fn export(
+        &self,
+        mut f: impl FnMut(&str, Kvpair, Option<Duration>) -> Result<(), KvError>,
+    ) -> Result<(), KvError> {
+        // 导出期间阻塞所有修改，导出的是同一时刻的数据
+        let _guard = self.txn_lock.write().unwrap();
+        let mut tables: Vec<_> = self
+            .tables
+            .iter()
+            .map(|v| (v.key().clone(), v.value().clone()))
+            .collect();
+        tables.sort_by(|a, b| a.0.cmp(&b.0));
+        let now = Instant::now();
+        for (name, table) in tables {
+            let table = table.read().unwrap();
+            for (k, v) in table.iter().filter(|(_, v)| !v.is_expired(now)) {
+                let ttl = v.expire_at.map(|at| at.saturating_duration_since(now));
+                f(&name, Kvpair::new(k, v.data.to_value().into_owned()), ttl)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn on_expired(&self, hook: ExpiredHook) {
+        self.expired.set(hook);
+    }
+
+    fn on_evicted(&self, hook: ExpiredHook) {
+        self.evicted.set(hook);
+    }
+
+    fn is_blocking(&self) -> bool {
+        // 开启 WAL 时写入要做文件 I/O，生成快照和事务提交期间还会持有 txn_lock 的写锁
+        self.wal.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EvictionPolicy;
+    use std::{fs, io::Write};
+    use tempfile::tempdir;
+
+    #[test]
+    fn purge_expired_should_remove_expired_keys() {
+        let store = MemTable::new();
+        store
+            .set_with_ttl("t1", "k1", "v1", Duration::from_millis(10))
+            .unwrap();
+        store.set("t1", "k2", "v2").unwrap();
+        thread::sleep(Duration::from_millis(20));
+
+        store.purge_expired();
+        let table = store.tables.get("t1").unwrap();
+        let table = table.read().unwrap();
+        assert_eq!(table.len(), 1);
+        assert!(table.contains_key("k2"));
+    }
+
+    #[test]
+    fn range_iter_should_read_across_batches() {
+        let store = MemTable::new();
+        let n = RANGE_BATCH_SIZE * 2 + 10;
+        for i in 0..n {
+            store.set("t1", format!("k{:04}", i), i as i64).unwrap();
+        }
+        let keys: Vec<_> = store.get_iter("t1").unwrap().map(|v| v.key).collect();
+        assert_eq!(keys.len(), n);
+        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn wal_should_restore_data_after_restart() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("kv.wal");
+        {
+            let store = MemTable::with_wal(&path, FsyncPolicy::Always, None).unwrap();
+            store.set("t1", "k1", "v1").unwrap();
+            store.set("t1", "k2", "v2").unwrap();
+            store.del("t1", "k2").unwrap();
+            store
+                .set_with_ttl("t1", "k3", "v3", Duration::from_secs(60))
+                .unwrap();
+            store
+                .set_with_ttl("t1", "k4", "v4", Duration::from_millis(10))
+                .unwrap();
+            store.incr("t2", "counter", 5).unwrap();
+            store.set("t3", "k1", "v1").unwrap();
+            store.rename_table("t3", "t4").unwrap();
+            store.set("t5", "k1", "v1").unwrap();
+            store.drop_table("t5").unwrap();
+            let batch = WriteBatch {
+                reads: vec![],
+                writes: vec![WriteOp::Set {
+                    table: "t1".into(),
+                    key: "k5".into(),
+                    value: "v5".into(),
+                    ttl: None,
+                }],
+            };
+            assert!(store.commit(batch).unwrap());
+        }
+        thread::sleep(Duration::from_millis(20));
+
+        let store = MemTable::with_wal(&path, FsyncPolicy::Always, None).unwrap();
+        assert_eq!(store.get("t1", "k1").unwrap(), Some("v1".into()));
+        assert_eq!(store.get("t1", "k2").unwrap(), None);
+        assert!(store.ttl("t1", "k3").unwrap().unwrap() > Duration::from_secs(50));
+        assert_eq!(store.get("t1", "k4").unwrap(), None);
+        assert_eq!(store.get("t1", "k5").unwrap(), Some("v5".into()));
+        assert_eq!(store.get("t2", "counter").unwrap(), Some(5.into()));
+        assert_eq!(store.list_tables().unwrap(), ["t1", "t2", "t4"]);
+    }
+
+    #[test]
+    fn wal_should_replay_zset_changes() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("kv.wal");
+        let members = |v: &[(&str, f64)]| {
+            v.iter()
+                .map(|(m, s)| ScoredMember::new(*m, *s))
+                .collect::<Vec<_>>()
+        };
+        {
+            let store = MemTable::with_wal(&path, FsyncPolicy::Never, None).unwrap();
+            store
+                .zadd("t1", "z1", members(&[("a", 1.0), ("b", 2.0), ("c", -0.0)]))
+                .unwrap();
+            store.zadd("t1", "z1", members(&[("a", 3.0)])).unwrap();
+            store.zrem("t1", "z1", vec!["b".into()]).unwrap();
+            store.zadd("t1", "z2", members(&[("a", 1.0)])).unwrap();
+            store.zrem("t1", "z2", vec!["a".into()]).unwrap();
+            // 过期的有序集合重新创建，不会保留旧的 member 和过期时间
+            store.zadd("t1", "z3", members(&[("a", 1.0)])).unwrap();
+            store.expire("t1", "z3", Duration::from_millis(10)).unwrap();
+            thread::sleep(Duration::from_millis(20));
+            store.zadd("t1", "z3", members(&[("b", 2.0)])).unwrap();
+            store.snapshot().unwrap();
+            store.zadd("t1", "z3", members(&[("c", 3.0)])).unwrap();
+        }
+
+        let store = MemTable::with_wal(&path, FsyncPolicy::Never, None).unwrap();
+        assert_eq!(
+            store.zrange("t1", "z1", 0, -1, false).unwrap(),
+            members(&[("c", 0.0), ("a", 3.0)])
+        );
+        assert_eq!(store.get("t1", "z2").unwrap(), None);
+        assert_eq!(
+            store.zrange("t1", "z3", 0, -1, false).unwrap(),
+            members(&[("b", 2.0), ("c", 3.0)])
+        );
+        assert_eq!(store.ttl("t1", "z3").unwrap(), None);
+    }
+
+    #[test]
+    fn snapshot_should_truncate_wal() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("kv.wal");
+        {
+            let store = MemTable::with_wal(&path, FsyncPolicy::Never, None).unwrap();
+            // 生成快照时会阻塞所有修改，所以开启 WAL 的 MemTable 总是阻塞的
+            assert!(store.is_blocking());
+            assert!(!MemTable::new().is_blocking());
+            for i in 0..100 {
+                store.set("t1", format!("k{}", i), i as i64).unwrap();
+            }
+            store.snapshot().unwrap();
+            assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+            store.set("t1", "k0", "v0").unwrap();
+        }
+
+        let store = MemTable::with_wal(&path, FsyncPolicy::Never, None).unwrap();
+        assert_eq!(store.table_len("t1").unwrap(), 100);
+        assert_eq!(store.get("t1", "k0").unwrap(), Some("v0".into()));
+        assert_eq!(store.get("t1", "k99").unwrap(), Some(99.into()));
+    }
+
+    #[test]
+    fn wal_should_skip_incomplete_record() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("kv.wal");
+        {
+            let store = MemTable::with_wal(&path, FsyncPolicy::Never, None).unwrap();
+            store.set("t1", "k1", "v1").unwrap();
+        }
+        let len = fs::metadata(&path).unwrap().len();
+        // 模拟写到一半时崩溃
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[0, 0, 0, 100, 1, 2]).unwrap();
+
+        let store = MemTable::with_wal(&path, FsyncPolicy::Never, None).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+        store.set("t1", "k2", "v2").unwrap();
+        drop(store);
+
+        let store = MemTable::with_wal(&path, FsyncPolicy::Never, None).unwrap();
+        assert_eq!(store.get("t1", "k1").unwrap(), Some("v1".into()));
+        assert_eq!(store.get("t1", "k2").unwrap(), Some("v2".into()));
+    }
+
+    #[test]
+    fn eviction_should_remove_keys_across_tables() {
+        let store = MemTable::new()
+            .with_eviction(EvictionConfig {
+                policy: EvictionPolicy::Lru,
+                max_entries: Some(3),
+                max_memory: None,
+            })
+            .unwrap();
+        store.set("t1", "k1", "v1").unwrap();
+        store.set("t2", "k1", "v2").unwrap();
+        store.set("t1", "k2", "v3").unwrap();
+        store.get("t1", "k1").unwrap();
+        store.set("t2", "k2", "v4").unwrap();
+        store.incr("t3", "counter", 1).unwrap();
+
+        assert_eq!(store.evicted_count(), 2);
+        assert_eq!(store.get("t2", "k1").unwrap(), None);
+        assert!(!store.contains("t1", "k2").unwrap());
+        assert_eq!(
+            store.get_all("t1").unwrap(),
+            [Kvpair::new("k1", "v1".into())]
+        );
+        assert_eq!(
+            store.get_iter("t2").unwrap().collect::<Vec<_>>(),
+            [Kvpair::new("k2", "v4".into())]
+        );
+
+        // 删除的 key 不再计入限制
+        store.del("t1", "k1").unwrap();
+        store.set("t4", "k1", "v5").unwrap();
+        assert_eq!(store.evicted_count(), 2);
+    }
+
+    #[test]
+    fn with_eviction_should_evict_existing_keys() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("kv.wal");
+        {
+            let store = MemTable::with_wal(&path, FsyncPolicy::Never, None).unwrap();
+            for i in 0..10 {
+                store.set("t1", format!("k{}", i), i as i64).unwrap();
+            }
+            let store = store
+                .with_eviction(EvictionConfig {
+                    policy: EvictionPolicy::Random,
+                    max_entries: Some(4),
+                    max_memory: None,
+                })
+                .unwrap();
+            assert_eq!(store.table_len("t1").unwrap(), 4);
+        }
+
+        // 淘汰也会写入 WAL
+        let store = MemTable::with_wal(&path, FsyncPolicy::Never, None).unwrap();
+        assert_eq!(store.table_len("t1").unwrap(), 4);
+    }
 }
@@ -1,14 +1,39 @@
+use std::{
+    fmt,
+    ops::Bound,
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
 use crate::{
-    pb::abi::{Kvpair, Value},
+    pb::abi::{Kvpair, ScoredMember, Value},
     KvError,
 };
 
+mod backup;
+mod collection;
+mod eviction;
 mod memory;
+#[cfg(feature = "redb")]
+mod redbdb;
 mod sleddb;
+mod txn;
+mod wal;
+pub use backup::{backup, restore, BACKUP_VERSION};
 pub use memory::MemTable;
+#[cfg(feature = "redb")]
+pub use redbdb::RedbDb;
 pub use sleddb::SledDb;
+pub use txn::{TxnStore, WriteBatch, WriteOp};
 
-pub trait Storage: Send + Sync + 'static {
+/// Service 内部使用的 table，例如保存 topic 消息的 `__pubsub:<topic>`。
+/// client 不能直接访问这些 table，ListTables 不会列出它们，MemTable 也不会淘汰其中的 key
+pub fn is_reserved_table(table: &str) -> bool {
+    table.starts_with(crate::PUBSUB_TABLE_PREFIX)
+}
+
+pub trait Storage: Send + Sync {
     fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError>;
     fn set(
         &self,
@@ -19,7 +44,222 @@ pub trait Storage: Send + Sync + 'static {
     fn contains(&self, table: &str, key: &str) -> Result<bool, KvError>;
     fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError>;
     fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError>;
-    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError>;
+    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair> + Send>, KvError>;
+    /// 按 key 的顺序遍历 table 中位于 start 和 end 之间的数据
+    fn get_range(
+        &self,
+        table: &str,
+        start: Bound<String>,
+        end: Bound<String>,
+    ) -> Result<Box<dyn Iterator<Item = Kvpair> + Send>, KvError>;
+    /// 设置 key 并指定过期时间，返回旧的 value
+    fn set_with_ttl(
+        &self,
+        table: &str,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+        ttl: Duration,
+    ) -> Result<Option<Value>, KvError>;
+    /// 为已存在的 key 设置过期时间，key 不存在时返回 false
+    fn expire(&self, table: &str, key: &str, ttl: Duration) -> Result<bool, KvError>;
+    /// 返回 key 的剩余存活时间，key 不存在或没有过期时间时返回 None
+    fn ttl(&self, table: &str, key: &str) -> Result<Option<Duration>, KvError>;
+    /// 去掉 key 的过期时间，原本有过期时间时返回 true
+    fn persist(&self, table: &str, key: &str) -> Result<bool, KvError>;
+    /// 原子地给整数加上 delta，key 不存在时从 0 开始，返回新的值
+    fn incr(&self, table: &str, key: &str, delta: i64) -> Result<i64, KvError>;
+    /// 原子地给浮点数加上 delta，key 不存在时从 0 开始，返回新的值
+    fn incr_float(&self, table: &str, key: &str, delta: f64) -> Result<f64, KvError>;
+    /// 当前值等于 expected（None 表示 key 不存在）时写入 value，写入成功返回 true
+    fn compare_and_swap(
+        &self,
+        table: &str,
+        key: &str,
+        expected: Option<Value>,
+        value: Value,
+    ) -> Result<bool, KvError>;
+    /// 原子地修改 key：f 拿到没有过期的当前值，返回新的值（None 表示删除 key）和交给调用者的结果，
+    /// key 原来的过期时间保持不变。发生冲突时 f 可能被调用多次
+    fn modify<T>(
+        &self,
+        table: &str,
+        key: &str,
+        f: impl FnMut(Option<&Value>) -> Result<(Option<Value>, T), KvError>,
+    ) -> Result<T, KvError>;
+    /// 把 values 依次插入列表头部，key 不存在时创建列表，返回列表的长度。
+    /// 列表和集合整体保存在一个 Value 中，修改的耗时和元素数量成正比
+    fn lpush(&self, table: &str, key: &str, values: Vec<Value>) -> Result<usize, KvError> {
+        self.modify(table, key, |v| collection::push(v, &values, true))
+    }
+    /// 把 values 依次插入列表尾部，key 不存在时创建列表，返回列表的长度
+    fn rpush(&self, table: &str, key: &str, values: Vec<Value>) -> Result<usize, KvError> {
+        self.modify(table, key, |v| collection::push(v, &values, false))
+    }
+    /// 从列表头部弹出最多 count 个元素，列表为空时删除 key
+    fn lpop(&self, table: &str, key: &str, count: usize) -> Result<Vec<Value>, KvError> {
+        self.modify(table, key, |v| collection::pop(v, count, true))
+    }
+    /// 从列表尾部弹出最多 count 个元素，列表为空时删除 key
+    fn rpop(&self, table: &str, key: &str, count: usize) -> Result<Vec<Value>, KvError> {
+        self.modify(table, key, |v| collection::pop(v, count, false))
+    }
+    /// 返回列表中下标在 [start, stop] 之间的元素，负数表示从尾部开始计数
+    fn lrange(&self, table: &str, key: &str, start: i64, stop: i64) -> Result<Vec<Value>, KvError> {
+        collection::range(self.get(table, key)?.as_ref(), start, stop)
+    }
+    /// 把 values 加入集合，key 不存在时创建集合，返回新加入的元素数量
+    fn sadd(&self, table: &str, key: &str, values: Vec<Value>) -> Result<usize, KvError> {
+        self.modify(table, key, |v| collection::add(v, &values))
+    }
+    /// 从集合中删除 values，集合为空时删除 key，返回删除的元素数量
+    fn srem(&self, table: &str, key: &str, values: Vec<Value>) -> Result<usize, KvError> {
+        self.modify(table, key, |v| collection::remove(v, &values))
+    }
+    /// 返回集合中的所有元素，key 不存在时返回空集合
+    fn smembers(&self, table: &str, key: &str) -> Result<Vec<Value>, KvError> {
+        collection::members(self.get(table, key)?.as_ref())
+    }
+    /// 判断 value 是否在集合中，key 不存在时返回 false
+    fn sismember(&self, table: &str, key: &str, value: &Value) -> Result<bool, KvError> {
+        collection::is_member(self.get(table, key)?.as_ref(), value)
+    }
+    /// 把 members 加入有序集合，已有的 member 更新 score，返回新加入的 member 数量
+    fn zadd(&self, table: &str, key: &str, members: Vec<ScoredMember>) -> Result<usize, KvError> {
+        self.modify(table, key, |v| collection::zadd(v, &members))
+    }
+    /// 从有序集合中删除 members，有序集合为空时删除 key，返回删除的 member 数量
+    fn zrem(&self, table: &str, key: &str, members: Vec<String>) -> Result<usize, KvError> {
+        self.modify(table, key, |v| collection::zrem(v, &members))
+    }
+    /// 返回 member 的 score，member 不存在时返回 None
+    fn zscore(&self, table: &str, key: &str, member: &str) -> Result<Option<f64>, KvError> {
+        collection::zscore(self.get(table, key)?.as_ref(), member)
+    }
+    /// 返回 member 按 score 从小到大的排名，rev 为 true 时从大到小，member 不存在时返回 None
+    fn zrank(
+        &self,
+        table: &str,
+        key: &str,
+        member: &str,
+        rev: bool,
+    ) -> Result<Option<usize>, KvError> {
+        collection::zrank(self.get(table, key)?.as_ref(), member, rev)
+    }
+    /// 按排名返回 [start, stop] 之间的 member，负数表示从最后一名开始计数
+    fn zrange(
+        &self,
+        table: &str,
+        key: &str,
+        start: i64,
+        stop: i64,
+        rev: bool,
+    ) -> Result<Vec<ScoredMember>, KvError> {
+        collection::zrange(self.get(table, key)?.as_ref(), start, stop, rev)
+    }
+    /// 按 score 从小到大返回 score 在 [min, max] 之间的 member
+    fn zrange_by_score(
+        &self,
+        table: &str,
+        key: &str,
+        min: f64,
+        max: f64,
+    ) -> Result<Vec<ScoredMember>, KvError> {
+        collection::zrange_by_score(self.get(table, key)?.as_ref(), min, max)
+    }
+    /// 原子地提交事务：batch.reads 中的值都没有变化时写入 batch.writes 并返回 true，
+    /// 否则什么都不写，返回 false
+    fn commit(&self, batch: WriteBatch) -> Result<bool, KvError>;
+    /// 返回所有有数据的 table，按名字排序
+    fn list_tables(&self) -> Result<Vec<String>, KvError>;
+    /// 删除 table 及其所有数据，table 中原来有数据时返回 true
+    fn drop_table(&self, table: &str) -> Result<bool, KvError>;
+    /// 返回 table 中没有过期的 key 的数量
+    fn table_len(&self, table: &str) -> Result<usize, KvError>;
+    /// 把 from 重命名为 to，from 没有数据时返回 false，to 已经有数据时返回 PreconditionFailed
+    fn rename_table(&self, from: &str, to: &str) -> Result<bool, KvError>;
+    /// 按 table 依次把所有没有过期的数据和它们剩余的存活时间交给 f，f 返回错误时停止导出
+    fn export(
+        &self,
+        f: impl FnMut(&str, Kvpair, Option<Duration>) -> Result<(), KvError>,
+    ) -> Result<(), KvError>;
+    /// 操作是否可能因为磁盘 I/O 等阻塞，为 true 时 Service 会在 spawn_blocking 的线程池中执行命令
+    fn is_blocking(&self) -> bool {
+        true
+    }
+    /// 注册 key 过期被删除时的回调，只有第一次注册的回调生效。不支持的 Storage 忽略它
+    fn on_expired(&self, _hook: ExpiredHook) {}
+    /// 注册 key 因为容量限制被淘汰时的回调，参数和 on_expired 的回调相同。
+    /// 只有开启容量限制的 MemTable 会淘汰 key，其他 Storage 忽略它
+    fn on_evicted(&self, _hook: ExpiredHook) {}
+}
+
+/// key 过期被删除时的回调，参数是 table、key 和过期前的 value。
+/// 它可能在后台清理线程中被调用，不能阻塞
+pub type ExpiredHook = Arc<dyn Fn(&str, &str, &Value) + Send + Sync>;
+
+/// 保存 Storage 注册的 ExpiredHook，可以在多个线程之间共享
+#[derive(Clone, Default)]
+pub(crate) struct ExpiryListener(Arc<OnceLock<ExpiredHook>>);
+
+impl ExpiryListener {
+    pub fn set(&self, hook: ExpiredHook) {
+        let _ = self.0.set(hook);
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.0.get().is_some()
+    }
+
+    pub fn notify(&self, table: &str, key: &str, value: &Value) {
+        if let Some(hook) = self.0.get() {
+            hook(table, key, value);
+        }
+    }
+}
+
+impl fmt::Debug for ExpiryListener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ExpiryListener")
+            .field(&self.is_set())
+            .finish()
+    }
+}
+
+/// 计算 incr 之后的整数值，原来的值不是整数时返回 ConvertError
+fn add_integer(v: Option<&Value>, delta: i64) -> Result<i64, KvError> {
+    let i: i64 = match v {
+        Some(v) => v.try_into()?,
+        None => 0,
+    };
+    i.checked_add(delta)
+        .ok_or_else(|| KvError::InvalidCommand(format!("{} + {} overflows", i, delta)))
+}
+
+/// 计算 incr_float 之后的浮点值，原来的值不是数字时返回 ConvertError
+fn add_float(v: Option<&Value>, delta: f64) -> Result<f64, KvError> {
+    let f: f64 = match v {
+        Some(v) => v.try_into()?,
+        None => 0.0,
+    };
+    Ok(f + delta)
+}
+
+/// start 大于 end 等不包含任何 key 的范围，BTreeMap 和 sled 遇到这种范围会 panic
+fn is_empty_range(start: &Bound<String>, end: &Bound<String>) -> bool {
+    match (start, end) {
+        (Bound::Included(s), Bound::Included(e)) => s > e,
+        (Bound::Included(s) | Bound::Excluded(s), Bound::Included(e) | Bound::Excluded(e)) => {
+            s >= e
+        }
+        _ => false,
+    }
+}
+
+/// 在文件名后面加上后缀，用于生成临时文件等相关文件的路径
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    path.into()
 }
 
 pub struct StorageIter<T> {
@@ -45,6 +285,8 @@ where
 
 #[cfg(test)]
 mod tests {
+    use std::thread;
+
     use tempfile::tempdir;
 
     use super::*;
@@ -67,6 +309,80 @@ mod tests {
         test_get_iter(store);
     }
 
+    #[test]
+    fn memtable_expiry_should_work() {
+        let store = MemTable::new();
+        test_expiry(store);
+    }
+
+    #[test]
+    fn memtable_incr_should_work() {
+        let store = MemTable::new();
+        test_incr(store);
+    }
+
+    #[test]
+    fn memtable_concurrent_incr_should_work() {
+        let store = MemTable::new();
+        test_concurrent_incr(store);
+    }
+
+    #[test]
+    fn memtable_compare_and_swap_should_work() {
+        let store = MemTable::new();
+        test_compare_and_swap(store);
+    }
+
+    #[test]
+    fn memtable_commit_should_work() {
+        let store = MemTable::new();
+        test_commit(store);
+    }
+
+    #[test]
+    fn memtable_get_range_should_work() {
+        let store = MemTable::new();
+        test_get_range(store);
+    }
+
+    #[test]
+    fn memtable_keys_with_colon_should_work() {
+        let store = MemTable::new();
+        test_keys_with_colon(store);
+    }
+
+    #[test]
+    fn memtable_table_management_should_work() {
+        let store = MemTable::new();
+        test_table_management(store);
+    }
+
+    #[test]
+    fn memtable_export_should_work() {
+        let store = MemTable::new();
+        test_export(store);
+    }
+
+    #[test]
+    fn memtable_list_should_work() {
+        test_list(MemTable::new());
+    }
+
+    #[test]
+    fn memtable_set_should_work() {
+        test_set(MemTable::new());
+    }
+
+    #[test]
+    fn memtable_zset_should_work() {
+        test_zset(MemTable::new());
+    }
+
+    #[test]
+    fn memtable_concurrent_push_should_work() {
+        test_concurrent_push(MemTable::new());
+    }
+
     fn test_basic_interface(store: impl Storage) {
         let v = store.set("t1", "hello", "world");
         assert!(v.unwrap().is_none());
@@ -118,6 +434,413 @@ mod tests {
         )
     }
 
+    fn test_expiry(store: impl Storage) {
+        let ttl = Duration::from_millis(50);
+        assert!(store.set_with_ttl("t4", "k1", "v1", ttl).unwrap().is_none());
+        store.set("t4", "k2", "v2").unwrap();
+        assert_eq!(store.get("t4", "k1").unwrap(), Some("v1".into()));
+        assert!(store.ttl("t4", "k1").unwrap().unwrap() <= ttl);
+        assert!(store.ttl("t4", "k2").unwrap().is_none());
+
+        thread::sleep(Duration::from_millis(60));
+        assert!(store.get("t4", "k1").unwrap().is_none());
+        assert!(!store.contains("t4", "k1").unwrap());
+        assert!(store.ttl("t4", "k1").unwrap().is_none());
+        let expected = vec![Kvpair::new("k2", "v2".into())];
+        assert_eq!(store.get_all("t4").unwrap(), expected);
+        assert_eq!(store.get_iter("t4").unwrap().collect::<Vec<_>>(), expected);
+        // 过期的 key 不会作为旧值返回
+        assert!(store.set_with_ttl("t4", "k1", "v3", ttl).unwrap().is_none());
+        assert!(store.persist("t4", "k1").unwrap());
+        assert!(!store.persist("t4", "k1").unwrap());
+
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(store.get("t4", "k1").unwrap(), Some("v3".into()));
+        assert!(store.expire("t4", "k1", ttl).unwrap());
+        assert!(!store.expire("t4", "k3", ttl).unwrap());
+        thread::sleep(Duration::from_millis(60));
+        assert!(store.del("t4", "k1").unwrap().is_none());
+    }
+
+    fn test_incr(store: impl Storage) {
+        assert_eq!(store.incr("t5", "counter", 2).unwrap(), 2);
+        assert_eq!(store.incr("t5", "counter", -5).unwrap(), -3);
+        assert_eq!(store.get("t5", "counter").unwrap(), Some((-3).into()));
+        assert_eq!(store.incr_float("t5", "counter", 0.5).unwrap(), -2.5);
+        assert_eq!(store.incr_float("t5", "price", 1.5).unwrap(), 1.5);
+        assert!(matches!(
+            store.incr("t5", "price", 1),
+            Err(KvError::ConvertError(_, "Integer"))
+        ));
+
+        store.set("t5", "name", "kv").unwrap();
+        assert!(matches!(
+            store.incr_float("t5", "name", 1.0),
+            Err(KvError::ConvertError(_, "Float"))
+        ));
+        assert_eq!(store.get("t5", "name").unwrap(), Some("kv".into()));
+    }
+
+    fn test_concurrent_incr(store: impl Storage + 'static) {
+        let store = std::sync::Arc::new(store);
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let store = store.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        store.incr("t6", "counter", 1).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(store.get("t6", "counter").unwrap(), Some(800.into()));
+    }
+
+    fn test_compare_and_swap(store: impl Storage) {
+        assert!(store
+            .compare_and_swap("t7", "k1", None, "v1".into())
+            .unwrap());
+        assert!(!store
+            .compare_and_swap("t7", "k1", None, "v2".into())
+            .unwrap());
+        assert!(!store
+            .compare_and_swap("t7", "k1", Some("v0".into()), "v2".into())
+            .unwrap());
+        assert_eq!(store.get("t7", "k1").unwrap(), Some("v1".into()));
+        assert!(store
+            .compare_and_swap("t7", "k1", Some("v1".into()), "v2".into())
+            .unwrap());
+        assert_eq!(store.get("t7", "k1").unwrap(), Some("v2".into()));
+        assert!(!store
+            .compare_and_swap("t7", "k2", Some("v1".into()), "v2".into())
+            .unwrap());
+        assert!(!store.contains("t7", "k2").unwrap());
+
+        // 过期的 key 视为不存在
+        store
+            .set_with_ttl("t7", "k3", "v1", Duration::from_millis(10))
+            .unwrap();
+        thread::sleep(Duration::from_millis(20));
+        assert!(store
+            .compare_and_swap("t7", "k3", None, "v2".into())
+            .unwrap());
+        assert_eq!(store.get("t7", "k3").unwrap(), Some("v2".into()));
+    }
+
+    fn test_commit(store: impl Storage) {
+        store.set("t8", "k1", "v1").unwrap();
+        let batch = WriteBatch {
+            reads: vec![("t8".into(), "k1".into(), Some("v1".into()))],
+            writes: vec![
+                WriteOp::Set {
+                    table: "t8".into(),
+                    key: "k2".into(),
+                    value: "v2".into(),
+                    ttl: None,
+                },
+                WriteOp::Del {
+                    table: "t8".into(),
+                    key: "k1".into(),
+                },
+            ],
+        };
+        assert!(store.commit(batch.clone()).unwrap());
+        assert!(!store.contains("t8", "k1").unwrap());
+        assert_eq!(store.get("t8", "k2").unwrap(), Some("v2".into()));
+
+        // k1 已经被删除，读到的值和提交时不一致，不会写入
+        store.del("t8", "k2").unwrap();
+        assert!(!store.commit(batch).unwrap());
+        assert!(!store.contains("t8", "k2").unwrap());
+    }
+
+    fn test_get_range(store: impl Storage) {
+        for key in ["a1", "a2", "b1", "b2", "c1"] {
+            store.set("t9", key, key).unwrap();
+        }
+        store.set("t90", "a3", "a3").unwrap();
+        store
+            .set_with_ttl("t9", "a3", "a3", Duration::from_millis(10))
+            .unwrap();
+        thread::sleep(Duration::from_millis(20));
+
+        let keys = |start, end| -> Vec<_> {
+            store
+                .get_range("t9", start, end)
+                .unwrap()
+                .map(|v| v.key)
+                .collect()
+        };
+        assert_eq!(
+            keys(Bound::Unbounded, Bound::Unbounded),
+            ["a1", "a2", "b1", "b2", "c1"]
+        );
+        assert_eq!(
+            keys(Bound::Excluded("a1".into()), Bound::Excluded("b2".into())),
+            ["a2", "b1"]
+        );
+        assert_eq!(
+            keys(Bound::Included("b1".into()), Bound::Unbounded),
+            ["b1", "b2", "c1"]
+        );
+        assert!(keys(Bound::Included("c".into()), Bound::Excluded("b".into())).is_empty());
+    }
+
+    fn test_keys_with_colon(store: impl Storage) {
+        store.set("a", "b:c", "v1").unwrap();
+        store.set("a:b", "c", "v2").unwrap();
+        assert_eq!(store.get("a", "b:c").unwrap(), Some("v1".into()));
+        assert_eq!(store.get("a:b", "c").unwrap(), Some("v2".into()));
+        assert_eq!(
+            store.get_all("a").unwrap(),
+            [Kvpair::new("b:c", "v1".into())]
+        );
+        assert_eq!(
+            store.get_all("a:b").unwrap(),
+            [Kvpair::new("c", "v2".into())]
+        );
+    }
+
+    fn test_table_management(store: impl Storage) {
+        store.set("users", "u1", "alice").unwrap();
+        store.set("users", "u2", "bob").unwrap();
+        store.set("orders", "o1", 10).unwrap();
+        store
+            .set_with_ttl("orders", "o2", 20, Duration::from_millis(10))
+            .unwrap();
+        // 只读过的 table 不算存在
+        store.get("empty", "k1").unwrap();
+        thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(store.list_tables().unwrap(), ["orders", "users"]);
+        assert_eq!(store.table_len("users").unwrap(), 2);
+        assert_eq!(store.table_len("orders").unwrap(), 1);
+        assert_eq!(store.table_len("empty").unwrap(), 0);
+
+        assert!(store.rename_table("users", "members").unwrap());
+        assert!(!store.rename_table("users", "members").unwrap());
+        assert!(!store.contains("users", "u1").unwrap());
+        assert_eq!(store.get("members", "u1").unwrap(), Some("alice".into()));
+        assert!(matches!(
+            store.rename_table("orders", "members"),
+            Err(KvError::PreconditionFailed(_))
+        ));
+
+        assert!(store.drop_table("orders").unwrap());
+        assert!(!store.drop_table("orders").unwrap());
+        assert_eq!(store.table_len("orders").unwrap(), 0);
+        assert_eq!(store.list_tables().unwrap(), ["members"]);
+
+        // 删除后重新写入的是一个新的 table
+        store.set("orders", "o2", 30).unwrap();
+        assert_eq!(
+            store.get_all("orders").unwrap(),
+            [Kvpair::new("o2", 30.into())]
+        );
+        assert!(store.ttl("orders", "o2").unwrap().is_none());
+    }
+
+    fn test_list(store: impl Storage) {
+        assert_eq!(
+            store
+                .rpush("t1", "jobs", vec!["a".into(), "b".into()])
+                .unwrap(),
+            2
+        );
+        assert_eq!(
+            store
+                .lpush("t1", "jobs", vec!["c".into(), "d".into()])
+                .unwrap(),
+            4
+        );
+        let all: Vec<Value> = vec!["d".into(), "c".into(), "a".into(), "b".into()];
+        assert_eq!(store.lrange("t1", "jobs", 0, -1).unwrap(), all);
+        assert_eq!(store.lrange("t1", "jobs", 1, 2).unwrap(), &all[1..3]);
+        store.expire("t1", "jobs", Duration::from_secs(60)).unwrap();
+        assert_eq!(store.lpop("t1", "jobs", 1).unwrap(), &all[..1]);
+        assert_eq!(
+            store.rpop("t1", "jobs", 2).unwrap(),
+            vec![Value::from("b"), "a".into()]
+        );
+        // 修改列表不会去掉过期时间
+        assert!(store.ttl("t1", "jobs").unwrap().is_some());
+        assert_eq!(store.rpop("t1", "jobs", 5).unwrap(), &all[1..2]);
+        assert!(!store.contains("t1", "jobs").unwrap());
+        assert!(store.lpop("t1", "jobs", 1).unwrap().is_empty());
+        assert!(store.lrange("t1", "jobs", 0, -1).unwrap().is_empty());
+
+        store.set("t1", "k1", "v1").unwrap();
+        assert!(store.lpush("t1", "k1", vec!["a".into()]).is_err());
+        assert!(store.lrange("t1", "k1", 0, -1).is_err());
+        assert_eq!(store.get("t1", "k1").unwrap(), Some("v1".into()));
+    }
+
+    fn test_set(store: impl Storage) {
+        let tags: Vec<Value> = vec!["rust".into(), "kv".into()];
+        assert_eq!(store.sadd("t1", "tags", tags.clone()).unwrap(), 2);
+        assert_eq!(
+            store
+                .sadd("t1", "tags", vec!["kv".into(), "db".into()])
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            store.smembers("t1", "tags").unwrap(),
+            vec![Value::from("rust"), "kv".into(), "db".into()]
+        );
+        assert_eq!(
+            store
+                .srem("t1", "tags", vec!["rust".into(), "go".into()])
+                .unwrap(),
+            1
+        );
+        assert!(store.sismember("t1", "tags", &"kv".into()).unwrap());
+        assert!(!store.sismember("t1", "tags", &"rust".into()).unwrap());
+        assert!(!store.sismember("t1", "none", &"kv".into()).unwrap());
+        assert_eq!(store.srem("t1", "tags", tags).unwrap(), 1);
+        assert_eq!(
+            store.smembers("t1", "tags").unwrap(),
+            vec![Value::from("db")]
+        );
+        assert_eq!(store.srem("t1", "tags", vec!["db".into()]).unwrap(), 1);
+        assert!(!store.contains("t1", "tags").unwrap());
+        assert!(store.smembers("t1", "tags").unwrap().is_empty());
+
+        store.rpush("t1", "jobs", vec!["a".into()]).unwrap();
+        assert!(store.sadd("t1", "jobs", vec!["a".into()]).is_err());
+    }
+
+    fn test_zset(store: impl Storage) {
+        let members = |v: &[(&str, f64)]| {
+            v.iter()
+                .map(|(m, s)| ScoredMember::new(*m, *s))
+                .collect::<Vec<_>>()
+        };
+        let added = store
+            .zadd(
+                "t1",
+                "board",
+                members(&[("a", 3.0), ("b", -1.5), ("c", 0.0)]),
+            )
+            .unwrap();
+        assert_eq!(added, 3);
+        assert_eq!(
+            store.zadd("t1", "board", members(&[("b", 5.0)])).unwrap(),
+            0
+        );
+        let all = members(&[("c", 0.0), ("a", 3.0), ("b", 5.0)]);
+        assert_eq!(store.zrange("t1", "board", 0, -1, false).unwrap(), all);
+        assert_eq!(store.zrange("t1", "board", 0, 0, true).unwrap(), &all[2..]);
+        assert_eq!(
+            store.zrange("t1", "board", -2, 5, false).unwrap(),
+            &all[1..]
+        );
+        assert_eq!(
+            store.zrange_by_score("t1", "board", -1.0, 3.0).unwrap(),
+            &all[..2]
+        );
+        assert!(store
+            .zrange_by_score("t1", "board", 4.0, 3.0)
+            .unwrap()
+            .is_empty());
+        assert_eq!(store.zrank("t1", "board", "a", false).unwrap(), Some(1));
+        assert_eq!(store.zrank("t1", "board", "c", true).unwrap(), Some(2));
+        assert_eq!(store.zscore("t1", "board", "b").unwrap(), Some(5.0));
+        assert_eq!(store.zscore("t1", "board", "x").unwrap(), None);
+
+        assert_eq!(store.zrem("t1", "board", vec!["c".into()]).unwrap(), 1);
+        assert_eq!(
+            store.zrange("t1", "board", 0, -1, false).unwrap(),
+            &all[1..]
+        );
+
+        // 覆盖、删除和过期之后不会留下旧的 member
+        store.set("t1", "board", "v1").unwrap();
+        assert!(store.zrange("t1", "board", 0, -1, false).is_err());
+        store.del("t1", "board").unwrap();
+        store.zadd("t1", "board", members(&[("d", 1.0)])).unwrap();
+        assert_eq!(
+            store.zrange("t1", "board", 0, -1, false).unwrap(),
+            members(&[("d", 1.0)])
+        );
+        store
+            .expire("t1", "board", Duration::from_millis(1))
+            .unwrap();
+        thread::sleep(Duration::from_millis(10));
+        assert!(store
+            .zrange("t1", "board", 0, -1, false)
+            .unwrap()
+            .is_empty());
+        store.zadd("t1", "board", members(&[("e", 2.0)])).unwrap();
+        store.drop_table("t1").unwrap();
+        store.zadd("t1", "board", members(&[("f", 2.0)])).unwrap();
+        assert_eq!(
+            store
+                .zrange_by_score("t1", "board", f64::NEG_INFINITY, f64::INFINITY)
+                .unwrap(),
+            members(&[("f", 2.0)])
+        );
+    }
+
+    fn test_concurrent_push(store: impl Storage + 'static) {
+        let store = std::sync::Arc::new(store);
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let store = store.clone();
+                thread::spawn(move || {
+                    for j in 0..50 {
+                        store
+                            .rpush("t7", "jobs", vec![(i * 50 + j).into()])
+                            .unwrap();
+                        store.sadd("t7", "tags", vec![(j % 10).into()]).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(store.lrange("t7", "jobs", 0, -1).unwrap().len(), 400);
+        assert_eq!(store.smembers("t7", "tags").unwrap().len(), 10);
+    }
+
+    fn test_export(store: impl Storage) {
+        store.set("t2", "k1", "v1").unwrap();
+        store.set("t1", "k2", "v2").unwrap();
+        store.set("t1", "k1", "v3").unwrap();
+        store
+            .set_with_ttl("t1", "k3", "v4", Duration::from_secs(60))
+            .unwrap();
+        store
+            .set_with_ttl("t1", "k4", "v5", Duration::from_millis(10))
+            .unwrap();
+        thread::sleep(Duration::from_millis(20));
+
+        let mut data = Vec::new();
+        store
+            .export(|table, pair, ttl| {
+                data.push((table.to_owned(), pair, ttl.is_some()));
+                Ok(())
+            })
+            .unwrap();
+        // 同一个 table 的数据是连续的
+        assert_eq!(
+            data,
+            [
+                ("t1".into(), Kvpair::new("k1", "v3".into()), false),
+                ("t1".into(), Kvpair::new("k2", "v2".into()), false),
+                ("t1".into(), Kvpair::new("k3", "v4".into()), true),
+                ("t2".into(), Kvpair::new("k1", "v1".into()), false),
+            ]
+        );
+
+        let result = store.export(|_, _, _| Err(KvError::Internal("stop".into())));
+        assert!(matches!(result, Err(KvError::Internal(_))));
+    }
+
     #[test]
     fn sleddb_basic_interface_should_work() {
         let dir = tempdir().unwrap();
@@ -138,4 +861,215 @@ mod tests {
         let store = SledDb::new(dir);
         test_get_iter(store);
     }
+
+    #[test]
+    fn sleddb_expiry_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_expiry(store);
+    }
+
+    #[test]
+    fn sleddb_incr_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_incr(store);
+    }
+
+    #[test]
+    fn sleddb_concurrent_incr_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_concurrent_incr(store);
+    }
+
+    #[test]
+    fn sleddb_compare_and_swap_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_compare_and_swap(store);
+    }
+
+    #[test]
+    fn sleddb_commit_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_commit(store);
+    }
+
+    #[test]
+    fn sleddb_get_range_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_get_range(store);
+    }
+
+    #[test]
+    fn sleddb_table_management_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_table_management(store);
+    }
+
+    #[test]
+    fn sleddb_export_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_export(store);
+    }
+
+    #[test]
+    fn sleddb_keys_with_colon_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_keys_with_colon(store);
+    }
+
+    #[test]
+    fn sleddb_list_should_work() {
+        let dir = tempdir().unwrap();
+        test_list(SledDb::new(dir));
+    }
+
+    #[test]
+    fn sleddb_set_should_work() {
+        let dir = tempdir().unwrap();
+        test_set(SledDb::new(dir));
+    }
+
+    #[test]
+    fn sleddb_zset_should_work() {
+        let dir = tempdir().unwrap();
+        test_zset(SledDb::new(dir));
+    }
+
+    #[test]
+    fn sleddb_concurrent_push_should_work() {
+        let dir = tempdir().unwrap();
+        test_concurrent_push(SledDb::new(dir));
+    }
+
+    #[cfg(feature = "redb")]
+    #[test]
+    fn redb_basic_interface_should_work() {
+        let dir = tempdir().unwrap();
+        let store = RedbDb::new(dir.path().join("kv.redb"));
+        test_basic_interface(store);
+    }
+
+    #[cfg(feature = "redb")]
+    #[test]
+    fn redb_get_all_should_work() {
+        let dir = tempdir().unwrap();
+        let store = RedbDb::new(dir.path().join("kv.redb"));
+        test_get_all(store);
+    }
+
+    #[cfg(feature = "redb")]
+    #[test]
+    fn redb_get_iter_should_work() {
+        let dir = tempdir().unwrap();
+        let store = RedbDb::new(dir.path().join("kv.redb"));
+        test_get_iter(store);
+    }
+
+    #[cfg(feature = "redb")]
+    #[test]
+    fn redb_expiry_should_work() {
+        let dir = tempdir().unwrap();
+        let store = RedbDb::new(dir.path().join("kv.redb"));
+        test_expiry(store);
+    }
+
+    #[cfg(feature = "redb")]
+    #[test]
+    fn redb_incr_should_work() {
+        let dir = tempdir().unwrap();
+        let store = RedbDb::new(dir.path().join("kv.redb"));
+        test_incr(store);
+    }
+
+    #[cfg(feature = "redb")]
+    #[test]
+    fn redb_concurrent_incr_should_work() {
+        let dir = tempdir().unwrap();
+        let store = RedbDb::new(dir.path().join("kv.redb"));
+        test_concurrent_incr(store);
+    }
+
+    #[cfg(feature = "redb")]
+    #[test]
+    fn redb_compare_and_swap_should_work() {
+        let dir = tempdir().unwrap();
+        let store = RedbDb::new(dir.path().join("kv.redb"));
+        test_compare_and_swap(store);
+    }
+
+    #[cfg(feature = "redb")]
+    #[test]
+    fn redb_commit_should_work() {
+        let dir = tempdir().unwrap();
+        let store = RedbDb::new(dir.path().join("kv.redb"));
+        test_commit(store);
+    }
+
+    #[cfg(feature = "redb")]
+    #[test]
+    fn redb_get_range_should_work() {
+        let dir = tempdir().unwrap();
+        let store = RedbDb::new(dir.path().join("kv.redb"));
+        test_get_range(store);
+    }
+
+    #[cfg(feature = "redb")]
+    #[test]
+    fn redb_keys_with_colon_should_work() {
+        let dir = tempdir().unwrap();
+        let store = RedbDb::new(dir.path().join("kv.redb"));
+        test_keys_with_colon(store);
+    }
+
+    #[cfg(feature = "redb")]
+    #[test]
+    fn redb_table_management_should_work() {
+        let dir = tempdir().unwrap();
+        let store = RedbDb::new(dir.path().join("kv.redb"));
+        test_table_management(store);
+    }
+
+    #[cfg(feature = "redb")]
+    #[test]
+    fn redb_export_should_work() {
+        let dir = tempdir().unwrap();
+        let store = RedbDb::new(dir.path().join("kv.redb"));
+        test_export(store);
+    }
+
+    #[cfg(feature = "redb")]
+    #[test]
+    fn redb_list_should_work() {
+        let dir = tempdir().unwrap();
+        test_list(RedbDb::new(dir.path().join("kv.redb")));
+    }
+
+    #[cfg(feature = "redb")]
+    #[test]
+    fn redb_set_should_work() {
+        let dir = tempdir().unwrap();
+        test_set(RedbDb::new(dir.path().join("kv.redb")));
+    }
+
+    #[cfg(feature = "redb")]
+    #[test]
+    fn redb_zset_should_work() {
+        let dir = tempdir().unwrap();
+        test_zset(RedbDb::new(dir.path().join("kv.redb")));
+    }
+
+    #[cfg(feature = "redb")]
+    #[test]
+    fn redb_concurrent_push_should_work() {
+        let dir = tempdir().unwrap();
+        test_concurrent_push(RedbDb::new(dir.path().join("kv.redb")));
+    }
 }
@@ -0,0 +1,585 @@
+use redb::{
+    Database, ReadableTable, ReadableTableMetadata, TableDefinition, TableError, TableHandle,
+    WriteTransaction,
+};
+use std::{
+    convert::TryInto,
+    ops::Bound,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use super::{add_float, add_integer, is_empty_range, ExpiredHook, ExpiryListener};
+use crate::{KvError, Kvpair, Storage, Value, WriteBatch, WriteOp};
+
+/// 用户 table 在 redb 中的名字前缀，避免和内部使用的表冲突
+const TABLE_PREFIX: &str = "table:";
+/// 按过期时间排序的索引：(过期时间戳, table, key) -> ()
+const EXPIRY_INDEX: TableDefinition<(u64, &str, &str), ()> = TableDefinition::new("__expiry_index");
+/// 每次写操作顺带清理的过期 key 数量上限
+const PURGE_BATCH_SIZE: usize = 128;
+/// 没有过期时间的 key 的过期时间戳
+const NO_EXPIRY: u64 = 0;
+
+/// 每个 table 是 redb 中的一张表：key -> (过期时间戳（毫秒）, value)
+type DataTable<'a> = TableDefinition<'a, &'static str, (u64, &'static [u8])>;
+
+#[derive(Debug)]
+pub struct RedbDb {
+    db: Database,
+    expired: ExpiryListener,
+}
+
+impl RedbDb {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        let db = Database::create(path).unwrap();
+        let txn = db.begin_write().unwrap();
+        txn.open_table(EXPIRY_INDEX).unwrap();
+        txn.commit().unwrap();
+        Self {
+            db,
+            expired: ExpiryListener::default(),
+        }
+    }
+
+    /// 按过期时间顺序清理已经过期的 key，最多清理 PURGE_BATCH_SIZE 个
+    pub fn purge_expired(&self) -> Result<(), KvError> {
+        let expired = self.write(purge_expired)?;
+        self.notify_expired(expired);
+        Ok(())
+    }
+
+    /// 事务提交之后再通知被清理的 key，避免回调看到没有提交的数据
+    fn notify_expired(&self, expired: Vec<(String, String, Value)>) {
+        for (table, key, value) in expired {
+            self.expired.notify(&table, &key, &value);
+        }
+    }
+
+    /// 在一个写事务中执行 f，f 返回错误时事务被丢弃
+    fn write<T>(
+        &self,
+        f: impl FnOnce(&WriteTransaction) -> Result<T, KvError>,
+    ) -> Result<T, KvError> {
+        let txn = self.db.begin_write()?;
+        let result = f(&txn)?;
+        txn.commit()?;
+        Ok(result)
+    }
+
+    /// 读取 key 没有过期的 value 和过期时间
+    fn get_live(&self, table: &str, key: &str) -> Result<Option<(u64, Value)>, KvError> {
+        let txn = self.db.begin_read()?;
+        let name = table_name(table);
+        let table = match txn.open_table(data_table(&name)) {
+            Ok(table) => table,
+            Err(TableError::TableDoesNotExist(_)) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let result = match table.get(key)? {
+            Some(v) => decode_live(v.value(), now_ms())?,
+            None => None,
+        };
+        Ok(result)
+    }
+
+    /// 写入 value 并替换过期时间，返回没有过期的旧 value
+    fn insert(
+        &self,
+        table: &str,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+        expire_at: Option<u64>,
+    ) -> Result<Option<Value>, KvError> {
+        let key = key.into();
+        let data: Vec<u8> = value.into().try_into()?;
+        let (old, expired) = self.write(|txn| {
+            let expired = purge_expired(txn)?;
+            let old = put(txn, table, &key, &data, expire_at.unwrap_or(NO_EXPIRY))?;
+            Ok((old.map(|(_, v)| v), expired))
+        })?;
+        self.notify_expired(expired);
+        Ok(old)
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+fn table_name(table: &str) -> String {
+    format!("{}{}", TABLE_PREFIX, table)
+}
+
+fn data_table(name: &str) -> DataTable<'_> {
+    TableDefinition::new(name)
+}
+
+fn is_live(at: u64, now: u64) -> bool {
+    at == NO_EXPIRY || at > now
+}
+
+fn decode_live((at, data): (u64, &[u8]), now: u64) -> Result<Option<(u64, Value)>, KvError> {
+    match is_live(at, now) {
+        true => Ok(Some((at, data.try_into()?))),
+        false => Ok(None),
+    }
+}
+
+/// 在写事务中读取 key 没有过期的 value 和过期时间
+fn get(txn: &WriteTransaction, table: &str, key: &str) -> Result<Option<(u64, Value)>, KvError> {
+    let name = table_name(table);
+    let table = txn.open_table(data_table(&name))?;
+    let result = match table.get(key)? {
+        Some(v) => decode_live(v.value(), now_ms())?,
+        None => None,
+    };
+    Ok(result)
+}
+
+/// 在写事务中写入 value，返回没有过期的旧值
+fn put(
+    txn: &WriteTransaction,
+    table: &str,
+    key: &str,
+    data: &[u8],
+    expire_at: u64,
+) -> Result<Option<(u64, Value)>, KvError> {
+    write_entry(txn, table, key, Some((data, expire_at)))
+}
+
+/// 写入（entry 为 None 时删除）key，同时维护过期索引，返回没有过期的旧值
+fn write_entry(
+    txn: &WriteTransaction,
+    table: &str,
+    key: &str,
+    entry: Option<(&[u8], u64)>,
+) -> Result<Option<(u64, Value)>, KvError> {
+    let name = table_name(table);
+    let mut data_table = txn.open_table(data_table(&name))?;
+    let old = match entry {
+        Some((data, at)) => data_table.insert(key, (at, data))?,
+        None => data_table.remove(key)?,
+    };
+    let old = match old {
+        Some(v) => {
+            let (at, data) = v.value();
+            Some((at, data.to_vec()))
+        }
+        None => None,
+    };
+
+    let mut index = txn.open_table(EXPIRY_INDEX)?;
+    if let Some((at, _)) = &old {
+        if *at != NO_EXPIRY {
+            index.remove((*at, table, key))?;
+        }
+    }
+    if let Some((_, at)) = entry {
+        if at != NO_EXPIRY {
+            index.insert((at, table, key), ())?;
+        }
+    }
+
+    match old {
+        Some((at, data)) => decode_live((at, &data), now_ms()),
+        None => Ok(None),
+    }
+}
+
+/// 在写事务中清理过期的 key，返回被清理的 (table, key, value)
+fn purge_expired(txn: &WriteTransaction) -> Result<Vec<(String, String, Value)>, KvError> {
+    let now = now_ms();
+    let mut index = txn.open_table(EXPIRY_INDEX)?;
+    let expired = index
+        .range::<(u64, &str, &str)>(..(now + 1, "", ""))?
+        .take(PURGE_BATCH_SIZE)
+        .map(|v| {
+            let (k, _) = v?;
+            let (at, table, key) = k.value();
+            Ok((at, table.to_owned(), key.to_owned()))
+        })
+        .collect::<Result<Vec<_>, redb::StorageError>>()?;
+    let mut purged = Vec::new();
+    for (at, table, key) in expired {
+        index.remove((at, table.as_str(), key.as_str()))?;
+        let name = table_name(&table);
+        let mut data = match txn.open_table(data_table(&name)) {
+            Ok(data) => data,
+            Err(TableError::TableDoesNotExist(_)) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        // key 可能已经被重新写入，只删除过期时间和索引一致的数据
+        let current = data.get(key.as_str())?.map(|v| v.value().0);
+        if current == Some(at) {
+            if let Some(old) = data.remove(key.as_str())? {
+                let value = old.value().1.try_into()?;
+                purged.push((table, key, value));
+            }
+        }
+    }
+    Ok(purged)
+}
+
+fn to_bound(bound: &Bound<String>) -> Bound<&str> {
+    match bound {
+        Bound::Included(key) => Bound::Included(key.as_str()),
+        Bound::Excluded(key) => Bound::Excluded(key.as_str()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+impl Storage for RedbDb {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        Ok(self.get_live(table, key)?.map(|(_, v)| v))
+    }
+
+    fn set(
+        &self,
+        table: &str,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+    ) -> Result<Option<Value>, KvError> {
+        self.insert(table, key, value, None)
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        Ok(self.get_live(table, key)?.is_some())
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        self.write(|txn| Ok(write_entry(txn, table, key, None)?.map(|(_, v)| v)))
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        Ok(self.get_iter(table)?.collect())
+    }
+
+    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair> + Send>, KvError> {
+        self.get_range(table, Bound::Unbounded, Bound::Unbounded)
+    }
+
+    fn get_range(
+        &self,
+        table: &str,
+        start: Bound<String>,
+        end: Bound<String>,
+    ) -> Result<Box<dyn Iterator<Item = Kvpair> + Send>, KvError> {
+        if is_empty_range(&start, &end) {
+            return Ok(Box::new(std::iter::empty()));
+        }
+        let txn = self.db.begin_read()?;
+        let name = table_name(table);
+        let table = match txn.open_table(data_table(&name)) {
+            Ok(table) => table,
+            Err(TableError::TableDoesNotExist(_)) => return Ok(Box::new(std::iter::empty())),
+            Err(e) => return Err(e.into()),
+        };
+        let now = now_ms();
+        let iter = table
+            .range::<&str>((to_bound(&start), to_bound(&end)))?
+            .filter_map(move |v| {
+                let (k, v) = v.ok()?;
+                let (_, value) = decode_live(v.value(), now).ok()??;
+                Some(Kvpair::new(k.value(), value))
+            });
+        Ok(Box::new(iter))
+    }
+
+    fn set_with_ttl(
+        &self,
+        table: &str,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+        ttl: Duration,
+    ) -> Result<Option<Value>, KvError> {
+        let expire_at = now_ms() + ttl.as_millis() as u64;
+        self.insert(table, key, value, Some(expire_at))
+    }
+
+    fn expire(&self, table: &str, key: &str, ttl: Duration) -> Result<bool, KvError> {
+        let expire_at = now_ms() + ttl.as_millis() as u64;
+        self.write(|txn| match get(txn, table, key)? {
+            Some((_, value)) => {
+                let data: Vec<u8> = value.try_into()?;
+                put(txn, table, key, &data, expire_at)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        })
+    }
+
+    fn ttl(&self, table: &str, key: &str) -> Result<Option<Duration>, KvError> {
+        let result = match self.get_live(table, key)? {
+            Some((at, _)) if at != NO_EXPIRY => {
+                Some(Duration::from_millis(at.saturating_sub(now_ms())))
+            }
+            _ => None,
+        };
+        Ok(result)
+    }
+
+    fn persist(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        self.write(|txn| match get(txn, table, key)? {
+            Some((at, value)) if at != NO_EXPIRY => {
+                let data: Vec<u8> = value.try_into()?;
+                put(txn, table, key, &data, NO_EXPIRY)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        })
+    }
+
+    fn incr(&self, table: &str, key: &str, delta: i64) -> Result<i64, KvError> {
+        self.modify(table, key, |v| {
+            let i = add_integer(v, delta)?;
+            Ok((Some(i.into()), i))
+        })
+    }
+
+    fn incr_float(&self, table: &str, key: &str, delta: f64) -> Result<f64, KvError> {
+        self.modify(table, key, |v| {
+            let f = add_float(v, delta)?;
+            Ok((Some(f.into()), f))
+        })
+    }
+
+    /// 在写事务中用 f 计算出新值后写回或删除 key，保留原来的过期时间，过期的 key 视为不存在
+    fn modify<T>(
+        &self,
+        table: &str,
+        key: &str,
+        mut f: impl FnMut(Option<&Value>) -> Result<(Option<Value>, T), KvError>,
+    ) -> Result<T, KvError> {
+        self.write(|txn| {
+            let (at, old) = match get(txn, table, key)? {
+                Some((at, v)) => (at, Some(v)),
+                None => (NO_EXPIRY, None),
+            };
+            let (value, result) = f(old.as_ref())?;
+            match value {
+                Some(value) => {
+                    let data: Vec<u8> = value.try_into()?;
+                    put(txn, table, key, &data, at)?;
+                }
+                None if old.is_some() => {
+                    write_entry(txn, table, key, None)?;
+                }
+                None => {}
+            }
+            Ok(result)
+        })
+    }
+
+    fn compare_and_swap(
+        &self,
+        table: &str,
+        key: &str,
+        expected: Option<Value>,
+        value: Value,
+    ) -> Result<bool, KvError> {
+        let data: Vec<u8> = value.try_into()?;
+        self.write(|txn| {
+            let current = get(txn, table, key)?;
+            if current.as_ref().map(|(_, v)| v) != expected.as_ref() {
+                return Ok(false);
+            }
+            let at = current.map_or(NO_EXPIRY, |(at, _)| at);
+            put(txn, table, key, &data, at)?;
+            Ok(true)
+        })
+    }
+
+    fn commit(&self, batch: WriteBatch) -> Result<bool, KvError> {
+        let txn = self.db.begin_write()?;
+        for (table, key, origin) in &batch.reads {
+            let current = get(&txn, table, key)?.map(|(_, v)| v);
+            if current.as_ref() != origin.as_ref() {
+                // 丢弃写事务
+                return Ok(false);
+            }
+        }
+        for op in batch.writes {
+            match op {
+                WriteOp::Set {
+                    table,
+                    key,
+                    value,
+                    ttl,
+                } => {
+                    let data: Vec<u8> = value.try_into()?;
+                    let at = ttl.map_or(NO_EXPIRY, |ttl| now_ms() + ttl.as_millis() as u64);
+                    put(&txn, &table, &key, &data, at)?;
+                }
+                WriteOp::Del { table, key } => {
+                    write_entry(&txn, &table, &key, None)?;
+                }
+            }
+        }
+        txn.commit()?;
+        Ok(true)
+    }
+
+    fn on_expired(&self, hook: ExpiredHook) {
+        self.expired.set(hook);
+    }
+
+    fn list_tables(&self) -> Result<Vec<String>, KvError> {
+        let txn = self.db.begin_read()?;
+        let mut tables = Vec::new();
+        for handle in txn.list_tables()? {
+            if let Some(table) = handle.name().strip_prefix(TABLE_PREFIX) {
+                if !txn.open_table(data_table(handle.name()))?.is_empty()? {
+                    tables.push(table.to_owned());
+                }
+            }
+        }
+        tables.sort();
+        Ok(tables)
+    }
+
+    fn drop_table(&self, table: &str) -> Result<bool, KvError> {
+        // 过期索引中留下的数据会在清理时被忽略
+        let name = table_name(table);
+        self.write(|txn| {
+            let existed = match txn.open_table(data_table(&name)) {
+                Ok(table) => !table.is_empty()?,
+                Err(TableError::TableDoesNotExist(_)) => return Ok(false),
+                Err(e) => return Err(e.into()),
+            };
+            txn.delete_table(data_table(&name))?;
+            Ok(existed)
+        })
+    }
+
+    fn table_len(&self, table: &str) -> Result<usize, KvError> {
+        let txn = self.db.begin_read()?;
+        let name = table_name(table);
+        let table = match txn.open_table(data_table(&name)) {
+            Ok(table) => table,
+            Err(TableError::TableDoesNotExist(_)) => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+        let now = now_ms();
+        let mut len = 0;
+        for item in table.iter()? {
+            let (_, v) = item?;
+            if is_live(v.value().0, now) {
+                len += 1;
+            }
+        }
+        Ok(len)
+    }
+
+    fn rename_table(&self, from: &str, to: &str) -> Result<bool, KvError> {
+        let (from_name, to_name) = (table_name(from), table_name(to));
+        self.write(|txn| {
+            let is_empty = |name: &str| match txn.open_table(data_table(name)) {
+                Ok(table) => Ok(table.is_empty()?),
+                Err(TableError::TableDoesNotExist(_)) => Ok(true),
+                Err(e) => Err(KvError::from(e)),
+            };
+            if is_empty(&from_name)? {
+                return Ok(false);
+            }
+            if !is_empty(&to_name)? {
+                return Err(KvError::PreconditionFailed(format!(
+                    "table {} already exists",
+                    to
+                )));
+            }
+            txn.delete_table(data_table(&to_name))?;
+            txn.rename_table(data_table(&from_name), data_table(&to_name))?;
+
+            // 过期索引中记录了 table 名字，需要一起修改
+            let mut index = txn.open_table(EXPIRY_INDEX)?;
+            let mut entries = Vec::new();
+            for item in index.iter()? {
+                let (k, _) = item?;
+                let (at, table, key) = k.value();
+                if table == from {
+                    entries.push((at, key.to_owned()));
+                }
+            }
+            for (at, key) in entries {
+                index.remove((at, from, key.as_str()))?;
+                index.insert((at, to, key.as_str()), ())?;
+            }
+            Ok(true)
+        })
+    }
+
+    fn export(
+        &self,
+        mut f: impl FnMut(&str, Kvpair, Option<Duration>) -> Result<(), KvError>,
+    ) -> Result<(), KvError> {
+        // 读事务看到的是同一时刻的快照
+        let txn = self.db.begin_read()?;
+        let now = now_ms();
+        for handle in txn.list_tables()? {
+            let Some(name) = handle.name().strip_prefix(TABLE_PREFIX) else {
+                continue;
+            };
+            let table = txn.open_table(data_table(handle.name()))?;
+            for item in table.iter()? {
+                let (k, v) = item?;
+                if let Some((at, value)) = decode_live(v.value(), now)? {
+                    let ttl = (at != NO_EXPIRY).then(|| Duration::from_millis(at - now));
+                    f(name, Kvpair::new(k.value(), value), ttl)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn expired_hook_should_be_called_when_key_is_purged() {
+        let dir = tempdir().unwrap();
+        let store = RedbDb::new(dir.path().join("kv.redb"));
+        let expired = Arc::new(Mutex::new(vec![]));
+        let list = expired.clone();
+        store.on_expired(Arc::new(move |table, key, value| {
+            let item = (table.to_owned(), key.to_owned(), value.clone());
+            list.lock().unwrap().push(item);
+        }));
+        store
+            .set_with_ttl("t1", "k1", "v1", Duration::from_millis(1))
+            .unwrap();
+        store
+            .set_with_ttl("t2", "k2", "v2", Duration::from_millis(1))
+            .unwrap();
+        thread::sleep(Duration::from_millis(5));
+        // 写入时顺带清理过期的 key
+        store.set("t1", "k3", "v3").unwrap();
+        assert_eq!(
+            *expired.lock().unwrap(),
+            [
+                ("t1".to_owned(), "k1".to_owned(), "v1".into()),
+                ("t2".to_owned(), "k2".to_owned(), "v2".into())
+            ]
+        );
+
+        store
+            .set_with_ttl("t1", "k4", "v4", Duration::from_millis(1))
+            .unwrap();
+        thread::sleep(Duration::from_millis(5));
+        store.purge_expired().unwrap();
+        assert_eq!(expired.lock().unwrap().len(), 3);
+        assert_eq!(store.get("t1", "k3").unwrap(), Some("v3".into()));
+    }
+}
@@ -1,22 +1,478 @@
-use sled::{Db, IVec};
-use std::{convert::TryInto, path::Path, str};
+use dashmap::DashMap;
+use prost::Message;
+use sled::{
+    transaction::{
+        ConflictableTransactionError, ConflictableTransactionResult, TransactionError,
+        TransactionalTree,
+    },
+    Db, IVec, Transactional, Tree,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryInto,
+    ops::{Bound, Deref},
+    path::Path,
+    str,
+    sync::{mpsc, Arc, RwLock},
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::warn;
 
-use crate::{Kvpair, Storage, StorageIter, Value};
+use super::{
+    add_float, add_integer,
+    collection::{cmp_member, normalize_range},
+    is_empty_range, ExpiredHook, ExpiryListener,
+};
+use crate::{
+    value, KvError, Kvpair, ScoredMember, Storage, StorageIter, Value, ValueZset, WriteBatch,
+    WriteOp,
+};
+
+/// 记录 table 名字到 table id 的映射：table -> id
+const TABLES_TREE: &str = "__tables";
+/// 每个 table 的数据存放在单独的 Tree 中，Tree 的名字是这个前缀加上 table id
+const TABLE_TREE_PREFIX: &str = "__table_";
+/// 记录 key 的过期时间：table id + key -> 过期时间戳（毫秒）
+const EXPIRES_TREE: &str = "__expires";
+/// 按过期时间排序的索引：过期时间戳 + table id + key -> ()
+const EXPIRY_INDEX_TREE: &str = "__expiry_index";
+/// 有序集合按 score 排序的索引：table id + key 的长度 + key + 编码后的 score + member -> ()
+const ZSET_INDEX_TREE: &str = "__zset_index";
+/// 记录 key 的类型：table id + key -> 类型标记。目前只有有序集合需要记录，其他类型没有记录
+const TYPES_TREE: &str = "__types";
+/// 有序集合的类型标记
+const ZSET_TYPE: u8 = 1;
+/// 每次清理的过期 key 数量上限
+const PURGE_BATCH_SIZE: usize = 128;
+/// 后台清理过期 key 的间隔
+const EXPIRE_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+/// 记录数据库的元信息，目前只有存储格式的版本
+const META_TREE: &str = "__meta";
+const FORMAT_VERSION_KEY: &str = "format_version";
+/// 存储格式的版本：1 是所有数据用 "table:key" 存在默认 Tree 中，2 是每个 table 一个 Tree，
+/// 3 在 __types 中记录有序集合的类型
+const FORMAT_VERSION: u64 = 3;
+
+type TxResult<T> = ConflictableTransactionResult<T, KvError>;
+
+#[derive(Debug)]
+pub struct SledDb {
+    inner: Arc<SledInner>,
+    /// 后台清理线程，drop 时通知它退出并等待，保证 SledDb 被释放后数据库马上关闭
+    sweeper: Option<(mpsc::Sender<()>, JoinHandle<()>)>,
+}
 
 #[derive(Debug)]
-pub struct SledDb(Db);
+pub struct SledInner {
+    db: Db,
+    tables: Tree,
+    /// tables 的反向映射：table id -> table，通知过期时用来查找 table 的名字
+    names: DashMap<u64, String>,
+    expires: Tree,
+    expiry_index: Tree,
+    zset_index: Tree,
+    types: Tree,
+    /// key 过期被清理时通知
+    expired: ExpiryListener,
+    /// sled 不支持快照：写操作持有读锁，export 持有写锁，导出期间不会有写入
+    barrier: RwLock<()>,
+}
 
 impl SledDb {
     pub fn new(path: impl AsRef<Path>) -> Self {
-        Self(sled::open(path).unwrap())
+        Self::with_db(sled::open(path).unwrap())
     }
 
-    fn get_full_key(table: &str, key: &str) -> String {
-        format!("{}:{}", table, key)
+    fn with_db(db: Db) -> Self {
+        let tables = db.open_tree(TABLES_TREE).unwrap();
+        let expires = db.open_tree(EXPIRES_TREE).unwrap();
+        let expiry_index = db.open_tree(EXPIRY_INDEX_TREE).unwrap();
+        let zset_index = db.open_tree(ZSET_INDEX_TREE).unwrap();
+        let types = db.open_tree(TYPES_TREE).unwrap();
+        let inner = SledInner {
+            db,
+            tables,
+            names: DashMap::new(),
+            expires,
+            expiry_index,
+            zset_index,
+            types,
+            expired: ExpiryListener::default(),
+            barrier: RwLock::new(()),
+        };
+        inner.migrate().unwrap();
+        inner.load_names().unwrap();
+        let inner = Arc::new(inner);
+        let sweeper = Some(start_sweeper(&inner));
+        Self { inner, sweeper }
     }
+}
 
-    fn get_table_prefix(table: &str) -> String {
-        format!("{}:", table)
+impl Drop for SledDb {
+    fn drop(&mut self) {
+        if let Some((stop, handle)) = self.sweeper.take() {
+            drop(stop);
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Deref for SledDb {
+    type Target = SledInner;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+/// 启动后台线程定期清理过期的 key，返回的 Sender 被释放或者 SledDb 被释放后线程退出
+fn start_sweeper(inner: &Arc<SledInner>) -> (mpsc::Sender<()>, JoinHandle<()>) {
+    let (stop, rx) = mpsc::channel::<()>();
+    let inner = Arc::downgrade(inner);
+    let handle = thread::spawn(move || loop {
+        if rx.recv_timeout(EXPIRE_SWEEP_INTERVAL) != Err(mpsc::RecvTimeoutError::Timeout) {
+            break;
+        }
+        let Some(inner) = inner.upgrade() else {
+            break;
+        };
+        loop {
+            match inner.purge_batch() {
+                Ok(PURGE_BATCH_SIZE) => continue,
+                Ok(_) => break,
+                Err(e) => {
+                    warn!("Failed to purge expired keys: {:?}", e);
+                    break;
+                }
+            }
+        }
+    });
+    (stop, handle)
+}
+
+impl SledInner {
+    /// 把旧格式的数据库升级到当前的格式，返回从默认 Tree 中迁移的 key 数量。
+    /// 已经是当前格式的数据库不会做任何事
+    pub fn migrate(&self) -> Result<usize, KvError> {
+        let meta = self.db.open_tree(META_TREE)?;
+        let count = match meta.get(FORMAT_VERSION_KEY)?.map(|v| decode_u64(&v)) {
+            Some(FORMAT_VERSION) => return Ok(0),
+            Some(_) => {
+                self.mark_zsets()?;
+                0
+            }
+            None => self.migrate_legacy()?,
+        };
+        meta.insert(FORMAT_VERSION_KEY, &FORMAT_VERSION.to_be_bytes())?;
+        self.db.flush()?;
+        Ok(count)
+    }
+
+    /// 把旧格式（默认 Tree 中的 "table:key"）的数据迁移到每个 table 一个 Tree 的格式，
+    /// 每个 key 在一个事务中迁移，中途退出后再次打开数据库会继续迁移剩下的 key
+    fn migrate_legacy(&self) -> Result<usize, KvError> {
+        let legacy: Tree = (*self.db).clone();
+        let now = now_ms();
+        let mut count = 0;
+        for item in legacy.iter() {
+            let (name, data) = item?;
+            let legacy_key = ivec_to_key(&name);
+            let (table, key) = legacy_key.split_once(':').unwrap_or(("", legacy_key));
+            let (id, tree) = self.open_or_create_table(table)?;
+            let new_name = expiry_name(id, key);
+            let data = Data::try_from(Value::try_from(data.as_ref())?)?;
+            let trees = [
+                tree,
+                legacy.clone(),
+                self.expires.clone(),
+                self.expiry_index.clone(),
+                self.zset_index.clone(),
+                self.types.clone(),
+            ];
+            // 旧格式的过期信息用 "table:key" 标识，和数据一起换成新格式
+            let migrated = trees.as_slice().transaction(|trees| -> TxResult<_> {
+                let (tree, legacy, expires, index, zindex, types) = (
+                    &trees[0], &trees[1], &trees[2], &trees[3], &trees[4], &trees[5],
+                );
+                legacy.remove(&name)?;
+                let at = expires.remove(&name)?.map(|v| decode_u64(&v));
+                if let Some(at) = at {
+                    index.remove(index_key(at, &name))?;
+                }
+                if matches!(at, Some(at) if at <= now) {
+                    return Ok(false);
+                }
+                let old = tree.insert(key.as_bytes(), data.bytes.as_slice())?;
+                reindex(zindex, types, &new_name, old.as_deref(), Some(&data))?;
+                if let Some(at) = at {
+                    expires.insert(new_name.as_slice(), &at.to_be_bytes())?;
+                    index.insert(index_key(at, &new_name), &[])?;
+                }
+                Ok(true)
+            })?;
+            count += migrated as usize;
+        }
+        self.clear_legacy_expiries()?;
+        Ok(count)
+    }
+
+    /// 版本 2 的有序集合没有类型标记，根据 zset_index 中的 member 补上
+    fn mark_zsets(&self) -> Result<(), KvError> {
+        for key in self.zset_index.iter().keys() {
+            self.types.insert(zset_name(&key?), &[ZSET_TYPE])?;
+        }
+        Ok(())
+    }
+
+    /// 删除旧格式残留的过期信息，它们对应的 key 已经不存在了。
+    /// 新格式的名字以 table id 开头，旧格式的名字是 "table:key"
+    fn clear_legacy_expiries(&self) -> Result<(), KvError> {
+        let ids = self
+            .tables
+            .iter()
+            .values()
+            .map(|id| id.map(|id| id.to_vec()))
+            .collect::<Result<HashSet<_>, _>>()?;
+        let is_legacy = |name: &[u8]| name.len() < 8 || !ids.contains(&name[..8]);
+        for item in self.expires.iter() {
+            let (name, at) = item?;
+            if is_legacy(&name) {
+                self.expiry_index
+                    .remove(index_key(decode_u64(&at), &name))?;
+                self.expires.remove(name)?;
+            }
+        }
+        for key in self.expiry_index.iter().keys() {
+            let key = key?;
+            if is_legacy(&key[8..]) {
+                self.expiry_index.remove(key)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 读取 table id 到 table 的反向映射，并删除 drop_table 中途退出时留下的、没有映射的 Tree
+    fn load_names(&self) -> Result<(), KvError> {
+        for item in self.tables.iter() {
+            let (table, id) = item?;
+            self.names
+                .insert(decode_u64(&id), ivec_to_key(&table).to_owned());
+        }
+        for name in self.db.tree_names() {
+            if let Some(id) = name.strip_prefix(TABLE_TREE_PREFIX.as_bytes()) {
+                if !self.names.contains_key(&decode_u64(id)) {
+                    self.db.drop_tree(name)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 按过期时间顺序清理已经过期的 key，最多清理 PURGE_BATCH_SIZE 个
+    pub fn purge_expired(&self) -> Result<(), KvError> {
+        self.purge_batch().map(|_| ())
+    }
+
+    /// 清理一批过期的 key，返回这一批的大小，等于 PURGE_BATCH_SIZE 时可能还有过期的 key
+    fn purge_batch(&self) -> Result<usize, KvError> {
+        let now = now_ms();
+        let end = index_key(now + 1, &[]);
+        let expired: Vec<_> = self
+            .expiry_index
+            .range(..end)
+            .keys()
+            .take(PURGE_BATCH_SIZE)
+            .collect::<Result<_, _>>()?;
+        for key in &expired {
+            let name = &key[8..];
+            let id = decode_u64(&name[..8]);
+            // table 已经被删除，只清理残留的过期信息，不再打开它的 Tree
+            if !self.names.contains_key(&id) {
+                self.expires.remove(name)?;
+                self.expiry_index.remove(key)?;
+                continue;
+            }
+            let tree = self.db.open_tree(table_tree_name(id))?;
+            self.purge_key(&tree, name, now)?;
+        }
+        Ok(expired.len())
+    }
+
+    /// 打开 table 对应的 Tree，table 不存在时返回 None
+    fn open_table(&self, table: &str) -> Result<Option<(u64, Tree)>, KvError> {
+        match self.tables.get(table)? {
+            Some(id) => {
+                let id = decode_u64(&id);
+                Ok(Some((id, self.db.open_tree(table_tree_name(id))?)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn open_or_create_table(&self, table: &str) -> Result<(u64, Tree), KvError> {
+        if let Some(v) = self.open_table(table)? {
+            return Ok(v);
+        }
+        let id = self.db.generate_id()?;
+        let id = match self.tables.compare_and_swap(
+            table,
+            None as Option<&[u8]>,
+            Some(&id.to_be_bytes()),
+        )? {
+            Ok(()) => id,
+            // 其他人已经创建了这个 table
+            Err(e) => e.current.map_or(id, |v| decode_u64(&v)),
+        };
+        self.names.insert(id, table.to_owned());
+        Ok((id, self.db.open_tree(table_tree_name(id))?))
+    }
+
+    fn trees<'a>(&'a self, tree: &'a Tree) -> (&'a Tree, &'a Tree, &'a Tree, &'a Tree, &'a Tree) {
+        (
+            tree,
+            &self.expires,
+            &self.expiry_index,
+            &self.zset_index,
+            &self.types,
+        )
+    }
+
+    /// 如果 key 已经过期，删除 key 及其过期信息，返回是否删除
+    fn purge_key(&self, tree: &Tree, name: &[u8], now: u64) -> Result<bool, KvError> {
+        // 大部分 key 没有过期，先在事务外检查，只有过期时才开启事务
+        if !is_expired(&self.expires, name, now)? {
+            return Ok(false);
+        }
+        let barrier = self.barrier.read().unwrap();
+        let result = self.trees(tree).transaction(
+            |(tree, expires, index, zindex, types)| -> TxResult<_> {
+                match expires.get(name)?.map(|v| decode_u64(&v)) {
+                    Some(at) if at <= now => {
+                        let old = tree.remove(&name[8..])?;
+                        reindex(zindex, types, name, old.as_deref(), None)?;
+                        expires.remove(name)?;
+                        index.remove(index_key(at, name))?;
+                        Ok(Some(old))
+                    }
+                    _ => Ok(None),
+                }
+            },
+        )?;
+        drop(barrier);
+        match result {
+            Some(old) => {
+                if let Some(old) = old.filter(|_| self.expired.is_set()) {
+                    self.notify_expired(name, &old)?;
+                }
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// 通知 key 过期被清理了，table 已经被删除时不通知
+    fn notify_expired(&self, name: &[u8], old: &[u8]) -> Result<(), KvError> {
+        if let Some(table) = self.names.get(&decode_u64(&name[..8])) {
+            let value: Value = old.try_into()?;
+            self.expired.notify(&table, ivec_to_key(&name[8..]), &value);
+        }
+        Ok(())
+    }
+
+    /// 写入 value 并替换过期时间，返回没有过期的旧 value
+    fn insert(
+        &self,
+        table: &str,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+        expire_at: Option<u64>,
+    ) -> Result<Option<Value>, KvError> {
+        let (id, tree) = self.open_or_create_table(table)?;
+        let key = key.into();
+        let name = expiry_name(id, &key);
+        let data = Data::try_from(value.into())?;
+        let now = now_ms();
+        let _barrier = self.barrier.read().unwrap();
+        let result = self.trees(&tree).transaction(
+            |(tree, expires, index, zindex, types)| -> TxResult<_> {
+                let old = tree.insert(key.as_bytes(), data.bytes.as_slice())?;
+                reindex(zindex, types, &name, old.as_deref(), Some(&data))?;
+                let old_at = match expire_at {
+                    Some(at) => expires.insert(name.as_slice(), &at.to_be_bytes())?,
+                    None => expires.remove(name.as_slice())?,
+                };
+                let old_at = old_at.map(|v| decode_u64(&v));
+                if let Some(at) = old_at {
+                    index.remove(index_key(at, &name))?;
+                }
+                if let Some(at) = expire_at {
+                    index.insert(index_key(at, &name), &[])?;
+                }
+                match old_at {
+                    Some(at) if at <= now => Ok(None),
+                    _ => Ok(old),
+                }
+            },
+        )?;
+        flip(result.map(|v| v.as_ref().try_into()))
+    }
+
+    /// 当 key 的数据仍然是 old 时写入 new，new 为 None 时删除 key 及其过期信息，返回是否写入
+    fn replace_if(
+        &self,
+        tree: &Tree,
+        key: &str,
+        name: &[u8],
+        old: Option<&[u8]>,
+        new: Option<&Data>,
+    ) -> Result<bool, KvError> {
+        let _barrier = self.barrier.read().unwrap();
+        let result = self.trees(tree).transaction(
+            |(tree, expires, index, zindex, types)| -> TxResult<_> {
+                if tree.get(key)?.as_deref() != old {
+                    return Ok(false);
+                }
+                match new {
+                    Some(data) => {
+                        tree.insert(key, data.bytes.as_slice())?;
+                    }
+                    None => {
+                        tree.remove(key)?;
+                        if let Some(at) = expires.remove(name)? {
+                            index.remove(index_key(decode_u64(&at), name))?;
+                        }
+                    }
+                }
+                reindex(zindex, types, name, old, new)?;
+                Ok(true)
+            },
+        )?;
+        Ok(result)
+    }
+
+    /// 返回有序集合在 zset_index 中的前缀，key 不存在或者已经过期时返回 None
+    fn open_zset(&self, table: &str, key: &str) -> Result<Option<Vec<u8>>, KvError> {
+        let (id, tree) = match self.open_table(table)? {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        let name = expiry_name(id, key);
+        if self.purge_key(&tree, &name, now_ms())? {
+            return Ok(None);
+        }
+        match tree.get(key)? {
+            Some(_) if self.types.contains_key(&name)? => Ok(Some(zset_prefix(&name))),
+            Some(data) => {
+                let value: Value = data.as_ref().try_into()?;
+                Err(KvError::ConvertError(value.format(), "Zset"))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn is_expired(&self, name: &[u8]) -> Result<bool, KvError> {
+        Ok(is_expired(&self.expires, name, now_ms())?)
     }
 }
 
@@ -24,10 +480,60 @@ fn flip<T, E>(x: Option<Result<T, E>>) -> Result<Option<T>, E> {
     x.map_or(Ok(None), |x| x.map(Some))
 }
 
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+fn decode_u64(v: &[u8]) -> u64 {
+    v.try_into().map(u64::from_be_bytes).unwrap_or(u64::MAX)
+}
+
+fn table_tree_name(id: u64) -> Vec<u8> {
+    let mut name = TABLE_TREE_PREFIX.as_bytes().to_vec();
+    name.extend_from_slice(&id.to_be_bytes());
+    name
+}
+
+/// 过期信息中用 table id + key 来标识一个 key
+fn expiry_name(id: u64, key: &str) -> Vec<u8> {
+    let mut name = Vec::with_capacity(8 + key.len());
+    name.extend_from_slice(&id.to_be_bytes());
+    name.extend_from_slice(key.as_bytes());
+    name
+}
+
+fn index_key(at: u64, name: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(8 + name.len());
+    key.extend_from_slice(&at.to_be_bytes());
+    key.extend_from_slice(name);
+    key
+}
+
+fn is_expired(expires: &Tree, name: &[u8], now: u64) -> Result<bool, sled::Error> {
+    Ok(matches!(expires.get(name)?, Some(v) if decode_u64(&v) <= now))
+}
+
+fn to_bound(bound: Bound<String>) -> Bound<Vec<u8>> {
+    match bound {
+        Bound::Included(key) => Bound::Included(key.into_bytes()),
+        Bound::Excluded(key) => Bound::Excluded(key.into_bytes()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
 impl Storage for SledDb {
     fn get(&self, table: &str, key: &str) -> Result<Option<crate::Value>, crate::KvError> {
-        let name = Self::get_full_key(table, key);
-        let result = self.0.get(name.as_bytes())?.map(|v| v.as_ref().try_into());
+        let (id, tree) = match self.open_table(table)? {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        if self.purge_key(&tree, &expiry_name(id, key), now_ms())? {
+            return Ok(None);
+        }
+        let result = tree.get(key)?.map(|v| v.as_ref().try_into());
         flip(result)
     }
 
@@ -37,38 +543,556 @@ impl Storage for SledDb {
         key: impl Into<String>,
         value: impl Into<Value>,
     ) -> Result<Option<crate::Value>, crate::KvError> {
-        let key = key.into();
-        let name = Self::get_full_key(table, &key);
-        let data: Vec<u8> = value.into().try_into()?;
-        let result = self.0.insert(name, data)?.map(|v| v.as_ref().try_into());
-        flip(result)
+        self.insert(table, key, value, None)
     }
 
     fn contains(&self, table: &str, key: &str) -> Result<bool, crate::KvError> {
-        let name = Self::get_full_key(table, key);
-        Ok(self.0.contains_key(name)?)
+        match self.open_table(table)? {
+            Some((id, tree)) => {
+                Ok(tree.contains_key(key)? && !self.is_expired(&expiry_name(id, key))?)
+            }
+            None => Ok(false),
+        }
     }
 
     fn del(&self, table: &str, key: &str) -> Result<Option<crate::Value>, crate::KvError> {
-        let name = Self::get_full_key(table, key);
-        let result = self.0.remove(name)?.map(|v| v.as_ref().try_into());
-        flip(result)
+        let (id, tree) = match self.open_table(table)? {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        let name = expiry_name(id, key);
+        let now = now_ms();
+        let _barrier = self.barrier.read().unwrap();
+        let result = self.trees(&tree).transaction(
+            |(tree, expires, index, zindex, types)| -> TxResult<_> {
+                let old = tree.remove(key)?;
+                reindex(zindex, types, &name, old.as_deref(), None)?;
+                let old_at = expires.remove(name.as_slice())?.map(|v| decode_u64(&v));
+                if let Some(at) = old_at {
+                    index.remove(index_key(at, &name))?;
+                }
+                match old_at {
+                    Some(at) if at <= now => Ok(None),
+                    _ => Ok(old),
+                }
+            },
+        )?;
+        flip(result.map(|v| v.as_ref().try_into()))
     }
 
     fn get_all(&self, table: &str) -> Result<Vec<crate::Kvpair>, crate::KvError> {
-        let prefix = Self::get_table_prefix(table);
-        let result = self.0.scan_prefix(prefix).map(|v| v.into()).collect();
-        Ok(result)
+        Ok(self.get_iter(table)?.collect())
     }
 
     fn get_iter(
         &self,
         table: &str,
-    ) -> Result<Box<dyn Iterator<Item = crate::Kvpair>>, crate::KvError> {
-        let prefix = Self::get_table_prefix(table);
-        let iter = StorageIter::new(self.0.scan_prefix(prefix));
+    ) -> Result<Box<dyn Iterator<Item = crate::Kvpair> + Send>, crate::KvError> {
+        self.get_range(table, Bound::Unbounded, Bound::Unbounded)
+    }
+
+    fn get_range(
+        &self,
+        table: &str,
+        start: Bound<String>,
+        end: Bound<String>,
+    ) -> Result<Box<dyn Iterator<Item = Kvpair> + Send>, KvError> {
+        if is_empty_range(&start, &end) {
+            return Ok(Box::new(std::iter::empty()));
+        }
+        let (id, tree) = match self.open_table(table)? {
+            Some(v) => v,
+            None => return Ok(Box::new(std::iter::empty())),
+        };
+        let expires = self.expires.clone();
+        let now = now_ms();
+        let data = tree
+            .range((to_bound(start), to_bound(end)))
+            .filter(move |v| match v {
+                Ok((k, _)) => {
+                    let name = expiry_name(id, ivec_to_key(k));
+                    !is_expired(&expires, &name, now).unwrap_or(false)
+                }
+                Err(_) => true,
+            });
+        let iter = StorageIter::new(data);
         Ok(Box::new(iter))
     }
+
+    fn set_with_ttl(
+        &self,
+        table: &str,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+        ttl: Duration,
+    ) -> Result<Option<Value>, KvError> {
+        let expire_at = now_ms() + ttl.as_millis() as u64;
+        self.insert(table, key, value, Some(expire_at))
+    }
+
+    fn expire(&self, table: &str, key: &str, ttl: Duration) -> Result<bool, KvError> {
+        let (id, tree) = match self.open_table(table)? {
+            Some(v) => v,
+            None => return Ok(false),
+        };
+        let name = expiry_name(id, key);
+        let now = now_ms();
+        let expire_at = now + ttl.as_millis() as u64;
+        let _barrier = self.barrier.read().unwrap();
+        let result =
+            self.trees(&tree)
+                .transaction(|(tree, expires, index, _, _)| -> TxResult<_> {
+                    if tree.get(key)?.is_none() {
+                        return Ok(false);
+                    }
+                    if let Some(at) = expires.get(name.as_slice())?.map(|v| decode_u64(&v)) {
+                        if at <= now {
+                            return Ok(false);
+                        }
+                        index.remove(index_key(at, &name))?;
+                    }
+                    expires.insert(name.as_slice(), &expire_at.to_be_bytes())?;
+                    index.insert(index_key(expire_at, &name), &[])?;
+                    Ok(true)
+                })?;
+        Ok(result)
+    }
+
+    fn ttl(&self, table: &str, key: &str) -> Result<Option<Duration>, KvError> {
+        let (id, tree) = match self.open_table(table)? {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        let name = expiry_name(id, key);
+        let now = now_ms();
+        if self.purge_key(&tree, &name, now)? {
+            return Ok(None);
+        }
+        let result = self
+            .expires
+            .get(&name)?
+            .map(|v| Duration::from_millis(decode_u64(&v).saturating_sub(now)));
+        Ok(result)
+    }
+
+    fn persist(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        let (id, tree) = match self.open_table(table)? {
+            Some(v) => v,
+            None => return Ok(false),
+        };
+        let name = expiry_name(id, key);
+        let now = now_ms();
+        let _barrier = self.barrier.read().unwrap();
+        let result = self
+            .trees(&tree)
+            .transaction(|(_, expires, index, _, _)| -> TxResult<_> {
+                match expires.get(name.as_slice())?.map(|v| decode_u64(&v)) {
+                    Some(at) if at > now => {
+                        expires.remove(name.as_slice())?;
+                        index.remove(index_key(at, &name))?;
+                        Ok(true)
+                    }
+                    _ => Ok(false),
+                }
+            })?;
+        Ok(result)
+    }
+
+    fn incr(&self, table: &str, key: &str, delta: i64) -> Result<i64, KvError> {
+        self.modify(table, key, |v| {
+            let i = add_integer(v, delta)?;
+            Ok((Some(i.into()), i))
+        })
+    }
+
+    fn incr_float(&self, table: &str, key: &str, delta: f64) -> Result<f64, KvError> {
+        self.modify(table, key, |v| {
+            let f = add_float(v, delta)?;
+            Ok((Some(f.into()), f))
+        })
+    }
+
+    /// 用 compare_and_swap 循环把 f 计算出的新值写回，过期的 key 视为不存在
+    fn modify<T>(
+        &self,
+        table: &str,
+        key: &str,
+        mut f: impl FnMut(Option<&Value>) -> Result<(Option<Value>, T), KvError>,
+    ) -> Result<T, KvError> {
+        let (id, tree) = self.open_or_create_table(table)?;
+        let name = expiry_name(id, key);
+        self.purge_key(&tree, &name, now_ms())?;
+        loop {
+            let old = tree.get(key)?;
+            let (value, result) = match &old {
+                Some(v) => f(Some(&v.as_ref().try_into()?))?,
+                None => f(None)?,
+            };
+            let data = value.map(Data::try_from).transpose()?;
+            if old.is_none() && data.is_none() {
+                return Ok(result);
+            }
+            if self.replace_if(&tree, key, &name, old.as_deref(), data.as_ref())? {
+                return Ok(result);
+            }
+        }
+    }
+
+    fn compare_and_swap(
+        &self,
+        table: &str,
+        key: &str,
+        expected: Option<Value>,
+        value: Value,
+    ) -> Result<bool, KvError> {
+        let (id, tree) = self.open_or_create_table(table)?;
+        let name = expiry_name(id, key);
+        self.purge_key(&tree, &name, now_ms())?;
+        let expected: Option<Vec<u8>> = expected.map(|v| v.try_into()).transpose()?;
+        let data = Data::try_from(value)?;
+        self.replace_if(&tree, key, &name, expected.as_deref(), Some(&data))
+    }
+
+    fn zrank(
+        &self,
+        table: &str,
+        key: &str,
+        member: &str,
+        rev: bool,
+    ) -> Result<Option<usize>, KvError> {
+        let prefix = match self.open_zset(table, key)? {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        // 先查到 member 的 score，再数出索引中排在它前面（rev 时是后面）的 key
+        let Some(score) = self.zscore(table, key, member)? else {
+            return Ok(None);
+        };
+        let target = zset_index_key(&prefix, &ScoredMember::new(member, score));
+        let keys = match rev {
+            true => self
+                .zset_index
+                .range((Bound::Excluded(target), Bound::Unbounded)),
+            false => self.zset_index.range(prefix.clone()..target),
+        };
+        let mut rank = 0;
+        for k in keys.keys() {
+            if !k?.starts_with(&prefix) {
+                break;
+            }
+            rank += 1;
+        }
+        Ok(Some(rank))
+    }
+
+    fn zrange(
+        &self,
+        table: &str,
+        key: &str,
+        start: i64,
+        stop: i64,
+        rev: bool,
+    ) -> Result<Vec<ScoredMember>, KvError> {
+        let prefix = match self.open_zset(table, key)? {
+            Some(v) => v,
+            None => return Ok(Vec::new()),
+        };
+        // 只有负数下标需要知道有序集合的大小
+        let len = match start < 0 || stop < 0 {
+            true => self.zset_index.scan_prefix(&prefix).count(),
+            false => i64::MAX as usize,
+        };
+        let (start, stop) = match normalize_range(len, start, stop) {
+            Some(v) => v,
+            None => return Ok(Vec::new()),
+        };
+        let keys = self.zset_index.scan_prefix(&prefix).keys();
+        let keys: Box<dyn Iterator<Item = _>> = match rev {
+            true => Box::new(keys.rev()),
+            false => Box::new(keys),
+        };
+        keys.skip(start)
+            .take(stop - start + 1)
+            .map(|k| decode_zset_member(&k?[prefix.len()..]))
+            .collect()
+    }
+
+    fn zrange_by_score(
+        &self,
+        table: &str,
+        key: &str,
+        min: f64,
+        max: f64,
+    ) -> Result<Vec<ScoredMember>, KvError> {
+        let prefix = match self.open_zset(table, key)? {
+            Some(v) => v,
+            None => return Ok(Vec::new()),
+        };
+        // 包括 min 或 max 是 NaN 的情况
+        if min.partial_cmp(&max).is_none_or(|v| v.is_gt()) {
+            return Ok(Vec::new());
+        }
+        let mut start = prefix.clone();
+        start.extend_from_slice(&encode_score(min + 0.0));
+        // member 是 UTF-8 字符串，不会以 0xff 开头
+        let mut end = prefix.clone();
+        end.extend_from_slice(&encode_score(max + 0.0));
+        end.push(0xff);
+        self.zset_index
+            .range(start..end)
+            .keys()
+            .map(|k| decode_zset_member(&k?[prefix.len()..]))
+            .collect()
+    }
+
+    fn commit(&self, batch: WriteBatch) -> Result<bool, KvError> {
+        // 事务涉及的 Tree：前四个是 expires、expiry_index、zset_index 和 types，后面是各个 table
+        let mut trees = vec![
+            self.expires.clone(),
+            self.expiry_index.clone(),
+            self.zset_index.clone(),
+            self.types.clone(),
+        ];
+        let mut table_ids = HashMap::new();
+        let mut locate = |table: &str| -> Result<(usize, u64), KvError> {
+            if let Some(v) = table_ids.get(table) {
+                return Ok(*v);
+            }
+            let (id, tree) = self.open_or_create_table(table)?;
+            trees.push(tree);
+            table_ids.insert(table.to_owned(), (trees.len() - 1, id));
+            Ok((trees.len() - 1, id))
+        };
+
+        let reads = batch
+            .reads
+            .into_iter()
+            .map(|(table, key, origin)| {
+                let origin: Option<Vec<u8>> = origin.map(|v| v.try_into()).transpose()?;
+                let (i, id) = locate(&table)?;
+                let name = expiry_name(id, &key);
+                Ok((i, key, name, origin))
+            })
+            .collect::<Result<Vec<_>, KvError>>()?;
+        let writes = batch
+            .writes
+            .into_iter()
+            .map(|op| match op {
+                WriteOp::Set {
+                    table,
+                    key,
+                    value,
+                    ttl,
+                } => {
+                    let data = Data::try_from(value)?;
+                    let expire_at = ttl.map(|ttl| now_ms() + ttl.as_millis() as u64);
+                    let (i, id) = locate(&table)?;
+                    let name = expiry_name(id, &key);
+                    Ok((i, key, name, Some((data, expire_at))))
+                }
+                WriteOp::Del { table, key } => {
+                    let (i, id) = locate(&table)?;
+                    let name = expiry_name(id, &key);
+                    Ok((i, key, name, None))
+                }
+            })
+            .collect::<Result<Vec<_>, KvError>>()?;
+
+        let now = now_ms();
+        let _barrier = self.barrier.read().unwrap();
+        let result = trees.as_slice().transaction(|trees| -> TxResult<_> {
+            let (expires, index, zindex, types) = (&trees[0], &trees[1], &trees[2], &trees[3]);
+            for (i, key, name, origin) in &reads {
+                let expired =
+                    matches!(expires.get(name.as_slice())?, Some(v) if decode_u64(&v) <= now);
+                let current = trees[*i].get(key.as_bytes())?.filter(|_| !expired);
+                if current.as_deref() != origin.as_deref() {
+                    return Err(ConflictableTransactionError::Abort(KvError::Conflict(
+                        format!("key {} has been changed", key),
+                    )));
+                }
+            }
+            for (i, key, name, write) in &writes {
+                if let Some(at) = expires.remove(name.as_slice())?.map(|v| decode_u64(&v)) {
+                    index.remove(index_key(at, name))?;
+                }
+                match write {
+                    Some((data, expire_at)) => {
+                        let old = trees[*i].insert(key.as_bytes(), data.bytes.as_slice())?;
+                        reindex(zindex, types, name, old.as_deref(), Some(data))?;
+                        if let Some(at) = expire_at {
+                            expires.insert(name.as_slice(), &at.to_be_bytes())?;
+                            index.insert(index_key(*at, name), &[])?;
+                        }
+                    }
+                    None => {
+                        let old = trees[*i].remove(key.as_bytes())?;
+                        reindex(zindex, types, name, old.as_deref(), None)?;
+                    }
+                }
+            }
+            Ok(())
+        });
+        match result {
+            Ok(()) => Ok(true),
+            Err(TransactionError::Abort(KvError::Conflict(_))) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn on_expired(&self, hook: ExpiredHook) {
+        self.expired.set(hook);
+    }
+
+    fn list_tables(&self) -> Result<Vec<String>, KvError> {
+        let mut tables = Vec::new();
+        for item in self.tables.iter() {
+            let (name, id) = item?;
+            if !self
+                .db
+                .open_tree(table_tree_name(decode_u64(&id)))?
+                .is_empty()
+            {
+                tables.push(ivec_to_key(&name).to_owned());
+            }
+        }
+        Ok(tables)
+    }
+
+    fn drop_table(&self, table: &str) -> Result<bool, KvError> {
+        let _barrier = self.barrier.read().unwrap();
+        let id = match self.tables.get(table)? {
+            Some(id) => decode_u64(&id),
+            None => return Ok(false),
+        };
+        let prefix = id.to_be_bytes();
+        let expiries: Vec<_> = self.expires.scan_prefix(prefix).collect::<Result<_, _>>()?;
+        let zset_keys: Vec<_> = self
+            .zset_index
+            .scan_prefix(prefix)
+            .keys()
+            .collect::<Result<_, _>>()?;
+        let type_keys: Vec<_> = self
+            .types
+            .scan_prefix(prefix)
+            .keys()
+            .collect::<Result<_, _>>()?;
+        // 映射和元数据在一个事务中删除，之后的写入会创建新的 table
+        let trees = (
+            &self.tables,
+            &self.expires,
+            &self.expiry_index,
+            &self.zset_index,
+            &self.types,
+        );
+        let dropped =
+            trees.transaction(|(tables, expires, index, zindex, types)| -> TxResult<_> {
+                // 其他人已经删除或者重命名了这个 table
+                if tables.get(table)?.map(|v| decode_u64(&v)) != Some(id) {
+                    return Ok(false);
+                }
+                tables.remove(table)?;
+                for (k, at) in &expiries {
+                    expires.remove(k)?;
+                    index.remove(index_key(decode_u64(at), k))?;
+                }
+                for k in &zset_keys {
+                    zindex.remove(k)?;
+                }
+                for k in &type_keys {
+                    types.remove(k)?;
+                }
+                Ok(true)
+            })?;
+        if !dropped {
+            return Ok(false);
+        }
+        self.names.remove(&id);
+        // 删除 Tree 之前退出时，重新打开数据库会删除没有映射的 Tree
+        let name = table_tree_name(id);
+        let existed = !self.db.open_tree(&name)?.is_empty();
+        self.db.drop_tree(name)?;
+        Ok(existed)
+    }
+
+    fn table_len(&self, table: &str) -> Result<usize, KvError> {
+        let (id, tree) = match self.open_table(table)? {
+            Some(v) => v,
+            None => return Ok(0),
+        };
+        let now = now_ms();
+        let mut len = 0;
+        for key in tree.iter().keys() {
+            let name = expiry_name(id, ivec_to_key(&key?));
+            if !is_expired(&self.expires, &name, now)? {
+                len += 1;
+            }
+        }
+        Ok(len)
+    }
+
+    fn rename_table(&self, from: &str, to: &str) -> Result<bool, KvError> {
+        let exists_error = || KvError::PreconditionFailed(format!("table {} already exists", to));
+        if !matches!(self.open_table(from)?, Some((_, tree)) if !tree.is_empty()) {
+            return Ok(false);
+        }
+        let target = self.open_table(to)?;
+        if matches!(&target, Some((_, tree)) if !tree.is_empty()) {
+            return Err(exists_error());
+        }
+        let target_id = target.map(|(id, _)| id.to_be_bytes());
+        let _barrier = self.barrier.read().unwrap();
+        let renamed = self.tables.transaction(|tables| -> TxResult<_> {
+            // 检查之后目标 table 被其他人创建了
+            if tables.get(to)?.as_deref() != target_id.as_ref().map(|v| v.as_slice()) {
+                return Err(ConflictableTransactionError::Abort(exists_error()));
+            }
+            match tables.remove(from)? {
+                Some(id) => {
+                    tables.insert(to, id.clone())?;
+                    Ok(Some(decode_u64(&id)))
+                }
+                None => Ok(None),
+            }
+        })?;
+        if let Some(id) = renamed {
+            self.names.insert(id, to.to_owned());
+        }
+        let renamed = renamed.is_some();
+        // 目标 table 原来是空的，改名后它的 Tree 就没用了
+        if let (true, Some(id)) = (renamed, target_id) {
+            let id = u64::from_be_bytes(id);
+            self.names.remove(&id);
+            self.db.drop_tree(table_tree_name(id))?;
+        }
+        Ok(renamed)
+    }
+
+    fn export(
+        &self,
+        mut f: impl FnMut(&str, Kvpair, Option<Duration>) -> Result<(), KvError>,
+    ) -> Result<(), KvError> {
+        // 持有写锁，导出的是某一时刻的完整数据
+        let _barrier = self.barrier.write().unwrap();
+        let now = now_ms();
+        for item in self.tables.iter() {
+            let (name, id) = item?;
+            let id = decode_u64(&id);
+            let tree = self.db.open_tree(table_tree_name(id))?;
+            for item in tree.iter() {
+                let (k, v) = item?;
+                let key = ivec_to_key(&k);
+                let ttl = match self.expires.get(expiry_name(id, key))? {
+                    Some(at) if decode_u64(&at) <= now => continue,
+                    Some(at) => Some(Duration::from_millis(decode_u64(&at) - now)),
+                    None => None,
+                };
+                let value = v.as_ref().try_into()?;
+                f(ivec_to_key(&name), Kvpair::new(key, value), ttl)?;
+            }
+        }
+        Ok(())
+    }
 }
 
 impl From<Result<(IVec, IVec), sled::Error>> for Kvpair {
@@ -83,10 +1107,379 @@ impl From<Result<(IVec, IVec), sled::Error>> for Kvpair {
     }
 }
 
+/// 编码后写入 table 的数据，有序集合同时保留解码后的 member，用来更新索引和类型标记
+struct Data {
+    bytes: Vec<u8>,
+    zset: Option<ValueZset>,
+}
+
+impl TryFrom<Value> for Data {
+    type Error = KvError;
+
+    fn try_from(mut value: Value) -> Result<Self, Self::Error> {
+        // 更新索引时按顺序比较新旧 member，保存的有序集合需要排好序
+        if let Some(value::Value::Zset(zset)) = &mut value.value {
+            zset.members.sort_by(cmp_member);
+        }
+        let bytes = value.encode_to_vec();
+        let zset = match value.value {
+            Some(value::Value::Zset(zset)) => Some(zset),
+            _ => None,
+        };
+        Ok(Self { bytes, zset })
+    }
+}
+
+/// 有序集合在 zset_index 中的前缀，记录 key 的长度使一个 key 的前缀不会是另一个 key 的前缀
+fn zset_prefix(name: &[u8]) -> Vec<u8> {
+    let (id, key) = name.split_at(8);
+    let mut prefix = id.to_vec();
+    prefix.extend_from_slice(&(key.len() as u32).to_be_bytes());
+    prefix.extend_from_slice(key);
+    prefix
+}
+
+/// 从 zset_index 的 key 中取出有序集合的 table id + key
+fn zset_name(index_key: &[u8]) -> Vec<u8> {
+    let len = u32::from_be_bytes(index_key[8..12].try_into().unwrap()) as usize;
+    let mut name = index_key[..8].to_vec();
+    name.extend_from_slice(&index_key[12..12 + len]);
+    name
+}
+
+/// 把 score 编码成按字节比较时和数值大小顺序一致的 8 个字节
+fn encode_score(score: f64) -> [u8; 8] {
+    let bits = score.to_bits();
+    let bits = match bits >> 63 {
+        1 => !bits,
+        _ => bits | (1 << 63),
+    };
+    bits.to_be_bytes()
+}
+
+fn decode_score(v: &[u8]) -> f64 {
+    let bits = decode_u64(v);
+    let bits = match bits >> 63 {
+        1 => bits & !(1 << 63),
+        _ => !bits,
+    };
+    f64::from_bits(bits)
+}
+
+/// 解码索引中去掉前缀之后的 score + member
+fn decode_zset_member(v: &[u8]) -> Result<ScoredMember, KvError> {
+    let member = str::from_utf8(&v[8..])
+        .map_err(|_| KvError::Internal("invalid member in zset index".into()))?;
+    Ok(ScoredMember::new(member, decode_score(&v[..8])))
+}
+
+/// 有序集合的 member 在 zset_index 中的 key
+fn zset_index_key(prefix: &[u8], member: &ScoredMember) -> Vec<u8> {
+    let mut k = prefix.to_vec();
+    k.extend_from_slice(&encode_score(member.score));
+    k.extend_from_slice(member.member.as_bytes());
+    k
+}
+
+/// key 的数据从 old 变成 new 时，在事务中同步更新有序集合的索引和类型标记。
+/// 新旧 member 都按 (score, member) 排好序，按顺序比较，只更新变化的 member
+fn reindex(
+    zindex: &TransactionalTree,
+    types: &TransactionalTree,
+    name: &[u8],
+    old: Option<&[u8]>,
+    new: Option<&Data>,
+) -> TxResult<()> {
+    let old = match (old, types.get(name)?) {
+        (Some(data), Some(_)) => {
+            let zset = Value::try_from(data)
+                .and_then(|v| ValueZset::try_from(&v))
+                .map_err(ConflictableTransactionError::Abort)?;
+            zset.members
+        }
+        _ => Vec::new(),
+    };
+    let zset = new.and_then(|data| data.zset.as_ref());
+    let new = zset.map(|zset| zset.members.as_slice()).unwrap_or_default();
+    let prefix = zset_prefix(name);
+    let (mut old, mut new) = (old.iter().peekable(), new.iter().peekable());
+    loop {
+        match (old.peek(), new.peek()) {
+            (None, None) => break,
+            (Some(a), b) if b.is_none_or(|b| cmp_member(a, b).is_lt()) => {
+                zindex.remove(zset_index_key(&prefix, a))?;
+                old.next();
+            }
+            (a, Some(b)) if a.is_none_or(|a| cmp_member(a, b).is_gt()) => {
+                zindex.insert(zset_index_key(&prefix, b), &[])?;
+                new.next();
+            }
+            _ => {
+                old.next();
+                new.next();
+            }
+        }
+    }
+    if zset.is_some() {
+        types.insert(name, &[ZSET_TYPE])?;
+    } else {
+        types.remove(name)?;
+    }
+    Ok(())
+}
+
 fn ivec_to_key(ivec: &[u8]) -> &str {
-    let from_utf8 = str::from_utf8(ivec);
-    let s = from_utf8.unwrap();
-    let mut iter = s.split(":");
-    iter.next();
-    iter.next().unwrap()
+    str::from_utf8(ivec).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn export_should_not_see_partial_commits() {
+        let dir = tempdir().unwrap();
+        let store = Arc::new(SledDb::new(dir.path()));
+        let set = |table: &str, i: i64| WriteOp::Set {
+            table: table.into(),
+            key: "k1".into(),
+            value: i.into(),
+            ttl: None,
+        };
+        let writer = {
+            let store = Arc::clone(&store);
+            thread::spawn(move || {
+                for i in 0..200 {
+                    let batch = WriteBatch {
+                        reads: vec![],
+                        writes: vec![set("t1", i), set("t2", i)],
+                    };
+                    assert!(store.commit(batch).unwrap());
+                }
+            })
+        };
+        while !writer.is_finished() {
+            let mut values = vec![];
+            store
+                .export(|_, pair, _| {
+                    values.push(pair.value);
+                    Ok(())
+                })
+                .unwrap();
+            // 同一个事务写入的两个 table 要么都导出新值，要么都是旧值
+            assert!(values.is_empty() || values == [values[0].clone(), values[0].clone()]);
+        }
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn migrate_should_convert_legacy_layout() {
+        let dir = tempdir().unwrap();
+        let db = sled::open(dir.path()).unwrap();
+        {
+            // 按旧格式写入数据
+            let expires = db.open_tree(EXPIRES_TREE).unwrap();
+            let index = db.open_tree(EXPIRY_INDEX_TREE).unwrap();
+            let value = |v: &str| -> Vec<u8> { Value::from(v).try_into().unwrap() };
+            db.insert("t1:k1", value("v1")).unwrap();
+            db.insert("t1:user:42", value("v2")).unwrap();
+            db.insert("t2:k1", value("v3")).unwrap();
+            db.insert("t2:k2", value("v4")).unwrap();
+            let future = now_ms() + 60_000;
+            expires.insert("t2:k1", &future.to_be_bytes()).unwrap();
+            index.insert(index_key(future, b"t2:k1"), &[]).unwrap();
+            expires.insert("t2:k2", &1u64.to_be_bytes()).unwrap();
+            index.insert(index_key(1, b"t2:k2"), &[]).unwrap();
+        }
+
+        let store = SledDb::with_db(db);
+        assert!(store.db.is_empty());
+        assert_eq!(store.list_tables().unwrap(), ["t1", "t2"]);
+        assert_eq!(store.get("t1", "user:42").unwrap(), Some("v2".into()));
+        assert_eq!(store.get("t2", "k1").unwrap(), Some("v3".into()));
+        assert!(store.ttl("t2", "k1").unwrap().is_some());
+        assert!(!store.contains("t2", "k2").unwrap());
+        // 旧格式的过期信息都换成了新格式
+        assert_eq!(store.expires.len(), 1);
+        assert_eq!(store.expiry_index.len(), 1);
+        assert_eq!(store.migrate().unwrap(), 0);
+    }
+
+    #[test]
+    fn migrate_should_resume_after_interrupted() {
+        let dir = tempdir().unwrap();
+        let db = sled::open(dir.path()).unwrap();
+        {
+            // 模拟迁移到一半退出：部分 key 已经是新格式，但还没有写入格式版本
+            let store = SledDb::with_db(db.clone());
+            let ttl = Duration::from_secs(60);
+            store.set_with_ttl("t1", "k1", "v1", ttl).unwrap();
+            db.open_tree(META_TREE)
+                .unwrap()
+                .remove(FORMAT_VERSION_KEY)
+                .unwrap();
+
+            let value: Vec<u8> = Value::from("v2").try_into().unwrap();
+            db.insert("t1:k2", value).unwrap();
+            let future = now_ms() + 60_000;
+            store
+                .expires
+                .insert("t1:k2", &future.to_be_bytes())
+                .unwrap();
+            let index = index_key(future, b"t1:k2");
+            store.expiry_index.insert(index, &[]).unwrap();
+            // key 已经不存在的旧过期信息
+            store
+                .expires
+                .insert("t3:gone", &future.to_be_bytes())
+                .unwrap();
+            let index = index_key(future, b"t3:gone");
+            store.expiry_index.insert(index, &[]).unwrap();
+        }
+
+        let store = SledDb::with_db(db);
+        assert!(store.db.is_empty());
+        assert_eq!(store.get("t1", "k1").unwrap(), Some("v1".into()));
+        assert_eq!(store.get("t1", "k2").unwrap(), Some("v2".into()));
+        assert!(store.ttl("t1", "k1").unwrap().is_some());
+        assert!(store.ttl("t1", "k2").unwrap().is_some());
+        assert_eq!(store.expires.len(), 2);
+        assert_eq!(store.expiry_index.len(), 2);
+        assert_eq!(store.migrate().unwrap(), 0);
+    }
+
+    #[test]
+    fn migrate_should_mark_zsets_of_version_2() {
+        let dir = tempdir().unwrap();
+        let db = sled::open(dir.path()).unwrap();
+        {
+            // 版本 2 没有类型标记
+            let store = SledDb::with_db(db.clone());
+            let members = vec![ScoredMember::new("a", 1.0), ScoredMember::new("b", 2.0)];
+            store.zadd("t1", "z1", members).unwrap();
+            store.set("t1", "k1", "v1").unwrap();
+            store.types.clear().unwrap();
+            let meta = db.open_tree(META_TREE).unwrap();
+            meta.insert(FORMAT_VERSION_KEY, &2u64.to_be_bytes())
+                .unwrap();
+        }
+
+        let store = SledDb::with_db(db);
+        assert_eq!(store.types.len(), 1);
+        assert_eq!(store.zrank("t1", "z1", "b", false).unwrap(), Some(1));
+        assert!(store.zrank("t1", "k1", "a", false).is_err());
+        // 有序集合变成普通的值后去掉类型标记和索引
+        store.set("t1", "z1", "v2").unwrap();
+        assert!(store.types.is_empty());
+        assert!(store.zset_index.is_empty());
+        assert!(store.zrank("t1", "z1", "a", false).is_err());
+    }
+
+    #[test]
+    fn zset_index_encoding_should_keep_order() {
+        let zset: Value = ValueZset {
+            members: vec![ScoredMember::new("a", 1.0)],
+        }
+        .into();
+        let data = Data::try_from(zset).unwrap();
+        let name = expiry_name(7, "z1");
+        for m in &data.zset.as_ref().unwrap().members {
+            assert_eq!(zset_name(&zset_index_key(&zset_prefix(&name), m)), name);
+        }
+        assert!(Data::try_from(Value::from("a")).unwrap().zset.is_none());
+
+        let scores = [f64::NEG_INFINITY, -2.5, -0.0, 1e-9, 3.0, f64::INFINITY];
+        for w in scores.windows(2) {
+            assert!(encode_score(w[0]) < encode_score(w[1]));
+        }
+        for score in scores {
+            assert_eq!(decode_score(&encode_score(score)), score);
+        }
+    }
+    #[test]
+    fn expired_hook_should_be_called_when_key_is_purged() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir.path());
+        let expired = Arc::new(Mutex::new(vec![]));
+        let list = expired.clone();
+        store.on_expired(Arc::new(move |table, key, value| {
+            let item = (table.to_owned(), key.to_owned(), value.clone());
+            list.lock().unwrap().push(item);
+        }));
+        store
+            .set_with_ttl("t1", "k1", "v1", Duration::from_millis(1))
+            .unwrap();
+        store.set("t1", "k2", "v2").unwrap();
+        thread::sleep(Duration::from_millis(5));
+        assert_eq!(store.get("t1", "k1").unwrap(), None);
+        assert_eq!(store.get("t1", "k2").unwrap(), Some("v2".into()));
+        assert_eq!(
+            *expired.lock().unwrap(),
+            [("t1".to_owned(), "k1".to_owned(), "v1".into())]
+        );
+    }
+
+    #[test]
+    fn sweeper_should_purge_expired_keys_in_background() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir.path());
+        let expired = Arc::new(Mutex::new(vec![]));
+        let list = expired.clone();
+        store.on_expired(Arc::new(move |table, key, _| {
+            list.lock()
+                .unwrap()
+                .push((table.to_owned(), key.to_owned()));
+        }));
+        store
+            .set_with_ttl("t1", "k1", "v1", Duration::from_millis(10))
+            .unwrap();
+        store.rename_table("t1", "t2").unwrap();
+        // 没有任何读写，过期的 key 也会被后台线程清理
+        thread::sleep(EXPIRE_SWEEP_INTERVAL * 2);
+        assert_eq!(
+            *expired.lock().unwrap(),
+            [("t2".to_owned(), "k1".to_owned())]
+        );
+        assert!(store.expires.is_empty());
+        assert!(store.expiry_index.is_empty());
+    }
+
+    #[test]
+    fn dropped_table_leftovers_should_be_cleaned() {
+        let dir = tempdir().unwrap();
+        let db = sled::open(dir.path()).unwrap();
+        {
+            // 模拟 drop_table 删除映射之后、删除 Tree 之前退出，同时留下了过期信息
+            let store = SledDb::with_db(db.clone());
+            let ttl = Duration::from_millis(1);
+            store.set_with_ttl("t1", "k1", "v1", ttl).unwrap();
+            let (id, _) = store.open_table("t1").unwrap().unwrap();
+            store.tables.remove("t1").unwrap();
+            store.names.remove(&id);
+            thread::sleep(Duration::from_millis(5));
+            store.purge_expired().unwrap();
+            assert!(store.expires.is_empty());
+            assert!(store.expiry_index.is_empty());
+            assert!(db.tree_names().contains(&table_tree_name(id).into()));
+        }
+
+        let store = SledDb::with_db(db.clone());
+        let prefix = TABLE_TREE_PREFIX.as_bytes();
+        assert!(!db.tree_names().iter().any(|name| name.starts_with(prefix)));
+        assert_eq!(store.get("t1", "k1").unwrap(), None);
+
+        store
+            .zadd("t2", "z1", vec![ScoredMember::new("a", 1.0)])
+            .unwrap();
+        assert!(store.drop_table("t2").unwrap());
+        assert!(store.zset_index.is_empty());
+        assert!(store.types.is_empty());
+    }
 }
@@ -0,0 +1,336 @@
+use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap},
+    ops::{Bound, RangeBounds},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use super::{add_float, add_integer};
+use crate::{KvError, Kvpair, Storage, StorageIter, Value};
+
+/// 事务提交时需要原子写入的数据
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct WriteBatch {
+    /// 事务中读到的值，提交时要校验它们没有被其他人修改
+    pub reads: Vec<(String, String, Option<Value>)>,
+    pub writes: Vec<WriteOp>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WriteOp {
+    Set {
+        table: String,
+        key: String,
+        value: Value,
+        ttl: Option<Duration>,
+    },
+    Del {
+        table: String,
+        key: String,
+    },
+}
+
+/// 事务中某个 key 的状态
+#[derive(Debug, Clone)]
+struct Staged {
+    origin: Option<Value>,
+    value: Option<Value>,
+    /// 过期的时间点，记录时间点而不是剩余时间，提交时才不会推迟过期
+    expire_at: Option<Instant>,
+    dirty: bool,
+}
+
+impl Staged {
+    fn ttl(&self) -> Option<Duration> {
+        self.expire_at
+            .map(|at| at.saturating_duration_since(Instant::now()))
+    }
+}
+
+/// 事务的写缓冲：读操作穿透到底层 Storage，写操作先记录下来，
+/// 所有命令执行成功后再通过 Storage::commit 一次性原子写入
+pub struct TxnStore<'a, S> {
+    store: &'a S,
+    staged: Mutex<HashMap<(String, String), Staged>>,
+}
+
+impl<'a, S: Storage> TxnStore<'a, S> {
+    pub fn new(store: &'a S) -> Self {
+        Self {
+            store,
+            staged: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 把事务中的读写提交到底层 Storage，读到的值被修改过时返回 false
+    pub fn commit(self) -> Result<bool, KvError> {
+        let staged = self.staged.into_inner().unwrap();
+        let mut batch = WriteBatch::default();
+        for ((table, key), v) in staged {
+            if v.dirty {
+                let ttl = v.ttl();
+                batch.writes.push(match v.value {
+                    Some(value) => WriteOp::Set {
+                        table: table.clone(),
+                        key: key.clone(),
+                        value,
+                        ttl,
+                    },
+                    None => WriteOp::Del {
+                        table: table.clone(),
+                        key: key.clone(),
+                    },
+                });
+            }
+            batch.reads.push((table, key, v.origin));
+        }
+        // 只读事务不需要提交
+        if batch.writes.is_empty() {
+            return Ok(true);
+        }
+        self.store.commit(batch)
+    }
+
+    /// 读取或修改事务中 key 的状态，第一次访问时从底层 Storage 加载
+    fn with_staged<T>(
+        &self,
+        table: &str,
+        key: &str,
+        f: impl FnOnce(&mut Staged) -> Result<T, KvError>,
+    ) -> Result<T, KvError> {
+        let mut staged = self.staged.lock().unwrap();
+        let staged = match staged.entry((table.into(), key.into())) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let value = self.store.get(table, key)?;
+                let ttl = self.store.ttl(table, key)?;
+                entry.insert(Staged {
+                    origin: value.clone(),
+                    value,
+                    expire_at: ttl.map(|ttl| Instant::now() + ttl),
+                    dirty: false,
+                })
+            }
+        };
+        f(staged)
+    }
+
+    fn write(
+        &self,
+        table: &str,
+        key: &str,
+        value: Option<Value>,
+        ttl: Option<Duration>,
+    ) -> Result<Option<Value>, KvError> {
+        self.with_staged(table, key, |v| {
+            v.dirty = true;
+            v.expire_at = ttl.map(|ttl| Instant::now() + ttl);
+            Ok(std::mem::replace(&mut v.value, value))
+        })
+    }
+}
+
+impl<S: Storage> Storage for TxnStore<'_, S> {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        self.with_staged(table, key, |v| Ok(v.value.clone()))
+    }
+
+    fn set(
+        &self,
+        table: &str,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+    ) -> Result<Option<Value>, KvError> {
+        self.write(table, &key.into(), Some(value.into()), None)
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        self.with_staged(table, key, |v| Ok(v.value.is_some()))
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        self.write(table, key, None, None)
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        Ok(self.get_iter(table)?.collect())
+    }
+
+    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair> + Send>, KvError> {
+        self.get_range(table, Bound::Unbounded, Bound::Unbounded)
+    }
+
+    fn get_range(
+        &self,
+        table: &str,
+        start: Bound<String>,
+        end: Bound<String>,
+    ) -> Result<Box<dyn Iterator<Item = Kvpair> + Send>, KvError> {
+        let range = (start, end);
+        let mut data: BTreeMap<_, _> = self
+            .store
+            .get_range(table, range.0.clone(), range.1.clone())?
+            .map(|v| (v.key, v.value.unwrap_or_default()))
+            .collect();
+        // 用事务中还没提交的修改覆盖底层 Storage 的数据
+        let staged = self.staged.lock().unwrap();
+        let dirty = staged
+            .iter()
+            .filter(|((t, k), v)| t == table && v.dirty && range.contains(k));
+        for ((_, key), v) in dirty {
+            match &v.value {
+                Some(value) => data.insert(key.clone(), value.clone()),
+                None => data.remove(key),
+            };
+        }
+        let iter = StorageIter::new(data.into_iter());
+        Ok(Box::new(iter))
+    }
+
+    fn set_with_ttl(
+        &self,
+        table: &str,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+        ttl: Duration,
+    ) -> Result<Option<Value>, KvError> {
+        self.write(table, &key.into(), Some(value.into()), Some(ttl))
+    }
+
+    fn expire(&self, table: &str, key: &str, ttl: Duration) -> Result<bool, KvError> {
+        self.with_staged(table, key, |v| match v.value {
+            Some(_) => {
+                v.dirty = true;
+                v.expire_at = Some(Instant::now() + ttl);
+                Ok(true)
+            }
+            None => Ok(false),
+        })
+    }
+
+    fn ttl(&self, table: &str, key: &str) -> Result<Option<Duration>, KvError> {
+        self.with_staged(table, key, |v| Ok(v.value.as_ref().and(v.ttl())))
+    }
+
+    fn persist(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        self.with_staged(table, key, |v| match (&v.value, v.expire_at) {
+            (Some(_), Some(_)) => {
+                v.dirty = true;
+                v.expire_at = None;
+                Ok(true)
+            }
+            _ => Ok(false),
+        })
+    }
+
+    fn incr(&self, table: &str, key: &str, delta: i64) -> Result<i64, KvError> {
+        self.with_staged(table, key, |v| {
+            let i = add_integer(v.value.as_ref(), delta)?;
+            v.value = Some(i.into());
+            v.dirty = true;
+            Ok(i)
+        })
+    }
+
+    fn incr_float(&self, table: &str, key: &str, delta: f64) -> Result<f64, KvError> {
+        self.with_staged(table, key, |v| {
+            let f = add_float(v.value.as_ref(), delta)?;
+            v.value = Some(f.into());
+            v.dirty = true;
+            Ok(f)
+        })
+    }
+
+    fn modify<T>(
+        &self,
+        table: &str,
+        key: &str,
+        mut f: impl FnMut(Option<&Value>) -> Result<(Option<Value>, T), KvError>,
+    ) -> Result<T, KvError> {
+        self.with_staged(table, key, |v| {
+            let (value, result) = f(v.value.as_ref())?;
+            v.value = value;
+            v.dirty = true;
+            Ok(result)
+        })
+    }
+
+    fn compare_and_swap(
+        &self,
+        table: &str,
+        key: &str,
+        expected: Option<Value>,
+        value: Value,
+    ) -> Result<bool, KvError> {
+        self.with_staged(table, key, |v| match v.value == expected {
+            true => {
+                if v.value.is_none() {
+                    v.expire_at = None;
+                }
+                v.value = Some(value);
+                v.dirty = true;
+                Ok(true)
+            }
+            false => Ok(false),
+        })
+    }
+
+    fn commit(&self, _batch: WriteBatch) -> Result<bool, KvError> {
+        Err(KvError::InvalidCommand(
+            "Nested transaction is not supported".into(),
+        ))
+    }
+
+    fn list_tables(&self) -> Result<Vec<String>, KvError> {
+        Err(table_command_not_supported())
+    }
+
+    fn drop_table(&self, _table: &str) -> Result<bool, KvError> {
+        Err(table_command_not_supported())
+    }
+
+    fn table_len(&self, _table: &str) -> Result<usize, KvError> {
+        Err(table_command_not_supported())
+    }
+
+    fn rename_table(&self, _from: &str, _to: &str) -> Result<bool, KvError> {
+        Err(table_command_not_supported())
+    }
+
+    fn export(
+        &self,
+        _f: impl FnMut(&str, Kvpair, Option<Duration>) -> Result<(), KvError>,
+    ) -> Result<(), KvError> {
+        Err(KvError::InvalidCommand(
+            "Backup is not supported in transaction".into(),
+        ))
+    }
+}
+
+fn table_command_not_supported() -> KvError {
+    KvError::InvalidCommand("Table management is not supported in transaction".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::MemTable;
+
+    #[test]
+    fn commit_should_not_extend_ttl() {
+        let store = MemTable::new();
+        let ttl = Duration::from_millis(100);
+        store.set_with_ttl("t1", "k1", 1, ttl).unwrap();
+        let txn = TxnStore::new(&store);
+        assert_eq!(txn.incr("t1", "k1", 1).unwrap(), 2);
+        thread::sleep(Duration::from_millis(60));
+        assert!(txn.ttl("t1", "k1").unwrap().unwrap() <= Duration::from_millis(40));
+        assert!(txn.commit().unwrap());
+
+        // 过期时间还是事务开始前的时间
+        assert!(store.ttl("t1", "k1").unwrap().unwrap() <= Duration::from_millis(40));
+        assert_eq!(store.get("t1", "k1").unwrap(), Some(2.into()));
+    }
+}
@@ -0,0 +1,327 @@
+use super::with_suffix;
+use crate::{
+    wal_op, DropTable, FsyncPolicy, KvError, RenameTable, ScoredMember, Value, WalDel, WalExpire,
+    WalOp, WalRecord, WalSet, Zadd, Zrem,
+};
+use flate2::Crc;
+use prost::Message;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, Weak},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// 每条记录的头部：4 字节的长度和 4 字节的 crc32
+const HEADER_LEN: usize = 8;
+/// 写快照时每条记录包含的 key 数量
+const SNAPSHOT_BATCH_SIZE: usize = 1024;
+/// FsyncPolicy::EverySec 时后台 fsync 的间隔
+const FSYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// MemTable 的 append-only WAL，每条修改在生效前先写入 WAL，
+/// 快照写入 `<path>.snapshot` 后 WAL 会被清空
+#[derive(Debug)]
+pub(crate) struct Wal {
+    snapshot_path: PathBuf,
+    fsync_policy: FsyncPolicy,
+    inner: Mutex<WalFile>,
+}
+
+#[derive(Debug)]
+struct WalFile {
+    file: File,
+    /// 最后一条写入的记录的 seq
+    seq: u64,
+    /// 已经完整写入的记录的长度
+    len: u64,
+}
+
+impl Wal {
+    /// 打开 WAL，按顺序把快照和 WAL 中的记录交给 apply 重放，
+    /// WAL 末尾没写完整的记录会被截掉
+    pub fn open(
+        path: impl AsRef<Path>,
+        fsync_policy: FsyncPolicy,
+        mut apply: impl FnMut(WalRecord),
+    ) -> Result<Arc<Self>, KvError> {
+        let path = path.as_ref();
+        let snapshot_path = with_suffix(path, ".snapshot");
+
+        let mut snapshot_seq = None;
+        match File::open(&snapshot_path) {
+            Ok(file) => {
+                read_records(&file, |record| {
+                    snapshot_seq.get_or_insert(record.seq);
+                    apply(record);
+                })?;
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        let snapshot_seq = snapshot_seq.unwrap_or_default();
+        let mut seq = snapshot_seq;
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let len = read_records(&file, |record| {
+            // 快照生成后、WAL 清空前崩溃时，WAL 中会留有已经包含在快照中的记录
+            if record.seq > snapshot_seq {
+                seq = record.seq;
+                apply(record);
+            }
+        })?;
+        file.set_len(len)?;
+
+        let wal = Arc::new(Self {
+            snapshot_path,
+            fsync_policy,
+            inner: Mutex::new(WalFile { file, seq, len }),
+        });
+        if fsync_policy == FsyncPolicy::EverySec {
+            start_syncer(Arc::downgrade(&wal));
+        }
+        Ok(wal)
+    }
+
+    /// 把一组修改作为一条记录追加到 WAL
+    pub fn append(&self, ops: Vec<WalOp>) -> Result<(), KvError> {
+        let mut inner = self.inner.lock().unwrap();
+        let record = WalRecord {
+            seq: inner.seq + 1,
+            ops,
+        };
+        let inner = &mut *inner;
+        let result = write_record(&mut inner.file, &record).and_then(|written| {
+            if self.fsync_policy == FsyncPolicy::Always {
+                inner.file.sync_data()?;
+            }
+            Ok(written)
+        });
+        match result {
+            Ok(written) => {
+                inner.seq = record.seq;
+                inner.len += written;
+                Ok(())
+            }
+            Err(e) => {
+                // 截掉写了一半的记录，否则重放时会停在这里，丢掉之后写入成功的记录
+                inner.file.set_len(inner.len)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// 把 WAL 落盘
+    pub fn sync(&self) -> Result<(), KvError> {
+        Ok(self.inner.lock().unwrap().file.sync_data()?)
+    }
+
+    /// 用 ops 生成新的快照，然后清空 WAL。调用者需要保证期间没有新的修改
+    pub fn snapshot(&self, ops: impl Iterator<Item = WalOp>) -> Result<(), KvError> {
+        let mut inner = self.inner.lock().unwrap();
+        let seq = inner.seq;
+        let tmp_path = with_suffix(&self.snapshot_path, ".tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        write_record(&mut writer, &WalRecord { seq, ops: vec![] })?;
+        let mut batch = Vec::with_capacity(SNAPSHOT_BATCH_SIZE);
+        for op in ops {
+            batch.push(op);
+            if batch.len() == SNAPSHOT_BATCH_SIZE {
+                let ops = std::mem::take(&mut batch);
+                write_record(&mut writer, &WalRecord { seq, ops })?;
+            }
+        }
+        if !batch.is_empty() {
+            write_record(&mut writer, &WalRecord { seq, ops: batch })?;
+        }
+        writer
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+        fs::rename(&tmp_path, &self.snapshot_path)?;
+
+        inner.file.set_len(0)?;
+        inner.len = 0;
+        inner.file.sync_all()?;
+        Ok(())
+    }
+}
+
+impl WalOp {
+    pub fn new_set(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        value: Value,
+        expire_at: Option<Instant>,
+    ) -> Self {
+        Self {
+            op: Some(wal_op::Op::Set(WalSet {
+                table: table.into(),
+                key: key.into(),
+                value: Some(value),
+                expire_at: to_unix_ms(expire_at),
+            })),
+        }
+    }
+
+    pub fn new_del(table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            op: Some(wal_op::Op::Del(WalDel {
+                table: table.into(),
+                key: key.into(),
+            })),
+        }
+    }
+
+    pub fn new_expire(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        expire_at: Option<Instant>,
+    ) -> Self {
+        Self {
+            op: Some(wal_op::Op::Expire(WalExpire {
+                table: table.into(),
+                key: key.into(),
+                expire_at: to_unix_ms(expire_at),
+            })),
+        }
+    }
+
+    pub fn new_drop_table(table: impl Into<String>) -> Self {
+        Self {
+            op: Some(wal_op::Op::DropTable(DropTable {
+                table: table.into(),
+            })),
+        }
+    }
+
+    pub fn new_zadd(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        members: Vec<ScoredMember>,
+    ) -> Self {
+        Self {
+            op: Some(wal_op::Op::Zadd(Zadd {
+                table: table.into(),
+                key: key.into(),
+                members,
+            })),
+        }
+    }
+
+    pub fn new_zrem(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        members: Vec<String>,
+    ) -> Self {
+        Self {
+            op: Some(wal_op::Op::Zrem(Zrem {
+                table: table.into(),
+                key: key.into(),
+                members,
+            })),
+        }
+    }
+
+    pub fn new_rename_table(from: impl Into<String>, to: impl Into<String>) -> Self {
+        Self {
+            op: Some(wal_op::Op::RenameTable(RenameTable {
+                from: from.into(),
+                to: to.into(),
+            })),
+        }
+    }
+}
+
+/// 把过期时间转换成 unix 毫秒时间戳，0 表示不过期
+fn to_unix_ms(at: Option<Instant>) -> u64 {
+    match at {
+        Some(at) => (unix_now() + at.saturating_duration_since(Instant::now())).as_millis() as u64,
+        None => 0,
+    }
+}
+
+/// 把 WAL 中的 unix 毫秒时间戳转换回过期时间，已经过去的时间会变成当前时间
+pub(crate) fn from_unix_ms(ms: u64) -> Option<Instant> {
+    match ms {
+        0 => None,
+        ms => Some(Instant::now() + Duration::from_millis(ms).saturating_sub(unix_now())),
+    }
+}
+
+fn unix_now() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// 把记录写入 writer，返回写入的字节数
+fn write_record(writer: &mut impl Write, record: &WalRecord) -> Result<u64, KvError> {
+    let data = record.encode_to_vec();
+    let mut crc = Crc::new();
+    crc.update(&data);
+    let mut buf = Vec::with_capacity(HEADER_LEN + data.len());
+    buf.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    buf.extend_from_slice(&crc.sum().to_be_bytes());
+    buf.extend_from_slice(&data);
+    // 整条记录一次写入，进程崩溃时最多留下最后一条不完整的记录
+    writer.write_all(&buf)?;
+    Ok(buf.len() as u64)
+}
+
+/// 依次读出 file 中完整的记录，遇到不完整或者校验失败的记录时停止，返回有效数据的长度
+fn read_records(file: &File, mut f: impl FnMut(WalRecord)) -> Result<u64, KvError> {
+    let file_len = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+    let mut len = 0;
+    let mut header = [0u8; HEADER_LEN];
+    loop {
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        let size = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+        let checksum = u32::from_be_bytes(header[4..].try_into().unwrap());
+        // 长度字段本身损坏时不要按它分配内存
+        if len + (HEADER_LEN + size) as u64 > file_len {
+            break;
+        }
+        let mut data = vec![0u8; size];
+        match reader.read_exact(&mut data) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        let mut crc = Crc::new();
+        crc.update(&data);
+        if crc.sum() != checksum {
+            break;
+        }
+        match WalRecord::decode(data.as_slice()) {
+            Ok(record) => f(record),
+            Err(_) => break,
+        }
+        len += (HEADER_LEN + size) as u64;
+    }
+    Ok(len)
+}
+
+fn start_syncer(wal: Weak<Wal>) {
+    thread::spawn(move || loop {
+        thread::sleep(FSYNC_INTERVAL);
+        // MemTable 被释放后退出
+        match wal.upgrade() {
+            Some(wal) => {
+                let _ = wal.sync();
+            }
+            None => break,
+        }
+    });
+}
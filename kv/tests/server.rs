@@ -1,7 +1,7 @@
 use anyhow::Result;
 use kv::{
-    start_client_with_config, start_server_with_config, ClientConfig, CommandRequest, ServerConfig,
-    StorageConfig,
+    start_client_with_config, start_server_with_config, ClientConfig, CommandRequest, FsyncPolicy,
+    ServerConfig, StorageConfig,
 };
 use std::time::Duration;
 use tokio::time;
@@ -12,7 +12,12 @@ async fn yamux_server_client_full_tests() -> Result<()> {
 
     let mut config: ServerConfig = toml::from_str(include_str!("../fixtures/server.conf"))?;
     config.general.addr = addr.into();
-    config.storage = StorageConfig::MemTable;
+    config.storage = StorageConfig::MemTable {
+        wal_path: None,
+        fsync_policy: FsyncPolicy::default(),
+        snapshot_interval: None,
+        eviction: None,
+    };
     tokio::spawn(async move {
         start_server_with_config(&config).await.unwrap();
     });